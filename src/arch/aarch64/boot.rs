@@ -0,0 +1,188 @@
+// mod arch::aarch64::boot
+
+//! Early boot path: drop from whatever exception level the firmware,
+//! QEMU or U-Boot left us in down to EL1 and call kmain().
+
+use arch::aarch64::{cache, current_el, mmu, read_cpu_id, smp, traps};
+use core::ptr;
+use cortex_a::{asm, regs::*};
+
+/// SCTLR_EL1 RES1 bits, see ARMv8 ARM D12.2.100.
+const SCTLR_EL1_RES1: u32 = (1 << 29) | (1 << 28) | (1 << 23) | (1 << 22) | (1 << 20) | (1 << 11);
+
+/// SCR_EL3 bits, see ARMv8 ARM D12.2.99.
+mod scr_el3 {
+    pub const NS: u64 = 1 << 0;
+    pub const RES1: u64 = (1 << 5) | (1 << 4);
+    pub const HCE: u64 = 1 << 8;
+    pub const RW: u64 = 1 << 10;
+}
+
+/// SPSR value for entering EL1 using SP_EL1 with all of DAIF masked.
+const SPSR_EL1H_DAIF_MASKED: u64 = 0b1111 << 6 | 0b0101;
+
+/// CPTR_EL2 RES1 bits, TFP (bit 10) left clear to not trap FP/SIMD.
+const CPTR_EL2_RES1: u64 = 0x33ff;
+
+/// Entry point at EL1, called on the EL1 stack with all interrupts masked.
+type El1Entry = unsafe extern "C" fn() -> !;
+
+/// Device tree blob address passed by the firmware, 0 if none.
+static mut DTB_ADDRESS: usize = 0;
+
+/// Physical address of the device tree blob the firmware passed
+/// to the boot core, 0 if there was none.
+pub fn dtb_address() -> usize {
+    unsafe { DTB_ADDRESS }
+}
+
+/// The entry to Rust, all things must be initialized
+/// This is invoked from the linker script, does arch-specific init
+/// and passes control to the kernel boot function kmain().
+///
+/// Firmware passes the device tree blob address in x0.
+#[no_mangle]
+pub unsafe extern "C" fn karch_start(dtb: u64) -> ! {
+    let cpu = read_cpu_id() as usize;
+    let stack = smp::stack_top(cpu);
+
+    SP.set(stack);
+
+    match cpu {
+        0 => {
+            zero_bss();
+            // Lives in .bss, so only after it's cleared.
+            DTB_ADDRESS = dtb as usize;
+            enter_el1(el1_start, stack)
+        }
+        // If not core0, wait to be released by the boot core, same as the
+        // firmware spin table does when it doesn't start us here at all.
+        _ => smp::wait_for_release(cpu),
+    }
+}
+
+/// The entry for secondary cores released via spin table or PSCI.
+#[no_mangle]
+pub unsafe extern "C" fn karch_secondary_start() -> ! {
+    let stack = smp::stack_top(read_cpu_id() as usize);
+
+    SP.set(stack);
+
+    enter_el1(el1_secondary_start, stack)
+}
+
+/// Clear .bss, no static may be touched before this is done.
+#[inline(always)]
+unsafe fn zero_bss() {
+    extern "C" {
+        // Boundaries of the .bss section, 8-byte aligned by the linker script.
+        static mut __bss_start: u64;
+        static mut __bss_end: u64;
+    }
+
+    let mut word = &mut __bss_start as *mut u64;
+    let end = &mut __bss_end as *mut u64;
+    while word < end {
+        ptr::write_volatile(word, 0);
+        word = word.offset(1);
+    }
+}
+
+/// Drop to EL1 from any exception level and continue at `entry`.
+#[inline]
+unsafe fn enter_el1(entry: El1Entry, stack: u64) -> ! {
+    match current_el() {
+        3 => enter_el1_from_el3(entry, stack),
+        2 => enter_el1_from_el2(entry, stack),
+        _ => {
+            setup_el1_controls();
+            entry()
+        }
+    }
+}
+
+/// Configure EL2 controls that affect EL1.
+///
+/// This is also done from EL3, where all EL2 registers are accessible,
+/// so that we can ERET straight into EL1.
+#[inline]
+unsafe fn setup_el2_controls() {
+    // Enable timer counter registers for EL1
+    CNTHCTL_EL2.write(CNTHCTL_EL2::EL1PCEN::SET + CNTHCTL_EL2::EL1PCTEN::SET);
+
+    // No offset for reading the counters
+    CNTVOFF_EL2.set(0);
+
+    // Set EL1 execution state to AArch64
+    HCR_EL2.write(HCR_EL2::RW::EL1IsAarch64);
+
+    // Do not trap FP/SIMD accesses to EL2
+    asm!("msr cptr_el2, $0" :: "r"(CPTR_EL2_RES1) :: "volatile");
+}
+
+/// Put EL1 into a known state: MMU and caches off, little endian,
+/// FP/SIMD accessible until the first thread is scheduled.
+#[inline]
+unsafe fn setup_el1_controls() {
+    SCTLR_EL1.set(SCTLR_EL1_RES1);
+
+    // CPACR_EL1.FPEN = 0b11: no FP/SIMD trapping at EL0 and EL1
+    asm!("msr cpacr_el1, $0" :: "r"(3u64 << 20) :: "volatile");
+}
+
+#[inline]
+unsafe fn enter_el1_from_el3(entry: El1Entry, stack: u64) -> ! {
+    setup_el2_controls();
+    setup_el1_controls();
+
+    // Lower levels are non-secure and AArch64, allow HVC
+    let scr = scr_el3::NS | scr_el3::RES1 | scr_el3::HCE | scr_el3::RW;
+    asm!("msr scr_el3, $0" :: "r"(scr) :: "volatile");
+
+    // Set up a simulated exception return into EL1
+    asm!("msr spsr_el3, $0" :: "r"(SPSR_EL1H_DAIF_MASKED) :: "volatile");
+    asm!("msr elr_el3, $0" :: "r"(entry as *const () as u64) :: "volatile");
+
+    SP_EL1.set(stack);
+
+    asm::eret()
+}
+
+#[inline]
+unsafe fn enter_el1_from_el2(entry: El1Entry, stack: u64) -> ! {
+    setup_el2_controls();
+    setup_el1_controls();
+
+    // Set up a simulated exception return into EL1
+    SPSR_EL2.write(
+        SPSR_EL2::D::Masked
+            + SPSR_EL2::A::Masked
+            + SPSR_EL2::I::Masked
+            + SPSR_EL2::F::Masked
+            + SPSR_EL2::M::EL1h,
+    );
+    ELR_EL2.set(entry as *const () as u64);
+
+    SP_EL1.set(stack);
+
+    asm::eret()
+}
+
+/// First code running at EL1 on the boot core.
+unsafe extern "C" fn el1_start() -> ! {
+    traps::set_vbar_el1();
+    // Nothing was cached so far, drop whatever firmware left behind.
+    cache::invalidate_dcache_all();
+    mmu::init();
+    ::kmain()
+}
+
+/// First code running at EL1 on secondary cores.
+unsafe extern "C" fn el1_secondary_start() -> ! {
+    traps::set_vbar_el1();
+    // Shared levels are in use by the boot core, only touch our own.
+    cache::invalidate_dcache_local();
+    mmu::enable();
+    smp::mark_online(read_cpu_id() as usize);
+    ::kmain_secondary()
+}
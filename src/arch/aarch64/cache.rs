@@ -0,0 +1,212 @@
+// mod arch::aarch64::cache
+
+//! Cache maintenance.
+//!
+//! Range operations work on virtual addresses, one cache line at a time,
+//! using the minimum line sizes reported by CTR_EL0:
+//!
+//! * `clean_*` writes dirty lines back so other observers (the VideoCore,
+//!   DMA masters, cores running with caches off) see our writes;
+//! * `invalidate_*` discards lines so we see memory written by others;
+//! * `clean_invalidate_*` does both.
+//!
+//! Operations "to PoC" reach main memory, "to PoU" only reach the point
+//! where instruction and data caches of this core see the same data,
+//! which is enough when loading code.
+//!
+//! Set/way operations walk the whole data cache hierarchy of the calling
+//! core and are meant for boot and power-down, when nothing else can be
+//! caching the same lines. They are not broadcast to other cores.
+
+use cortex_a::barrier;
+
+/// Cache line sizes in bytes.
+#[derive(Clone, Copy, Debug)]
+pub struct LineSizes {
+    pub dcache: usize,
+    pub icache: usize,
+}
+
+/// Smallest data and instruction cache line sizes in the system.
+#[inline]
+pub fn line_sizes() -> LineSizes {
+    let ctr: u64;
+    unsafe {
+        asm!("mrs $0, ctr_el0" : "=r"(ctr) ::: "volatile");
+    }
+    // Both fields are log2 of the line size in 4-byte words.
+    LineSizes {
+        dcache: 4 << ((ctr >> 16) & 0xf),
+        icache: 4 << (ctr & 0xf),
+    }
+}
+
+macro_rules! range_op {
+    ($start:expr, $len:expr, $line:expr, $insn:tt) => {{
+        let line = $line;
+        let end = $start + $len;
+        let mut addr = $start & !(line - 1);
+        while addr < end {
+            asm!(concat!($insn, ", $0") :: "r"(addr) : "memory" : "volatile");
+            addr += line;
+        }
+    }};
+}
+
+/// Clean data cache lines covering `start..start + len` to PoC.
+pub fn clean_range(start: usize, len: usize) {
+    unsafe {
+        range_op!(start, len, line_sizes().dcache, "dc cvac");
+        barrier::dsb(barrier::SY);
+    }
+}
+
+/// Invalidate data cache lines covering `start..start + len` to PoC.
+///
+/// Dirty data sharing the first or last line with the range is lost,
+/// so buffers handed to other observers should be line-aligned.
+pub fn invalidate_range(start: usize, len: usize) {
+    unsafe {
+        range_op!(start, len, line_sizes().dcache, "dc ivac");
+        barrier::dsb(barrier::SY);
+    }
+}
+
+/// Clean and invalidate data cache lines covering `start..start + len` to PoC.
+pub fn clean_invalidate_range(start: usize, len: usize) {
+    unsafe {
+        range_op!(start, len, line_sizes().dcache, "dc civac");
+        barrier::dsb(barrier::SY);
+    }
+}
+
+/// Clean data cache lines covering `start..start + len` to PoU.
+pub fn clean_range_pou(start: usize, len: usize) {
+    unsafe {
+        range_op!(start, len, line_sizes().dcache, "dc cvau");
+        barrier::dsb(barrier::SY);
+    }
+}
+
+/// Invalidate instruction cache lines covering `start..start + len` to PoU,
+/// in the whole inner shareable domain.
+pub fn invalidate_icache_range(start: usize, len: usize) {
+    unsafe {
+        range_op!(start, len, line_sizes().icache, "ic ivau");
+        barrier::dsb(barrier::SY);
+        barrier::isb(barrier::SY);
+    }
+}
+
+/// Invalidate the whole instruction cache of the calling core.
+pub fn invalidate_icache_all() {
+    unsafe {
+        asm!("ic iallu" ::: "memory" : "volatile");
+        barrier::dsb(barrier::SY);
+        barrier::isb(barrier::SY);
+    }
+}
+
+/// Make code written to `start..start + len` visible to instruction fetch.
+pub fn sync_icache_range(start: usize, len: usize) {
+    clean_range_pou(start, len);
+    invalidate_icache_range(start, len);
+}
+
+#[derive(Clone, Copy)]
+enum SetWayOp {
+    Invalidate,
+    Clean,
+    CleanInvalidate,
+}
+
+/// Apply `op` to every line of data and unified caches at levels below `levels`.
+unsafe fn set_way_all(op: SetWayOp, levels: u64) {
+    let clidr: u64;
+    asm!("mrs $0, clidr_el1" : "=r"(clidr) ::: "volatile");
+
+    barrier::dsb(barrier::SY);
+
+    for level in 0..levels {
+        // 0 - no cache, 1 - instruction only, 2+ - has data
+        if (clidr >> (level * 3)) & 0x7 < 2 {
+            continue;
+        }
+
+        let ccsidr: u64;
+        asm!("msr csselr_el1, $0" :: "r"(level << 1) :: "volatile");
+        barrier::isb(barrier::SY);
+        asm!("mrs $0, ccsidr_el1" : "=r"(ccsidr) ::: "volatile");
+
+        let line_shift = (ccsidr & 0x7) + 4;
+        let ways = ((ccsidr >> 3) & 0x3ff) + 1;
+        let sets = ((ccsidr >> 13) & 0x7fff) + 1;
+        // Way number lives in the top bits of the 32-bit operand.
+        let way_shift = u64::from((ways as u32 - 1).leading_zeros());
+
+        for way in 0..ways {
+            for set in 0..sets {
+                let sw = (way << way_shift) | (set << line_shift) | (level << 1);
+                match op {
+                    SetWayOp::Invalidate => asm!("dc isw, $0" :: "r"(sw) : "memory" : "volatile"),
+                    SetWayOp::Clean => asm!("dc csw, $0" :: "r"(sw) : "memory" : "volatile"),
+                    SetWayOp::CleanInvalidate => {
+                        asm!("dc cisw, $0" :: "r"(sw) : "memory" : "volatile")
+                    }
+                }
+            }
+        }
+    }
+
+    // Back to the L1 data cache.
+    asm!("msr csselr_el1, xzr" :::: "volatile");
+    barrier::dsb(barrier::SY);
+    barrier::isb(barrier::SY);
+}
+
+fn clidr_field(shift: u64) -> u64 {
+    let clidr: u64;
+    unsafe {
+        asm!("mrs $0, clidr_el1" : "=r"(clidr) ::: "volatile");
+    }
+    (clidr >> shift) & 0x7
+}
+
+/// Levels up to the Point of Coherency.
+fn level_of_coherency() -> u64 {
+    clidr_field(24)
+}
+
+/// Levels up to the Point of Unification, Inner Shareable.
+fn level_of_unification_is() -> u64 {
+    clidr_field(21)
+}
+
+/// Invalidate all data caches up to PoC without writing anything back.
+///
+/// Only for the boot core before the D-cache is first enabled:
+/// any dirty data in the caches, including shared levels, is lost.
+pub unsafe fn invalidate_dcache_all() {
+    set_way_all(SetWayOp::Invalidate, level_of_coherency());
+}
+
+/// Invalidate data caches private to the calling core without writing
+/// anything back. For secondary cores before they enable their D-cache.
+pub unsafe fn invalidate_dcache_local() {
+    set_way_all(SetWayOp::Invalidate, level_of_unification_is());
+}
+
+/// Clean all data caches up to PoC.
+pub fn clean_dcache_all() {
+    unsafe {
+        set_way_all(SetWayOp::Clean, level_of_coherency());
+    }
+}
+
+/// Clean and invalidate all data caches up to PoC, e.g. before
+/// turning the D-cache off or powering the core down.
+pub fn clean_invalidate_dcache_all() {
+    unsafe {
+        set_way_all(SetWayOp::CleanInvalidate, level_of_coherency());
+    }
+}
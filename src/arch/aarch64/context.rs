@@ -0,0 +1,106 @@
+// mod arch::aarch64::context
+
+//! User thread state beyond the TrapFrame.
+//!
+//! Exception entry only saves the general purpose registers. The kernel is
+//! built for a softfloat target without FP/SIMD, see the target spec in
+//! targets/, and never touches TPIDR_EL0, so the FP/SIMD registers and
+//! thread pointer of the last user thread stay live across kernel entries
+//! and are switched only when a core changes threads.
+//!
+//! The FP/SIMD registers are 512 bytes and most threads never use them.
+//! Unless built with the `eager_fpu` feature, a newly scheduled thread
+//! runs with FP/SIMD access disabled and its registers are only loaded
+//! when its first FP/SIMD instruction traps, see `sched::handle_fpu_trap`.
+
+use cortex_a::barrier;
+
+global_asm!(include_str!("fpu.S"));
+
+extern "C" {
+    fn __fpu_save(state: *mut FpuState);
+    fn __fpu_restore(state: *const FpuState);
+}
+
+/// FP/SIMD registers V0-V31 and their status and control registers.
+#[derive(Clone, Copy)]
+#[repr(C, align(16))]
+pub struct FpuState {
+    pub vregs: [u128; 32],
+    pub fpsr: u64,
+    pub fpcr: u64,
+}
+
+impl FpuState {
+    /// All registers zero, default rounding and no exceptions trapped.
+    pub const fn new() -> FpuState {
+        FpuState {
+            vregs: [0; 32],
+            fpsr: 0,
+            fpcr: 0,
+        }
+    }
+}
+
+/// Registers of a thread which are switched lazily.
+#[repr(C)]
+pub struct ExtendedContext {
+    pub fpu: FpuState,
+    /// User thread pointer, free for user code to use for TLS.
+    pub tpidr_el0: u64,
+}
+
+impl ExtendedContext {
+    pub const fn new() -> ExtendedContext {
+        ExtendedContext {
+            fpu: FpuState::new(),
+            tpidr_el0: 0,
+        }
+    }
+
+    /// Store the FP/SIMD registers of the thread that was running on this core.
+    pub fn save_fpu(&mut self) {
+        unsafe {
+            __fpu_save(&mut self.fpu);
+        }
+    }
+
+    /// Load the FP/SIMD registers of the thread about to run on this core.
+    pub fn restore_fpu(&self) {
+        unsafe {
+            __fpu_restore(&self.fpu);
+        }
+    }
+
+    pub fn save_tls(&mut self) {
+        unsafe {
+            asm!("mrs $0, tpidr_el0" : "=r"(self.tpidr_el0) ::: "volatile");
+        }
+    }
+
+    pub fn restore_tls(&self) {
+        unsafe {
+            asm!("msr tpidr_el0, $0" :: "r"(self.tpidr_el0) :: "volatile");
+        }
+    }
+}
+
+/// CPACR_EL1.FPEN values.
+const FPEN_TRAP_EL0: u64 = 0b01 << 20;
+const FPEN_NO_TRAP: u64 = 0b11 << 20;
+
+/// Let EL0 use FP/SIMD instructions.
+pub fn enable_fpu_access() {
+    unsafe {
+        asm!("msr cpacr_el1, $0" :: "r"(FPEN_NO_TRAP) :: "volatile");
+        barrier::isb(barrier::SY);
+    }
+}
+
+/// Trap FP/SIMD instructions at EL0 to the kernel.
+pub fn disable_fpu_access() {
+    unsafe {
+        asm!("msr cpacr_el1, $0" :: "r"(FPEN_TRAP_EL0) :: "volatile");
+        barrier::isb(barrier::SY);
+    }
+}
@@ -0,0 +1,220 @@
+// mod arch::aarch64::fault
+
+//! Decoding of ESR_EL1 syndromes and fault reports.
+//!
+//! See ARMv8 ARM D12.2.36 "ESR_EL1, Exception Syndrome Register (EL1)".
+
+use arch::aarch64::traps::{ExceptionOrigin, TrapFrame};
+use core::fmt;
+use cortex_a::regs::*;
+
+/// Decoded exception syndrome.
+#[derive(Clone, Copy)]
+pub struct Syndrome(pub u32);
+
+impl Syndrome {
+    /// Read the syndrome of the exception being handled.
+    pub fn read() -> Syndrome {
+        Syndrome(ESR_EL1.get())
+    }
+
+    /// Exception class, ESR_EL1[31:26].
+    pub fn class(&self) -> u32 {
+        self.0 >> 26
+    }
+
+    /// Instruction length, true for 32-bit instructions.
+    pub fn il(&self) -> bool {
+        self.0 & (1 << 25) != 0
+    }
+
+    /// Instruction specific syndrome, ESR_EL1[24:0].
+    pub fn iss(&self) -> u32 {
+        self.0 & 0x01ff_ffff
+    }
+
+    pub fn class_name(&self) -> &'static str {
+        match self.class() {
+            class::UNKNOWN => "Unknown reason",
+            class::TRAPPED_WFI_WFE => "Trapped WFI/WFE",
+            class::TRAPPED_FP_SIMD => "Trapped FP/SIMD access",
+            class::ILLEGAL_EXECUTION_STATE => "Illegal execution state",
+            class::SVC32 => "SVC from AArch32",
+            class::SVC64 => "SVC from AArch64",
+            class::HVC64 => "HVC from AArch64",
+            class::SMC64 => "SMC from AArch64",
+            class::TRAPPED_MSR_MRS => "Trapped MSR/MRS/system instruction",
+            class::INSTRUCTION_ABORT_LOWER_EL => "Instruction abort from lower EL",
+            class::INSTRUCTION_ABORT_CURRENT_EL => "Instruction abort from current EL",
+            class::PC_ALIGNMENT => "PC alignment fault",
+            class::DATA_ABORT_LOWER_EL => "Data abort from lower EL",
+            class::DATA_ABORT_CURRENT_EL => "Data abort from current EL",
+            class::SP_ALIGNMENT => "SP alignment fault",
+            class::TRAPPED_FP64 => "Trapped floating-point exception",
+            class::SERROR => "SError interrupt",
+            class::BREAKPOINT_LOWER_EL => "Breakpoint from lower EL",
+            class::BREAKPOINT_CURRENT_EL => "Breakpoint from current EL",
+            class::SOFTWARE_STEP_LOWER_EL => "Software step from lower EL",
+            class::SOFTWARE_STEP_CURRENT_EL => "Software step from current EL",
+            class::WATCHPOINT_LOWER_EL => "Watchpoint from lower EL",
+            class::WATCHPOINT_CURRENT_EL => "Watchpoint from current EL",
+            class::BRK64 => "BRK instruction",
+            _ => "Reserved",
+        }
+    }
+
+    pub fn is_abort(&self) -> bool {
+        match self.class() {
+            class::INSTRUCTION_ABORT_LOWER_EL
+            | class::INSTRUCTION_ABORT_CURRENT_EL
+            | class::DATA_ABORT_LOWER_EL
+            | class::DATA_ABORT_CURRENT_EL => true,
+            _ => false,
+        }
+    }
+
+    pub fn is_data_abort(&self) -> bool {
+        match self.class() {
+            class::DATA_ABORT_LOWER_EL | class::DATA_ABORT_CURRENT_EL => true,
+            _ => false,
+        }
+    }
+
+    /// Fault status code for instruction and data aborts, ISS[5:0].
+    pub fn fault_status(&self) -> u32 {
+        self.iss() & 0x3f
+    }
+
+    /// FAR_EL1 is not valid, ISS[10] for aborts.
+    pub fn far_not_valid(&self) -> bool {
+        self.is_abort() && self.iss() & (1 << 10) != 0
+    }
+}
+
+/// Exception class values, ESR_EL1.EC.
+pub mod class {
+    pub const UNKNOWN: u32 = 0b00_0000;
+    pub const TRAPPED_WFI_WFE: u32 = 0b00_0001;
+    pub const TRAPPED_FP_SIMD: u32 = 0b00_0111;
+    pub const ILLEGAL_EXECUTION_STATE: u32 = 0b00_1110;
+    pub const SVC32: u32 = 0b01_0001;
+    pub const SVC64: u32 = 0b01_0101;
+    pub const HVC64: u32 = 0b01_0110;
+    pub const SMC64: u32 = 0b01_0111;
+    pub const TRAPPED_MSR_MRS: u32 = 0b01_1000;
+    pub const INSTRUCTION_ABORT_LOWER_EL: u32 = 0b10_0000;
+    pub const INSTRUCTION_ABORT_CURRENT_EL: u32 = 0b10_0001;
+    pub const PC_ALIGNMENT: u32 = 0b10_0010;
+    pub const DATA_ABORT_LOWER_EL: u32 = 0b10_0100;
+    pub const DATA_ABORT_CURRENT_EL: u32 = 0b10_0101;
+    pub const SP_ALIGNMENT: u32 = 0b10_0110;
+    pub const TRAPPED_FP64: u32 = 0b10_1100;
+    pub const SERROR: u32 = 0b10_1111;
+    pub const BREAKPOINT_LOWER_EL: u32 = 0b11_0000;
+    pub const BREAKPOINT_CURRENT_EL: u32 = 0b11_0001;
+    pub const SOFTWARE_STEP_LOWER_EL: u32 = 0b11_0010;
+    pub const SOFTWARE_STEP_CURRENT_EL: u32 = 0b11_0011;
+    pub const WATCHPOINT_LOWER_EL: u32 = 0b11_0100;
+    pub const WATCHPOINT_CURRENT_EL: u32 = 0b11_0101;
+    pub const BRK64: u32 = 0b11_1100;
+}
+
+fn fault_status_name(code: u32) -> &'static str {
+    match code {
+        0b00_0000..=0b00_0011 => "Address size fault",
+        0b00_0100..=0b00_0111 => "Translation fault",
+        0b00_1001..=0b00_1011 => "Access flag fault",
+        0b00_1101..=0b00_1111 => "Permission fault",
+        0b01_0000 => "Synchronous external abort",
+        0b01_0100..=0b01_0111 => "Synchronous external abort on table walk",
+        0b01_1000 => "Synchronous parity/ECC error",
+        0b01_1100..=0b01_1111 => "Synchronous parity/ECC error on table walk",
+        0b10_0001 => "Alignment fault",
+        0b11_0000 => "TLB conflict abort",
+        _ => "Reserved fault status",
+    }
+}
+
+impl fmt::Display for Syndrome {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(
+            f,
+            "ESR_EL1: {:08x} EC: {:#04x} ({}) IL: {} ISS: {:#07x}",
+            self.0,
+            self.class(),
+            self.class_name(),
+            if self.il() { 32 } else { 16 },
+            self.iss()
+        )?;
+
+        if self.is_abort() {
+            let iss = self.iss();
+            write!(
+                f,
+                "  {}, level {}",
+                fault_status_name(self.fault_status()),
+                iss & 0x3
+            )?;
+            if self.is_data_abort() {
+                write!(
+                    f,
+                    ", {}",
+                    if iss & (1 << 6) != 0 { "write" } else { "read" }
+                )?;
+                if iss & (1 << 24) != 0 {
+                    // ISV: instruction syndrome valid
+                    write!(
+                        f,
+                        ", {} bytes via x{}",
+                        1 << ((iss >> 22) & 0x3),
+                        (iss >> 16) & 0x1f
+                    )?;
+                }
+                if iss & (1 << 8) != 0 {
+                    write!(f, ", cache maintenance")?;
+                }
+            }
+            if iss & (1 << 7) != 0 {
+                write!(f, ", stage 2 fault on stage 1 table walk")?;
+            }
+            if iss & (1 << 9) != 0 {
+                write!(f, ", external abort")?;
+            }
+            writeln!(f)?;
+        }
+        Ok(())
+    }
+}
+
+/// Full fault report: syndrome, fault address and saved registers.
+pub struct FaultReport<'a> {
+    pub origin: ExceptionOrigin,
+    pub syndrome: Syndrome,
+    pub far: u64,
+    pub frame: &'a TrapFrame,
+}
+
+impl<'a> FaultReport<'a> {
+    /// Collect fault information for the exception being handled.
+    pub fn new(origin: ExceptionOrigin, frame: &'a TrapFrame) -> Self {
+        FaultReport {
+            origin,
+            syndrome: Syndrome::read(),
+            far: FAR_EL1.get(),
+            frame,
+        }
+    }
+}
+
+impl<'a> fmt::Display for FaultReport<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "\n[!] Synchronous exception from {:?}", self.origin)?;
+        write!(f, "{}", self.syndrome)?;
+        if self.syndrome.far_not_valid() {
+            writeln!(f, "FAR_EL1: <not valid>")?;
+        } else {
+            writeln!(f, "FAR_EL1: {:016x}", self.far)?;
+        }
+        write!(f, "{}", self.frame)
+    }
+}
@@ -0,0 +1,407 @@
+// mod arch::aarch64::mmu
+
+//! Kernel translation tables and MMU enablement.
+//!
+//! Uses 4KiB granule with 39-bit virtual addresses, so translation starts
+//! at level 1 where each entry covers 1GiB, level 2 entries cover 2MiB
+//! and level 3 entries cover a single 4KiB page.
+//!
+//! The first GiB is identity mapped with 2MiB blocks: RAM as normal
+//! cacheable memory and the peripheral window as device-nGnRE.
+//! The second GiB holding BCM2836 local peripherals is a single
+//! device-nGnRE block.
+//! The first 2MiB containing the kernel image are mapped with 4KiB pages
+//! so that kernel code and read-only data can be write-protected and
+//! boot stack guard pages left unmapped.
+//!
+//! The kernel tables use ASID 0. User protection domains have their own
+//! tables and ASIDs, which share the kernel image level 3 table, see
+//! `objects::vspace`. Only the kernel image is mapped global, so the
+//! kernel's TLB entries for the rest of memory don't apply to them.
+
+use arch::aarch64::smp;
+use cortex_a::{barrier, regs::*};
+use platform::rpi3::BcmHost;
+
+register_bitfields! {
+    u64,
+
+    /// Level 0-2 table descriptor and level 1-3 block/page descriptor.
+    /// See ARMv8 ARM D4.3 "VMSAv8-64 translation table format descriptors".
+    STAGE1_DESCRIPTOR [
+        /// Unprivileged execute-never
+        UXN OFFSET(54) NUMBITS(1) [
+            False = 0,
+            True = 1
+        ],
+
+        /// Privileged execute-never
+        PXN OFFSET(53) NUMBITS(1) [
+            False = 0,
+            True = 1
+        ],
+
+        /// Output address of a 4KiB page or next level table
+        OUTPUT_ADDR_4KIB OFFSET(12) NUMBITS(36) [], // [47:12]
+
+        /// Output address of a 2MiB block
+        OUTPUT_ADDR_2MIB OFFSET(21) NUMBITS(27) [], // [47:21]
+
+        /// Output address of a 1GiB block
+        OUTPUT_ADDR_1GIB OFFSET(30) NUMBITS(18) [], // [47:30]
+
+        /// Not global, the entry only applies to the current ASID
+        NG OFFSET(11) NUMBITS(1) [
+            False = 0,
+            True = 1
+        ],
+
+        /// Access flag
+        AF OFFSET(10) NUMBITS(1) [
+            False = 0,
+            True = 1
+        ],
+
+        /// Shareability field
+        SH OFFSET(8) NUMBITS(2) [
+            OuterShareable = 0b10,
+            InnerShareable = 0b11
+        ],
+
+        /// Access Permissions
+        AP OFFSET(6) NUMBITS(2) [
+            RW_EL1 = 0b00,
+            RW_EL1_EL0 = 0b01,
+            RO_EL1 = 0b10,
+            RO_EL1_EL0 = 0b11
+        ],
+
+        /// Memory attributes index into the MAIR_EL1 register
+        AttrIndx OFFSET(2) NUMBITS(3) [],
+
+        TYPE OFFSET(1) NUMBITS(1) [
+            Block = 0,
+            Table = 1
+        ],
+
+        VALID OFFSET(0) NUMBITS(1) [
+            False = 0,
+            True = 1
+        ]
+    ]
+}
+
+/// Indices into MAIR_EL1 for the memory attributes we use.
+pub mod mair {
+    pub const DEVICE: u64 = 0;
+    pub const NORMAL: u64 = 1;
+    /// Normal non-cacheable, for write-combining user mappings.
+    pub const NORMAL_NC: u64 = 2;
+}
+
+pub const ENTRIES_PER_TABLE: usize = 512;
+pub const PAGE_SHIFT: usize = 12;
+pub const BLOCK_SHIFT: usize = 21;
+pub const HUGE_BLOCK_SHIFT: usize = 30;
+pub const PAGE_SIZE: usize = 1 << PAGE_SHIFT;
+pub const BLOCK_SIZE: usize = 1 << BLOCK_SHIFT;
+pub const HUGE_BLOCK_SIZE: usize = 1 << HUGE_BLOCK_SHIFT;
+
+#[repr(C)]
+#[repr(align(4096))]
+pub struct PageTable {
+    pub entries: [u64; ENTRIES_PER_TABLE],
+}
+
+impl PageTable {
+    const fn new() -> Self {
+        PageTable {
+            entries: [0; ENTRIES_PER_TABLE],
+        }
+    }
+}
+
+// Kernel translation tables, identity mapping the first GiB.
+static mut LVL1_TABLE: PageTable = PageTable::new();
+static mut LVL2_TABLE: PageTable = PageTable::new();
+static mut LVL3_TABLE: PageTable = PageTable::new();
+
+/// Descriptor pointing to the next level table.
+pub fn table_descriptor(table: *const PageTable) -> u64 {
+    (STAGE1_DESCRIPTOR::VALID::True
+        + STAGE1_DESCRIPTOR::TYPE::Table
+        + STAGE1_DESCRIPTOR::OUTPUT_ADDR_4KIB.val(table as u64 >> PAGE_SHIFT))
+        .value
+}
+
+/// Block descriptor for a 2MiB block at the given index.
+fn block_descriptor(index: usize, device: bool) -> u64 {
+    let attrs = if device {
+        STAGE1_DESCRIPTOR::AttrIndx.val(mair::DEVICE)
+            + STAGE1_DESCRIPTOR::SH::OuterShareable
+    } else {
+        STAGE1_DESCRIPTOR::AttrIndx.val(mair::NORMAL)
+            + STAGE1_DESCRIPTOR::SH::InnerShareable
+    };
+
+    (STAGE1_DESCRIPTOR::VALID::True
+        + STAGE1_DESCRIPTOR::TYPE::Block
+        + STAGE1_DESCRIPTOR::AP::RW_EL1
+        + STAGE1_DESCRIPTOR::AF::True
+        + STAGE1_DESCRIPTOR::NG::True
+        + STAGE1_DESCRIPTOR::PXN::True
+        + STAGE1_DESCRIPTOR::UXN::True
+        + STAGE1_DESCRIPTOR::OUTPUT_ADDR_2MIB.val(index as u64)
+        + attrs)
+        .value
+}
+
+/// Device block descriptor for a 1GiB block at the given index.
+fn huge_device_block_descriptor(index: usize) -> u64 {
+    (STAGE1_DESCRIPTOR::VALID::True
+        + STAGE1_DESCRIPTOR::TYPE::Block
+        + STAGE1_DESCRIPTOR::AP::RW_EL1
+        + STAGE1_DESCRIPTOR::AF::True
+        + STAGE1_DESCRIPTOR::NG::True
+        + STAGE1_DESCRIPTOR::PXN::True
+        + STAGE1_DESCRIPTOR::UXN::True
+        + STAGE1_DESCRIPTOR::OUTPUT_ADDR_1GIB.val(index as u64)
+        + STAGE1_DESCRIPTOR::AttrIndx.val(mair::DEVICE)
+        + STAGE1_DESCRIPTOR::SH::OuterShareable)
+        .value
+}
+
+/// Page descriptor for a 4KiB kernel image page at the given index.
+fn page_descriptor(index: usize, read_only: bool) -> u64 {
+    let access = if read_only {
+        // Kernel code and read-only data
+        STAGE1_DESCRIPTOR::AP::RO_EL1 + STAGE1_DESCRIPTOR::PXN::False
+    } else {
+        STAGE1_DESCRIPTOR::AP::RW_EL1 + STAGE1_DESCRIPTOR::PXN::True
+    };
+
+    (STAGE1_DESCRIPTOR::VALID::True
+        + STAGE1_DESCRIPTOR::TYPE::Table // page descriptors use the table bit pattern
+        + STAGE1_DESCRIPTOR::AttrIndx.val(mair::NORMAL)
+        + STAGE1_DESCRIPTOR::SH::InnerShareable
+        + STAGE1_DESCRIPTOR::AF::True
+        + STAGE1_DESCRIPTOR::UXN::True
+        + STAGE1_DESCRIPTOR::OUTPUT_ADDR_4KIB.val(index as u64)
+        + access)
+        .value
+}
+
+/// Memory type of a user mapping.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Memory {
+    Normal,
+    Device,
+    NonCacheable,
+}
+
+/// Access a user protection domain has to a page or block.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct UserAccess {
+    pub write: bool,
+    pub execute: bool,
+    pub memory: Memory,
+}
+
+/// Descriptor giving EL0 access to the page or 2MiB block at `address`.
+///
+/// The kernel never executes user memory.
+pub fn user_descriptor(address: usize, block: bool, access: UserAccess) -> u64 {
+    let kind = if block {
+        STAGE1_DESCRIPTOR::TYPE::Block
+    } else {
+        STAGE1_DESCRIPTOR::TYPE::Table // page descriptors use the table bit pattern
+    };
+    let ap = if access.write {
+        STAGE1_DESCRIPTOR::AP::RW_EL1_EL0
+    } else {
+        STAGE1_DESCRIPTOR::AP::RO_EL1_EL0
+    };
+    let uxn = if access.execute && access.memory != Memory::Device {
+        STAGE1_DESCRIPTOR::UXN::False
+    } else {
+        STAGE1_DESCRIPTOR::UXN::True
+    };
+    let attrs = match access.memory {
+        Memory::Normal => {
+            STAGE1_DESCRIPTOR::AttrIndx.val(mair::NORMAL) + STAGE1_DESCRIPTOR::SH::InnerShareable
+        }
+        Memory::Device => {
+            STAGE1_DESCRIPTOR::AttrIndx.val(mair::DEVICE) + STAGE1_DESCRIPTOR::SH::OuterShareable
+        }
+        Memory::NonCacheable => {
+            STAGE1_DESCRIPTOR::AttrIndx.val(mair::NORMAL_NC)
+                + STAGE1_DESCRIPTOR::SH::OuterShareable
+        }
+    };
+
+    (STAGE1_DESCRIPTOR::VALID::True
+        + kind
+        + ap
+        + uxn
+        + attrs
+        + STAGE1_DESCRIPTOR::AF::True
+        + STAGE1_DESCRIPTOR::NG::True
+        + STAGE1_DESCRIPTOR::PXN::True
+        + STAGE1_DESCRIPTOR::OUTPUT_ADDR_4KIB.val(address as u64 >> PAGE_SHIFT))
+        .value
+}
+
+/// Whether a descriptor is a valid entry.
+pub fn is_valid(descriptor: u64) -> bool {
+    descriptor & 1 != 0
+}
+
+/// Whether a page or block descriptor maps cached normal memory.
+pub fn is_cached(descriptor: u64) -> bool {
+    let attr_index = (descriptor >> 2) & 0b111; // AttrIndx
+    attr_index == mair::NORMAL
+}
+
+/// Point the level 1 table `l1` of a user protection domain at `l2` for
+/// the first GiB, which shares the kernel image with the kernel tables.
+///
+/// Everything else is left invalid, the tables must be zeroed.
+pub fn init_user_tables(l1: &mut PageTable, l2: &mut PageTable) {
+    l2.entries[0] = unsafe { table_descriptor(&LVL3_TABLE) };
+    l1.entries[0] = table_descriptor(l2);
+}
+
+/// Translate with the tables at `root` tagged `asid` from now on.
+///
+/// The kernel image stays mapped the same, so execution continues.
+pub fn switch_tables(root: usize, asid: u8) {
+    TTBR0_EL1.write(TTBR0_EL1::ASID.val(u64::from(asid)) + TTBR0_EL1::BADDR.val(root as u64 >> 1));
+    unsafe {
+        barrier::isb(barrier::SY);
+    }
+}
+
+/// Translate with the kernel tables, ASID 0.
+pub fn use_kernel_tables() {
+    switch_tables(unsafe { &LVL1_TABLE as *const _ as usize }, 0);
+}
+
+/// Make table writes visible to the table walks of all cores, needed
+/// when entries become valid.
+pub fn publish_tables() {
+    unsafe {
+        barrier::dsb(barrier::SY);
+        barrier::isb(barrier::SY);
+    }
+}
+
+/// Drop TLB entries of all cores for the page or block at `address` as
+/// translated under `asid`, after its entry changed.
+pub fn invalidate_page(asid: u8, address: usize) {
+    let operand = (u64::from(asid) << 48) | ((address >> PAGE_SHIFT) as u64 & ((1 << 44) - 1));
+    unsafe {
+        // Make the table update visible to walks before dropping old entries.
+        barrier::dsb(barrier::SY);
+        asm!("tlbi vae1is, $0" :: "r"(operand) : "memory" : "volatile");
+        barrier::dsb(barrier::SY);
+        barrier::isb(barrier::SY);
+    }
+}
+
+/// Drop TLB entries of all cores for everything translated under `asid`.
+pub fn invalidate_asid(asid: u8) {
+    let operand = u64::from(asid) << 48;
+    unsafe {
+        barrier::dsb(barrier::SY);
+        asm!("tlbi aside1is, $0" :: "r"(operand) : "memory" : "volatile");
+        barrier::dsb(barrier::SY);
+        barrier::isb(barrier::SY);
+    }
+}
+
+/// Fill in the kernel translation tables.
+unsafe fn populate_tables() {
+    extern "C" {
+        // Boundaries of the kernel code and read-only data, from the linker script.
+        static __ro_start: u64;
+        static __ro_end: u64;
+    }
+
+    let ro_start = &__ro_start as *const _ as usize >> PAGE_SHIFT;
+    let ro_end = &__ro_end as *const _ as usize >> PAGE_SHIFT;
+
+    for (index, entry) in LVL3_TABLE.entries.iter_mut().enumerate() {
+        *entry = if smp::is_stack_guard(index << PAGE_SHIFT) {
+            0 // Invalid, so stack overflows fault
+        } else {
+            page_descriptor(index, index >= ro_start && index < ro_end)
+        };
+    }
+
+    // The device tree can't be parsed with the MMU off (all accesses are
+    // Device memory, no unaligned loads), so this is the RPi3 default.
+    let peripherals_start = BcmHost::get_peripheral_address() >> BLOCK_SHIFT;
+
+    LVL2_TABLE.entries[0] = table_descriptor(&LVL3_TABLE);
+    for (index, entry) in LVL2_TABLE.entries.iter_mut().enumerate().skip(1) {
+        *entry = block_descriptor(index, index >= peripherals_start);
+    }
+
+    LVL1_TABLE.entries[0] = table_descriptor(&LVL2_TABLE);
+
+    let local_peripherals = BcmHost::get_local_peripheral_address() >> HUGE_BLOCK_SHIFT;
+    LVL1_TABLE.entries[local_peripherals] = huge_device_block_descriptor(local_peripherals);
+}
+
+/// Set up translation tables and turn on the MMU and caches.
+///
+/// Must be called once on the boot core at EL1 with MMU off.
+pub unsafe fn init() {
+    populate_tables();
+    enable();
+}
+
+/// Turn on the MMU and caches using the already populated kernel tables.
+///
+/// Must be called at EL1 with MMU off, secondary cores call this directly.
+pub unsafe fn enable() {
+    // Device-nGnRE for peripherals, normal write-back RW-allocate for RAM,
+    // normal non-cacheable for user mappings that combine writes.
+    MAIR_EL1.write(
+        MAIR_EL1::Attr2_HIGH::Memory_OuterNonCacheable
+            + MAIR_EL1::Attr2_LOW_MEMORY::InnerNonCacheable
+            + MAIR_EL1::Attr1_HIGH::Memory_OuterWriteBack_NonTransient_ReadAlloc_WriteAlloc
+            + MAIR_EL1::Attr1_LOW_MEMORY::InnerWriteBack_NonTransient_ReadAlloc_WriteAlloc
+            + MAIR_EL1::Attr0_HIGH::Device
+            + MAIR_EL1::Attr0_LOW_DEVICE::Device_nGnRE,
+    );
+
+    TTBR0_EL1.set_baddr(&LVL1_TABLE as *const _ as u64);
+
+    // Use the full physical address range the CPU reports,
+    // RPi3 Cortex-A53 reports 40 bits.
+    let ips = ID_AA64MMFR0_EL1.read(ID_AA64MMFR0_EL1::PARange);
+
+    TCR_EL1.write(
+        TCR_EL1::TBI0::Ignored
+            + TCR_EL1::IPS.val(ips)
+            + TCR_EL1::TG0::KiB_4
+            + TCR_EL1::SH0::Inner
+            + TCR_EL1::ORGN0::WriteBack_ReadAlloc_WriteAlloc_Cacheable
+            + TCR_EL1::IRGN0::WriteBack_ReadAlloc_WriteAlloc_Cacheable
+            + TCR_EL1::EPD0::EnableTTBR0Walks
+            + TCR_EL1::EPD1::DisableTTBR1Walks
+            + TCR_EL1::T0SZ.val(64 - 39),
+    );
+
+    // Make sure table writes and register changes are seen before enabling.
+    barrier::dsb(barrier::SY);
+    asm!("tlbi vmalle1" :::: "volatile");
+    barrier::dsb(barrier::SY);
+    barrier::isb(barrier::SY);
+
+    SCTLR_EL1.modify(SCTLR_EL1::M::Enable + SCTLR_EL1::C::Cacheable + SCTLR_EL1::I::Cacheable);
+
+    // Force MMU init to complete before next instruction
+    barrier::isb(barrier::SY);
+}
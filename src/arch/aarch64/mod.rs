@@ -2,21 +2,15 @@
 
 use cortex_a::{asm, barrier, regs::*};
 
-/// The entry to Rust, all things must be initialized
-/// This is invoked from the linker script, does arch-specific init
-/// and passes control to the kernel boot function kmain().
-#[no_mangle]
-pub unsafe extern "C" fn karch_start() -> ! {
-    // Set sp to 0x80000 (just before kernel start)
-    const STACK_START: u64 = 0x8_0000;
-
-    SP.set(STACK_START);
-
-    match read_cpu_id() {
-        0 => ::kmain(),
-        _ => endless_sleep(), // if not core0, indefinitely wait for events
-    }
-}
+pub mod boot;
+pub mod cache;
+pub mod context;
+pub mod fault;
+pub mod mmu;
+pub mod smp;
+pub mod sync;
+pub mod timer;
+pub mod traps;
 
 // Data memory barrier
 #[inline]
@@ -27,34 +21,64 @@ pub fn dmb() {
 }
 
 #[inline]
-pub fn flushcache(address: usize) {
+pub fn read_cpu_id() -> u64 {
+    const CORE_MASK: u64 = 0x3;
+    MPIDR_EL1.get() & CORE_MASK
+}
+
+/// Current exception level, 0 to 3.
+#[inline]
+pub fn current_el() -> u32 {
+    CurrentEL.read(CurrentEL::EL)
+}
+
+/// Unmask IRQs on the calling core.
+#[inline]
+pub fn enable_irqs() {
     unsafe {
-        asm!("dc ivac, $0" :: "r"(address) :: "volatile");
+        asm!("msr daifclr, #2" :::: "volatile");
     }
 }
 
+/// Mask IRQs on the calling core.
 #[inline]
-pub fn read_cpu_id() -> u64 {
-    const CORE_MASK: u64 = 0x3;
-    MPIDR_EL1.get() & CORE_MASK
+pub fn disable_irqs() {
+    unsafe {
+        asm!("msr daifset, #2" :::: "volatile");
+    }
 }
 
+/// Whether an IRQ is pending on the calling core, even if masked.
 #[inline]
-pub fn current_el() -> u32 {
-    CurrentEL.get()
+pub fn irq_pending() -> bool {
+    const ISR_I: u64 = 1 << 7;
+    let isr: u64;
+    unsafe {
+        asm!("mrs $0, isr_el1" : "=r"(isr) ::: "volatile");
+    }
+    isr & ISR_I != 0
 }
 
+/// Set the EL0 read-only thread register, where user code finds its IPC buffer.
 #[inline]
-pub fn endless_sleep() -> ! {
-    loop {
-        asm::wfe();
+pub fn write_tpidrro_el0(value: u64) {
+    unsafe {
+        asm!("msr tpidrro_el0, $0" :: "r"(value) :: "volatile");
     }
 }
 
+/// Sleep until an interrupt is pending, even if masked.
 #[inline]
-pub fn loop_delay(rounds: u32) {
-    for _ in 0..rounds {
-        asm::nop();
+pub fn wait_for_interrupt() {
+    unsafe {
+        asm!("wfi" :::: "volatile");
+    }
+}
+
+#[inline]
+pub fn endless_sleep() -> ! {
+    loop {
+        asm::wfe();
     }
 }
 
@@ -0,0 +1,186 @@
+// mod arch::aarch64::smp
+
+//! Secondary core bring-up.
+//!
+//! RPi3 firmware parks cores 1-3 in the armstub, each polling its own
+//! spin-table release address; writing an entry point there and issuing
+//! `sev` starts the core. Firmware implementing PSCI starts cores via
+//! CPU_ON call instead. QEMU may start all cores at the kernel entry,
+//! in which case karch_start parks them in an equivalent spin loop.
+
+use arch::aarch64::{boot::karch_secondary_start, cache};
+use core::{
+    ptr,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+use cortex_a::asm;
+
+/// Must match BOOT_CORES in linker/aarch64.ld.
+pub const MAX_CPUS: usize = 4;
+
+/// Must match BOOT_STACK_GUARD in linker/aarch64.ld.
+const STACK_GUARD_SIZE: usize = 0x1000;
+
+/// Release addresses polled by the RPi3 armstub, one per core.
+const SPIN_TABLE_RELEASE_ADDR: [usize; MAX_CPUS] = [0xd8, 0xe0, 0xe8, 0xf0];
+
+/// PSCI 0.2 CPU_ON function id, SMC64 calling convention.
+const PSCI_CPU_ON_64: u64 = 0xc400_0003;
+
+/// Bitmask of cores which reached the kernel.
+static CPUS_ONLINE: AtomicUsize = AtomicUsize::new(1); // boot core is always online
+
+/// How to call into PSCI firmware.
+#[derive(Clone, Copy, Debug)]
+pub enum PsciConduit {
+    Hvc,
+    Smc,
+}
+
+/// How to start a secondary core, as in device tree `enable-method` property.
+#[derive(Clone, Copy, Debug)]
+pub enum EnableMethod {
+    SpinTable,
+    Psci(PsciConduit),
+}
+
+#[derive(Debug)]
+pub enum SmpError {
+    InvalidCpu,
+    PsciError(i64),
+    Timeout,
+}
+
+pub type Result<T> = ::core::result::Result<T, SmpError>;
+
+/// Boot stacks region from the linker script, guard page and stack per core.
+#[inline(always)]
+fn boot_stacks() -> (usize, usize) {
+    extern "C" {
+        static __boot_stacks_start: u64;
+        static __boot_stacks_end: u64;
+    }
+
+    unsafe {
+        (
+            &__boot_stacks_start as *const _ as usize,
+            &__boot_stacks_end as *const _ as usize,
+        )
+    }
+}
+
+/// Top of the boot stack for the given core.
+///
+/// Called before the stack is set up, so must not use it.
+#[inline(always)]
+pub fn stack_top(cpu: usize) -> u64 {
+    let (start, end) = boot_stacks();
+    let stride = (end - start) / MAX_CPUS;
+    (start + (cpu + 1) * stride) as u64
+}
+
+/// Whether the address lies in one of the boot stack guard pages.
+pub fn is_stack_guard(address: usize) -> bool {
+    let (start, end) = boot_stacks();
+    if address < start || address >= end {
+        return false;
+    }
+    let stride = (end - start) / MAX_CPUS;
+    (address - start) % stride < STACK_GUARD_SIZE
+}
+
+/// Bitmask of online cores.
+pub fn online_mask() -> usize {
+    CPUS_ONLINE.load(Ordering::SeqCst)
+}
+
+/// Number of online cores.
+pub fn online_count() -> usize {
+    online_mask().count_ones() as usize
+}
+
+/// Record that the calling core has reached the kernel.
+pub fn mark_online(cpu: usize) {
+    CPUS_ONLINE.fetch_or(1 << cpu, Ordering::SeqCst);
+}
+
+/// Park the core until the boot core writes its release address.
+///
+/// Mirrors the firmware armstub for the case when all cores enter karch_start.
+pub unsafe fn wait_for_release(cpu: usize) -> ! {
+    let release = SPIN_TABLE_RELEASE_ADDR[cpu] as *const u64;
+    loop {
+        asm::wfe();
+        let entry = ptr::read_volatile(release);
+        if entry != 0 {
+            let entry: extern "C" fn() -> ! = ::core::mem::transmute(entry as usize);
+            entry()
+        }
+    }
+}
+
+/// Start a single secondary core, it will enter karch_secondary_start().
+pub fn start_cpu(cpu: usize, method: EnableMethod) -> Result<()> {
+    if cpu == 0 || cpu >= MAX_CPUS {
+        return Err(SmpError::InvalidCpu);
+    }
+
+    let entry = karch_secondary_start as *const () as u64;
+
+    match method {
+        EnableMethod::SpinTable => unsafe {
+            let release = SPIN_TABLE_RELEASE_ADDR[cpu];
+            ptr::write_volatile(release as *mut u64, entry);
+            // The parked core runs with caches off, push the write to memory.
+            cache::clean_invalidate_range(release, 8);
+            asm!("sev" :::: "volatile");
+            Ok(())
+        },
+        EnableMethod::Psci(conduit) => match unsafe { psci_cpu_on(conduit, cpu as u64, entry) } {
+            0 => Ok(()),
+            err => Err(SmpError::PsciError(err)),
+        },
+    }
+}
+
+/// Start all secondary cores and wait for them to come online.
+///
+/// Returns the number of online cores.
+pub fn start_secondary_cpus(method: EnableMethod) -> Result<usize> {
+    let mut expected = 1;
+    for cpu in 1..MAX_CPUS {
+        start_cpu(cpu, method)?;
+        expected |= 1 << cpu;
+    }
+
+    let mut count: u32 = 0;
+    while online_mask() != expected {
+        count += 1;
+        if count > (1 << 25) {
+            return Err(SmpError::Timeout);
+        }
+        asm::nop();
+    }
+
+    Ok(online_count())
+}
+
+/// PSCI CPU_ON: start core `mpidr` at physical address `entry`.
+unsafe fn psci_cpu_on(conduit: PsciConduit, mpidr: u64, entry: u64) -> i64 {
+    let ret: i64;
+    match conduit {
+        PsciConduit::Hvc => asm!("hvc #0"
+            : "={x0}"(ret)
+            : "{x0}"(PSCI_CPU_ON_64), "{x1}"(mpidr), "{x2}"(entry), "{x3}"(0u64)
+            : "x4", "x5", "x6", "x7", "x8", "x9", "x10", "x11",
+              "x12", "x13", "x14", "x15", "x16", "x17", "memory"
+            : "volatile"),
+        PsciConduit::Smc => asm!("smc #0"
+            : "={x0}"(ret)
+            : "{x0}"(PSCI_CPU_ON_64), "{x1}"(mpidr), "{x2}"(entry), "{x3}"(0u64)
+            : "x4", "x5", "x6", "x7", "x8", "x9", "x10", "x11",
+              "x12", "x13", "x14", "x15", "x16", "x17", "memory"
+            : "volatile"),
+    }
+    ret
+}
@@ -0,0 +1,263 @@
+// mod arch::aarch64::sync
+
+//! Spinning locks built on load-acquire exclusive/store-exclusive pairs.
+//!
+//! Waiters sleep in `wfe` until the owner's store-release clears their
+//! exclusive monitor, generating a wake-up event.
+//!
+//! Locks taken with `lock_irqsave()` additionally mask IRQs on the
+//! calling core for as long as the guard lives, which is required for
+//! data shared with interrupt handlers.
+
+use core::{
+    cell::UnsafeCell,
+    ops::{Deref, DerefMut},
+};
+use cortex_a::regs::*;
+
+/// Masks IRQs on the calling core until dropped, restoring the previous state.
+pub struct IrqGuard {
+    saved_daif: u32,
+}
+
+impl IrqGuard {
+    pub fn new() -> IrqGuard {
+        let saved_daif = DAIF.get();
+        unsafe {
+            asm!("msr daifset, #2" ::: "memory" : "volatile");
+        }
+        IrqGuard { saved_daif }
+    }
+}
+
+impl Drop for IrqGuard {
+    fn drop(&mut self) {
+        DAIF.set(self.saved_daif);
+    }
+}
+
+/// Simple test-and-set spinlock.
+pub struct SpinLock<T> {
+    locked: UnsafeCell<u32>,
+    data: UnsafeCell<T>,
+}
+
+unsafe impl<T: Send> Sync for SpinLock<T> {}
+unsafe impl<T: Send> Send for SpinLock<T> {}
+
+pub struct SpinLockGuard<'a, T: 'a> {
+    lock: &'a SpinLock<T>,
+    _irq: Option<IrqGuard>, // dropped after the lock is released
+}
+
+impl<T> SpinLock<T> {
+    pub const fn new(data: T) -> SpinLock<T> {
+        SpinLock {
+            locked: UnsafeCell::new(0),
+            data: UnsafeCell::new(data),
+        }
+    }
+
+    fn acquire(&self) {
+        let _tmp: u32;
+        unsafe {
+            asm!("
+                    sevl
+                1:  wfe
+                2:  ldaxr   ${0:w}, [$1]
+                    cbnz    ${0:w}, 1b
+                    stxr    ${0:w}, ${2:w}, [$1]
+                    cbnz    ${0:w}, 2b"
+                : "=&r"(_tmp)
+                : "r"(self.locked.get()), "r"(1u32)
+                : "memory"
+                : "volatile");
+        }
+    }
+
+    fn try_acquire(&self) -> bool {
+        let failed: u32;
+        unsafe {
+            asm!("
+                1:  ldaxr   ${0:w}, [$1]
+                    cbnz    ${0:w}, 2f
+                    stxr    ${0:w}, ${2:w}, [$1]
+                    cbnz    ${0:w}, 1b
+                    b       3f
+                2:  clrex
+                3:"
+                : "=&r"(failed)
+                : "r"(self.locked.get()), "r"(1u32)
+                : "memory"
+                : "volatile");
+        }
+        failed == 0
+    }
+
+    fn release(&self) {
+        unsafe {
+            asm!("stlr wzr, [$0]" :: "r"(self.locked.get()) : "memory" : "volatile");
+        }
+    }
+
+    /// Spin until the lock is ours.
+    pub fn lock(&self) -> SpinLockGuard<T> {
+        self.acquire();
+        SpinLockGuard {
+            lock: self,
+            _irq: None,
+        }
+    }
+
+    /// Mask IRQs on this core, then spin until the lock is ours.
+    pub fn lock_irqsave(&self) -> SpinLockGuard<T> {
+        let irq = IrqGuard::new();
+        self.acquire();
+        SpinLockGuard {
+            lock: self,
+            _irq: Some(irq),
+        }
+    }
+
+    pub fn try_lock(&self) -> Option<SpinLockGuard<T>> {
+        if self.try_acquire() {
+            Some(SpinLockGuard {
+                lock: self,
+                _irq: None,
+            })
+        } else {
+            None
+        }
+    }
+
+    /// Access the data without locking, for use before other cores run
+    /// or from a fault handler when the lock owner will never return.
+    pub unsafe fn force_get(&self) -> &mut T {
+        &mut *self.data.get()
+    }
+}
+
+impl<'a, T> Deref for SpinLockGuard<'a, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        unsafe { &*self.lock.data.get() }
+    }
+}
+
+impl<'a, T> DerefMut for SpinLockGuard<'a, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        unsafe { &mut *self.lock.data.get() }
+    }
+}
+
+impl<'a, T> Drop for SpinLockGuard<'a, T> {
+    fn drop(&mut self) {
+        self.lock.release();
+    }
+}
+
+/// Fair FIFO spinlock.
+///
+/// The lock word holds the ticket being served in the low halfword
+/// and the next ticket to hand out in the high halfword.
+pub struct TicketLock<T> {
+    tickets: UnsafeCell<u32>,
+    data: UnsafeCell<T>,
+}
+
+unsafe impl<T: Send> Sync for TicketLock<T> {}
+unsafe impl<T: Send> Send for TicketLock<T> {}
+
+pub struct TicketLockGuard<'a, T: 'a> {
+    lock: &'a TicketLock<T>,
+    _irq: Option<IrqGuard>, // dropped after the lock is released
+}
+
+impl<T> TicketLock<T> {
+    pub const fn new(data: T) -> TicketLock<T> {
+        TicketLock {
+            tickets: UnsafeCell::new(0),
+            data: UnsafeCell::new(data),
+        }
+    }
+
+    fn acquire(&self) {
+        let (_ticket, _tmp, _owner): (u32, u32, u32);
+        unsafe {
+            asm!("
+                    // Take a ticket
+                1:  ldaxr   ${0:w}, [$3]
+                    add     ${1:w}, ${0:w}, #0x10000
+                    stxr    ${2:w}, ${1:w}, [$3]
+                    cbnz    ${2:w}, 1b
+                    // Is it being served already?
+                    eor     ${1:w}, ${0:w}, ${0:w}, ror #16
+                    cbz     ${1:w}, 3f
+                    // Wait for our turn
+                    sevl
+                2:  wfe
+                    ldaxrh  ${2:w}, [$3]
+                    eor     ${1:w}, ${2:w}, ${0:w}, lsr #16
+                    cbnz    ${1:w}, 2b
+                3:"
+                : "=&r"(_ticket), "=&r"(_tmp), "=&r"(_owner)
+                : "r"(self.tickets.get())
+                : "memory"
+                : "volatile");
+        }
+    }
+
+    fn release(&self) {
+        let _owner: u32;
+        unsafe {
+            asm!("
+                ldrh    ${0:w}, [$1]
+                add     ${0:w}, ${0:w}, #1
+                stlrh   ${0:w}, [$1]"
+                : "=&r"(_owner)
+                : "r"(self.tickets.get())
+                : "memory"
+                : "volatile");
+        }
+    }
+
+    /// Take a ticket and spin until it is served.
+    pub fn lock(&self) -> TicketLockGuard<T> {
+        self.acquire();
+        TicketLockGuard {
+            lock: self,
+            _irq: None,
+        }
+    }
+
+    /// Mask IRQs on this core, then take a ticket and spin until it is served.
+    pub fn lock_irqsave(&self) -> TicketLockGuard<T> {
+        let irq = IrqGuard::new();
+        self.acquire();
+        TicketLockGuard {
+            lock: self,
+            _irq: Some(irq),
+        }
+    }
+}
+
+impl<'a, T> Deref for TicketLockGuard<'a, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        unsafe { &*self.lock.data.get() }
+    }
+}
+
+impl<'a, T> DerefMut for TicketLockGuard<'a, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        unsafe { &mut *self.lock.data.get() }
+    }
+}
+
+impl<'a, T> Drop for TicketLockGuard<'a, T> {
+    fn drop(&mut self) {
+        self.lock.release();
+    }
+}
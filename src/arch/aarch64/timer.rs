@@ -0,0 +1,198 @@
+// mod arch::aarch64::timer
+
+//! ARM generic timer.
+//!
+//! The system counter CNTPCT_EL0 ticks at CNTFRQ_EL0 Hz on all cores
+//! and provides monotonic time. Each core has its own EL1 physical timer
+//! which fires an interrupt when the counter reaches CNTP_CVAL_EL0;
+//! it is used for one-shot and periodic deadlines.
+//!
+//! Delivery of the timer interrupt is up to the interrupt controller,
+//! which must call `handle_interrupt()` on the core that got it.
+
+use arch::aarch64::{read_cpu_id, smp::MAX_CPUS};
+use core::time::Duration;
+use cortex_a::{asm, barrier, regs::*};
+
+/// Counter frequency used when firmware didn't program CNTFRQ_EL0,
+/// RPi3 crystal runs at 19.2MHz.
+const DEFAULT_FREQUENCY: u64 = 19_200_000;
+
+const NANOS_PER_SEC: u64 = 1_000_000_000;
+
+/// Called from the timer interrupt on the core it fired on.
+pub type Handler = fn();
+
+#[derive(Clone, Copy)]
+struct Deadline {
+    handler: Option<Handler>,
+    /// Re-arm period in ticks, 0 for one-shot deadlines.
+    period: u64,
+}
+
+/// Per-core deadline state, each core only touches its own entry.
+static mut DEADLINES: [Deadline; MAX_CPUS] = [Deadline {
+    handler: None,
+    period: 0,
+}; MAX_CPUS];
+
+/// Longest delay between a deadline and its handler running seen on
+/// each core, in ticks. Each core only raises its own entry.
+static mut WORST_LATENCY: [u64; MAX_CPUS] = [0; MAX_CPUS];
+
+/// Counter frequency in Hz.
+#[inline]
+pub fn frequency() -> u64 {
+    match CNTFRQ_EL0.get() as u64 {
+        0 => DEFAULT_FREQUENCY,
+        freq => freq,
+    }
+}
+
+/// Current counter value.
+#[inline]
+pub fn ticks() -> u64 {
+    unsafe {
+        // Prevent the counter read from being executed out of order.
+        barrier::isb(barrier::SY);
+    }
+    CNTPCT_EL0.get()
+}
+
+/// Convert a duration into counter ticks, rounding up.
+pub fn duration_to_ticks(duration: Duration) -> u64 {
+    let freq = frequency();
+    let secs = duration.as_secs() * freq;
+    let nanos = (u64::from(duration.subsec_nanos()) * freq + NANOS_PER_SEC - 1) / NANOS_PER_SEC;
+    secs + nanos
+}
+
+/// Convert counter ticks into a duration, rounding down.
+pub fn ticks_to_duration(ticks: u64) -> Duration {
+    let freq = frequency();
+    let secs = ticks / freq;
+    let nanos = (ticks % freq) * NANOS_PER_SEC / freq;
+    Duration::new(secs, nanos as u32)
+}
+
+/// Whole microseconds of a duration, as reported to user space.
+pub fn duration_to_micros(duration: Duration) -> u64 {
+    duration.as_secs() * 1_000_000 + u64::from(duration.subsec_micros())
+}
+
+/// Monotonic time since the counter started.
+pub fn monotonic() -> Duration {
+    ticks_to_duration(ticks())
+}
+
+/// Busy-wait until the counter reaches `deadline`.
+#[inline]
+pub fn wait_until(deadline: u64) {
+    while ticks() < deadline {
+        asm::nop();
+    }
+}
+
+/// Busy-wait for the given duration.
+pub fn delay(duration: Duration) {
+    wait_until(ticks() + duration_to_ticks(duration));
+}
+
+/// Busy-wait for the given number of microseconds.
+pub fn delay_us(us: u64) {
+    delay(Duration::from_micros(us))
+}
+
+#[inline]
+fn set_compare_value(value: u64) {
+    unsafe {
+        asm!("msr cntp_cval_el0, $0" :: "r"(value) :: "volatile");
+    }
+}
+
+#[inline]
+fn compare_value() -> u64 {
+    let value: u64;
+    unsafe {
+        asm!("mrs $0, cntp_cval_el0" : "=r"(value) ::: "volatile");
+    }
+    value
+}
+
+/// Arm the calling core's timer to call `handler` once at counter value `deadline`.
+pub fn set_oneshot(deadline: u64, handler: Handler) {
+    arm(deadline, 0, handler);
+}
+
+/// Arm the calling core's timer to call `handler` every `period`,
+/// starting one period from now.
+pub fn set_periodic(period: Duration, handler: Handler) {
+    let period = duration_to_ticks(period);
+    arm(ticks() + period, period, handler);
+}
+
+fn arm(deadline: u64, period: u64, handler: Handler) {
+    unsafe {
+        DEADLINES[read_cpu_id() as usize] = Deadline {
+            handler: Some(handler),
+            period,
+        };
+    }
+    set_compare_value(deadline);
+    CNTP_CTL_EL0.write(CNTP_CTL_EL0::ENABLE::SET + CNTP_CTL_EL0::IMASK::CLEAR);
+}
+
+/// Disarm the calling core's timer.
+pub fn cancel() {
+    CNTP_CTL_EL0.write(CNTP_CTL_EL0::ENABLE::CLEAR + CNTP_CTL_EL0::IMASK::SET);
+    unsafe {
+        DEADLINES[read_cpu_id() as usize].handler = None;
+    }
+}
+
+/// Whether the calling core's timer condition is met.
+pub fn is_pending() -> bool {
+    CNTP_CTL_EL0.is_set(CNTP_CTL_EL0::ISTATUS)
+}
+
+/// Worst-case interrupt latency observed on any core since the last
+/// `reset_worst_latency()`.
+///
+/// Measured from the deadline to the timer interrupt being handled, so it
+/// covers the longest stretch the kernel ran with interrupts masked.
+pub fn worst_latency() -> Duration {
+    let worst = unsafe { WORST_LATENCY.iter().cloned().max().unwrap_or(0) };
+    ticks_to_duration(worst)
+}
+
+pub fn reset_worst_latency() {
+    unsafe {
+        WORST_LATENCY = [0; MAX_CPUS];
+    }
+}
+
+/// Timer interrupt handler.
+///
+/// Records the interrupt latency, re-arms periodic deadlines relative to
+/// the previous deadline to avoid drift, disarms one-shot ones, then
+/// calls the registered handler.
+pub fn handle_interrupt() {
+    let cpu = read_cpu_id() as usize;
+    let latency = ticks().saturating_sub(compare_value());
+    let deadline = unsafe {
+        if latency > WORST_LATENCY[cpu] {
+            WORST_LATENCY[cpu] = latency;
+        }
+        DEADLINES[cpu]
+    };
+
+    if deadline.period != 0 {
+        set_compare_value(compare_value() + deadline.period);
+    } else {
+        CNTP_CTL_EL0.write(CNTP_CTL_EL0::ENABLE::CLEAR + CNTP_CTL_EL0::IMASK::SET);
+    }
+
+    if let Some(handler) = deadline.handler {
+        handler();
+    }
+}
@@ -0,0 +1,250 @@
+// mod arch::aarch64::traps
+
+//! Exception vectors and Rust-level exception dispatch.
+//!
+//! The vector table itself lives in vectors.S, every entry saves
+//! the interrupted context as a `TrapFrame` on the stack and calls
+//! one of the handlers below with a pointer to it.
+//!
+//! Entries from a lower EL first switch back to the kernel tables, only
+//! the kernel image is mapped in user protection domains.
+
+use arch::{
+    aarch64::fault::{class, FaultReport, Syndrome},
+    endless_sleep,
+};
+use core::fmt::{self, Write};
+use cortex_a::{barrier, regs::*};
+use objects::{tcb::Fault, vspace};
+use platform::{irq, uart::CONSOLE};
+use sched;
+use syscall;
+
+global_asm!(include_str!("vectors.S"));
+
+/// Saved context of the interrupted code.
+///
+/// Layout must match SAVE_CONTEXT/RESTORE_CONTEXT macros in vectors.S.
+#[derive(Clone, Copy)]
+#[repr(C)]
+pub struct TrapFrame {
+    /// General purpose registers x0-x30.
+    pub gpr: [u64; 31],
+    /// Stack pointer of EL0.
+    pub sp_el0: u64,
+    /// Exception link register, the address to return to.
+    pub elr_el1: u64,
+    /// Saved program status register.
+    pub spsr_el1: u64,
+}
+
+/// Kind of exception within a vector group.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ExceptionKind {
+    Synchronous,
+    Irq,
+    Fiq,
+    SError,
+}
+
+/// Where the exception was taken from, i.e. the vector group.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ExceptionOrigin {
+    CurrentElSp0,
+    CurrentElSpx,
+    LowerAArch64,
+    LowerAArch32,
+}
+
+impl fmt::Display for TrapFrame {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "ELR_EL1: {:016x} SPSR_EL1: {:08x}", self.elr_el1, self.spsr_el1)?;
+        writeln!(
+            f,
+            "  flags: {}{}{}{} mask: {}{}{}{} mode: {:#x}",
+            if self.spsr_el1 & (1 << 31) != 0 { 'N' } else { '-' },
+            if self.spsr_el1 & (1 << 30) != 0 { 'Z' } else { '-' },
+            if self.spsr_el1 & (1 << 29) != 0 { 'C' } else { '-' },
+            if self.spsr_el1 & (1 << 28) != 0 { 'V' } else { '-' },
+            if self.spsr_el1 & (1 << 9) != 0 { 'D' } else { '-' },
+            if self.spsr_el1 & (1 << 8) != 0 { 'A' } else { '-' },
+            if self.spsr_el1 & (1 << 7) != 0 { 'I' } else { '-' },
+            if self.spsr_el1 & (1 << 6) != 0 { 'F' } else { '-' },
+            self.spsr_el1 & 0x1f
+        )?;
+        writeln!(f, "SP_EL0:  {:016x}", self.sp_el0)?;
+        for i in (0..30).step_by(2) {
+            writeln!(
+                f,
+                "x{:<2}: {:016x}  x{:<2}: {:016x}",
+                i,
+                self.gpr[i],
+                i + 1,
+                self.gpr[i + 1]
+            )?;
+        }
+        writeln!(f, "x30: {:016x}", self.gpr[30])?;
+        Ok(())
+    }
+}
+
+/// Install the exception vector table for EL1.
+///
+/// Must be called at EL1 before any exception could possibly be taken.
+pub fn set_vbar_el1() {
+    extern "C" {
+        static __exception_vectors_start: u64;
+    }
+
+    unsafe {
+        VBAR_EL1.set(&__exception_vectors_start as *const _ as u64);
+        barrier::isb(barrier::SY);
+    }
+}
+
+/// Fallback for exceptions nobody has claimed yet.
+fn unhandled(origin: ExceptionOrigin, kind: ExceptionKind, frame: &mut TrapFrame) -> ! {
+    let mut console = CONSOLE.lock_irqsave();
+    writeln!(console, "\n[!] Unhandled {:?} exception from {:?}", kind, origin);
+    write!(console, "{}", frame);
+    endless_sleep()
+}
+
+// Dispatch per exception kind. Drivers and the syscall path hook in here.
+
+fn synchronous(origin: ExceptionOrigin, frame: &mut TrapFrame) {
+    if origin == ExceptionOrigin::LowerAArch64 {
+        let syndrome = Syndrome::read();
+        return match syndrome.class() {
+            class::SVC64 => syscall::handle_syscall(frame),
+            class::TRAPPED_FP_SIMD => sched::handle_fpu_trap(frame),
+            // Anything else only concerns the thread that caused it.
+            _ => {
+                let fault = Fault {
+                    syndrome: u64::from(syndrome.0),
+                    address: FAR_EL1.get(),
+                    pc: frame.elr_el1,
+                };
+                sched::handle_fault(frame, fault)
+            }
+        };
+    }
+
+    write!(CONSOLE.lock_irqsave(), "{}", FaultReport::new(origin, frame));
+    endless_sleep()
+}
+
+fn irq(origin: ExceptionOrigin, frame: &mut TrapFrame) {
+    match origin {
+        ExceptionOrigin::CurrentElSpx => irq::dispatch(),
+        ExceptionOrigin::LowerAArch64 => {
+            irq::dispatch();
+            sched::preempt(frame);
+        }
+        _ => unhandled(origin, ExceptionKind::Irq, frame),
+    }
+}
+
+fn fiq(origin: ExceptionOrigin, frame: &mut TrapFrame) {
+    unhandled(origin, ExceptionKind::Fiq, frame);
+}
+
+fn serror(origin: ExceptionOrigin, frame: &mut TrapFrame) {
+    unhandled(origin, ExceptionKind::SError, frame);
+}
+
+// Current EL with SP0
+
+#[no_mangle]
+unsafe extern "C" fn current_el0_synchronous(frame: &mut TrapFrame) {
+    synchronous(ExceptionOrigin::CurrentElSp0, frame);
+}
+
+#[no_mangle]
+unsafe extern "C" fn current_el0_irq(frame: &mut TrapFrame) {
+    irq(ExceptionOrigin::CurrentElSp0, frame);
+}
+
+#[no_mangle]
+unsafe extern "C" fn current_el0_fiq(frame: &mut TrapFrame) {
+    fiq(ExceptionOrigin::CurrentElSp0, frame);
+}
+
+#[no_mangle]
+unsafe extern "C" fn current_el0_serror(frame: &mut TrapFrame) {
+    serror(ExceptionOrigin::CurrentElSp0, frame);
+}
+
+// Current EL with SPx
+
+#[no_mangle]
+unsafe extern "C" fn current_elx_synchronous(frame: &mut TrapFrame) {
+    synchronous(ExceptionOrigin::CurrentElSpx, frame);
+}
+
+#[no_mangle]
+unsafe extern "C" fn current_elx_irq(frame: &mut TrapFrame) {
+    irq(ExceptionOrigin::CurrentElSpx, frame);
+}
+
+#[no_mangle]
+unsafe extern "C" fn current_elx_fiq(frame: &mut TrapFrame) {
+    fiq(ExceptionOrigin::CurrentElSpx, frame);
+}
+
+#[no_mangle]
+unsafe extern "C" fn current_elx_serror(frame: &mut TrapFrame) {
+    serror(ExceptionOrigin::CurrentElSpx, frame);
+}
+
+// Lower EL using AArch64
+
+#[no_mangle]
+unsafe extern "C" fn lower_aarch64_synchronous(frame: &mut TrapFrame) {
+    vspace::unload();
+    synchronous(ExceptionOrigin::LowerAArch64, frame);
+}
+
+#[no_mangle]
+unsafe extern "C" fn lower_aarch64_irq(frame: &mut TrapFrame) {
+    vspace::unload();
+    irq(ExceptionOrigin::LowerAArch64, frame);
+}
+
+#[no_mangle]
+unsafe extern "C" fn lower_aarch64_fiq(frame: &mut TrapFrame) {
+    vspace::unload();
+    fiq(ExceptionOrigin::LowerAArch64, frame);
+}
+
+#[no_mangle]
+unsafe extern "C" fn lower_aarch64_serror(frame: &mut TrapFrame) {
+    vspace::unload();
+    serror(ExceptionOrigin::LowerAArch64, frame);
+}
+
+// Lower EL using AArch32
+
+#[no_mangle]
+unsafe extern "C" fn lower_aarch32_synchronous(frame: &mut TrapFrame) {
+    vspace::unload();
+    synchronous(ExceptionOrigin::LowerAArch32, frame);
+}
+
+#[no_mangle]
+unsafe extern "C" fn lower_aarch32_irq(frame: &mut TrapFrame) {
+    vspace::unload();
+    irq(ExceptionOrigin::LowerAArch32, frame);
+}
+
+#[no_mangle]
+unsafe extern "C" fn lower_aarch32_fiq(frame: &mut TrapFrame) {
+    vspace::unload();
+    fiq(ExceptionOrigin::LowerAArch32, frame);
+}
+
+#[no_mangle]
+unsafe extern "C" fn lower_aarch32_serror(frame: &mut TrapFrame) {
+    vspace::unload();
+    serror(ExceptionOrigin::LowerAArch32, frame);
+}
@@ -0,0 +1,375 @@
+// mod caps::cspace
+
+//! Capability addressing and CNode operations.
+//!
+//! A CPtr is resolved starting from a thread's root CNode capability.
+//! Each CNode on the way consumes `guard_bits` bits, which must equal its
+//! guard, followed by `radix` bits indexing a slot, most significant bits
+//! first. If bits remain and the slot holds another CNode, resolution
+//! continues there.
+//!
+//! Operations are not thread safe, callers serialize them.
+
+use arch::sync::SpinLock;
+use caps::{slot::CapSlot, CapError, CapRights, Capability, Result};
+use core::ptr;
+use objects::{
+    cnode,
+    domain::{self, Domain},
+    endpoint::{self, Endpoint},
+    notification::{self, Notification},
+    tcb::{self, Tcb},
+    vspace, ObjectError, ObjectType,
+};
+use sched::{self, preemption};
+
+pub use vesper_user::{CPtr, CPTR_BITS};
+
+/// Result of resolving a CPtr.
+pub struct Resolved {
+    pub slot: &'static mut CapSlot,
+    /// Bits of the CPtr not consumed by the CNodes on the way.
+    pub bits_remaining: u8,
+}
+
+/// Walk `depth` most significant bits of `cptr` from `root`.
+///
+/// Stops early at a slot that doesn't hold a CNode.
+pub fn resolve(root: &Capability, cptr: CPtr, depth: u8) -> Result<Resolved> {
+    if depth == 0 || depth > CPTR_BITS {
+        return Err(CapError::RangeError);
+    }
+
+    let mut cnode = *root;
+    let mut remaining = depth;
+    loop {
+        let (ptr, radix, guard, guard_bits) = match cnode {
+            Capability::CNode {
+                ptr,
+                radix,
+                guard,
+                guard_bits,
+            } => (ptr, radix, guard, guard_bits),
+            _ => return Err(CapError::FailedLookup),
+        };
+
+        let level_bits = radix + guard_bits;
+        if level_bits > remaining {
+            return Err(CapError::FailedLookup);
+        }
+
+        let bits = |shift: u8, count: u8| {
+            if count == 0 {
+                0
+            } else {
+                (cptr >> shift) & (!0u64 >> (64 - u32::from(count)))
+            }
+        };
+
+        if bits(remaining - guard_bits, guard_bits) != guard {
+            return Err(CapError::FailedLookup);
+        }
+        let index = bits(remaining - level_bits, radix) as usize;
+        remaining -= level_bits;
+
+        let slot = cnode::slot(ptr, index);
+        match slot.cap() {
+            Capability::CNode { .. } if remaining > 0 => cnode = slot.cap(),
+            _ => {
+                return Ok(Resolved {
+                    slot,
+                    bits_remaining: remaining,
+                })
+            }
+        }
+    }
+}
+
+/// Slot at exactly `depth` bits of `cptr`, as required by CNode operations.
+pub fn lookup_slot(root: &Capability, cptr: CPtr, depth: u8) -> Result<&'static mut CapSlot> {
+    let resolved = resolve(root, cptr, depth)?;
+    if resolved.bits_remaining != 0 {
+        return Err(CapError::FailedLookup);
+    }
+    Ok(resolved.slot)
+}
+
+/// Capability addressed by all bits of `cptr`, as used for invocations.
+pub fn lookup_cap(root: &Capability, cptr: CPtr) -> Result<Capability> {
+    Ok(resolve(root, cptr, CPTR_BITS)?.slot.cap())
+}
+
+/// Slot `index` of a CNode capability.
+pub fn cnode_slot(cnode: &Capability, index: usize) -> Result<&'static mut CapSlot> {
+    match *cnode {
+        Capability::CNode { ptr, radix, .. } => {
+            if index >= 1 << radix {
+                return Err(CapError::RangeError);
+            }
+            Ok(cnode::slot(ptr, index))
+        }
+        _ => Err(CapError::InvalidCapability),
+    }
+}
+
+fn check_dest(src: &CapSlot, dest: &CapSlot) -> Result<()> {
+    if ptr::eq(src, dest) || !dest.is_empty() {
+        return Err(CapError::DeleteFirst);
+    }
+    if src.is_empty() {
+        return Err(CapError::FailedLookup);
+    }
+    Ok(())
+}
+
+fn check_derivable(src: &CapSlot) -> Result<()> {
+    if !src.cap().is_derivable() {
+        return Err(CapError::IllegalOperation);
+    }
+    Ok(())
+}
+
+/// Derive a copy of `src` into `dest` with rights masked by `rights`.
+pub fn copy(src: &mut CapSlot, dest: &mut CapSlot, rights: CapRights) -> Result<()> {
+    check_dest(src, dest)?;
+    check_derivable(src)?;
+    let cap = src.cap().unmapped().with_rights(rights);
+    src.insert_child(dest, cap);
+    Ok(())
+}
+
+/// Like `copy()`, additionally setting the badge or guard from `data`.
+pub fn mint(src: &mut CapSlot, dest: &mut CapSlot, rights: CapRights, data: u64) -> Result<()> {
+    check_dest(src, dest)?;
+    check_derivable(src)?;
+    let cap = src.cap().unmapped().with_rights(rights).with_data(data)?;
+    src.insert_child(dest, cap);
+    Ok(())
+}
+
+/// Move the capability from `src` to `dest`, keeping its derivation.
+pub fn move_cap(src: &mut CapSlot, dest: &mut CapSlot) -> Result<()> {
+    check_dest(src, dest)?;
+    src.move_to(dest);
+    Ok(())
+}
+
+/// Like `move_cap()`, additionally setting the badge or guard from `data`.
+pub fn mutate(src: &mut CapSlot, dest: &mut CapSlot, data: u64) -> Result<()> {
+    check_dest(src, dest)?;
+    let cap = src.cap().with_data(data)?;
+    src.move_to(dest);
+    dest.update(cap);
+    Ok(())
+}
+
+/// Upper bound on nested deletions of objects holding capability slots,
+/// keeping the recursion within the kernel stack.
+const MAX_DELETE_DEPTH: usize = 16;
+
+/// Slots whose object `delete()` is emptying, innermost last.
+struct Deleting {
+    slots: [usize; MAX_DELETE_DEPTH],
+    depth: usize,
+}
+
+impl Deleting {
+    const fn new() -> Deleting {
+        Deleting {
+            slots: [0; MAX_DELETE_DEPTH],
+            depth: 0,
+        }
+    }
+
+    fn contains(&self, slot: &CapSlot) -> bool {
+        let slot = slot as *const CapSlot as usize;
+        self.slots[..self.depth].contains(&slot)
+    }
+
+    /// Returns false if nesting is too deep.
+    fn push(&mut self, slot: &CapSlot) -> bool {
+        if self.depth == MAX_DELETE_DEPTH {
+            return false;
+        }
+        self.slots[self.depth] = slot as *const CapSlot as usize;
+        self.depth += 1;
+        true
+    }
+
+    fn pop(&mut self) {
+        self.depth -= 1;
+    }
+}
+
+static DELETING: SpinLock<Deleting> = SpinLock::new(Deleting::new());
+
+/// Empty the slot. Destroys the object if this was its last capability.
+///
+/// Access a frame granted and PageTable mappings go first. The object is
+/// emptied before the capability goes, so if either stops at
+/// a preemption point deleting the slot again continues where it left
+/// off. A slot met again through a cycle of CNodes while its object is
+/// being emptied is left for the outer deletion to remove.
+///
+/// Objects holding slots nested more than `MAX_DELETE_DEPTH` deep fail
+/// with `DeleteFirst`, the inner ones have to be deleted first.
+pub fn delete(slot: &mut CapSlot) -> Result<()> {
+    if slot.is_empty() || DELETING.lock().contains(slot) {
+        return Ok(());
+    }
+    vspace::unmap(slot)?;
+    let last = slot.is_final();
+    if last {
+        let holds_slots = match slot.cap() {
+            Capability::CNode { .. } | Capability::Tcb { .. } => true,
+            _ => false,
+        };
+        if holds_slots && !DELETING.lock().push(slot) {
+            return Err(CapError::DeleteFirst);
+        }
+        let emptied = empty_object(slot.cap());
+        if holds_slots {
+            DELETING.lock().pop();
+        }
+        emptied?;
+    }
+    let cap = slot.remove();
+    if last {
+        finalize(cap)?;
+    }
+    Ok(())
+}
+
+/// Delete all capabilities derived from the one in `slot`.
+///
+/// An Untyped is then cleared to be retyped from the start again.
+/// Stops at preemption points, revoking again continues.
+pub fn revoke(slot: &mut CapSlot) -> Result<()> {
+    // Leaves first, so nothing gets reparented.
+    while let Some(mut leaf) = slot.first_child() {
+        while let Some(child) = leaf.first_child() {
+            leaf = child;
+        }
+        delete(leaf)?;
+        preemption::point()?;
+    }
+    if let Capability::Untyped(mut untyped) = slot.cap() {
+        let reset = untyped.reset();
+        slot.update(Capability::Untyped(untyped));
+        reset?;
+    }
+    Ok(())
+}
+
+/// Release what an object about to be destroyed holds, as far as that can
+/// be done while it is still reachable. Stops at preemption points.
+fn empty_object(cap: Capability) -> Result<()> {
+    match cap {
+        Capability::CNode { ptr, radix, .. } => {
+            for index in 0..1usize << radix {
+                delete(cnode::slot(ptr, index))?;
+                preemption::point()?;
+            }
+            Ok(())
+        }
+        Capability::Endpoint { ptr, .. } => Ok(endpoint::cancel_all(Endpoint::from_ptr(ptr))?),
+        Capability::Notification { ptr, .. } => {
+            Ok(notification::cancel_all(Notification::from_ptr(ptr))?)
+        }
+        Capability::Tcb { ptr } => {
+            let thread = Tcb::from_ptr(ptr);
+            // Reply capabilities held by others go first.
+            revoke(&mut thread.slots[tcb::slots::REPLY])?;
+            for slot in thread.slots.iter_mut() {
+                delete(slot)?;
+                preemption::point()?;
+            }
+            Ok(())
+        }
+        _ => Ok(()),
+    }
+}
+
+/// Release whatever the object holds once nobody can reach it anymore.
+///
+/// With the capability gone there is nothing to restart from, so this runs
+/// without preemption points. `empty_object()` already emptied the slots
+/// the object holds, so this doesn't recurse into other objects.
+fn finalize(cap: Capability) -> Result<()> {
+    preemption::disable();
+    let result = release(cap);
+    preemption::enable();
+    result
+}
+
+fn release(cap: Capability) -> Result<()> {
+    match cap {
+        Capability::Endpoint { ptr, .. } => Ok(endpoint::cancel_all(Endpoint::from_ptr(ptr))?),
+        Capability::Notification { ptr, .. } => {
+            Ok(notification::cancel_all(Notification::from_ptr(ptr))?)
+        }
+        Capability::Domain { ptr } => {
+            let domain = Domain::from_ptr(ptr);
+            domain.unbind();
+            delete(&mut domain.dcb_frame)
+        }
+        Capability::VSpace { ptr, asid } => {
+            vspace::destroy(ptr, asid);
+            Ok(())
+        }
+        Capability::Tcb { ptr } => {
+            let thread = Tcb::from_ptr(ptr);
+            endpoint::cancel_ipc(thread);
+            notification::unbind_tcb(thread);
+            domain::unbind_tcb(thread);
+            sched::remove(thread);
+            Ok(())
+        }
+        _ => Ok(()),
+    }
+}
+
+/// Retype the Untyped in `untyped` into `count` objects, placing their
+/// capabilities into consecutive slots of `dest_cnode` starting at `dest_index`.
+pub fn retype(
+    untyped: &mut CapSlot,
+    object_type: ObjectType,
+    user_size_bits: u8,
+    dest_cnode: &Capability,
+    dest_index: usize,
+    count: usize,
+) -> Result<()> {
+    let mut parent = match untyped.cap() {
+        Capability::Untyped(parent) => parent,
+        _ => return Err(CapError::InvalidCapability),
+    };
+
+    let dest_end = dest_index.checked_add(count).ok_or(CapError::RangeError)?;
+    for index in dest_index..dest_end {
+        if !cnode_slot(dest_cnode, index)?.is_empty() {
+            return Err(CapError::DeleteFirst);
+        }
+    }
+    if object_type == ObjectType::VSpace && vspace::free_asids() < count {
+        return Err(CapError::Object(ObjectError::NotEnoughMemory));
+    }
+
+    // Reuse memory of objects that are all gone.
+    if !untyped.has_children() {
+        let reset = parent.reset();
+        untyped.update(Capability::Untyped(parent));
+        reset?;
+    }
+
+    let retyped = parent.retype(object_type, user_size_bits, count)?;
+    untyped.update(Capability::Untyped(parent));
+
+    for (index, object) in (dest_index..dest_end).zip(retyped.objects()) {
+        let mut cap = Capability::for_object(object_type, object, user_size_bits, &parent);
+        if let Capability::VSpace { ptr, ref mut asid } = cap {
+            *asid = vspace::assign_asid(ptr);
+        }
+        untyped.insert_child(cnode_slot(dest_cnode, index)?, cap);
+    }
+    Ok(())
+}
@@ -0,0 +1,281 @@
+// mod caps
+
+//! Capabilities.
+//!
+//! A capability names a kernel object together with the rights to it.
+//! Capabilities are stored in slots of CNode objects and user code refers
+//! to them by CPtr, a path through a tree of CNodes, see `cspace`.
+//!
+//! Every capability except the boot ones is derived from another: copies,
+//! mints and objects retyped from Untyped become children of their source
+//! in the capability derivation tree. Revoking a capability deletes all
+//! capabilities derived from it.
+
+use core::mem;
+use objects::{untyped::Untyped, ObjectError, ObjectType};
+
+pub use vesper_user::CapRights;
+
+pub mod cspace;
+pub mod root;
+pub mod slot;
+
+#[derive(Debug, PartialEq)]
+pub enum CapError {
+    /// CPtr doesn't resolve: guard or depth mismatch, or not a CNode.
+    FailedLookup,
+    /// Capability is of the wrong type for the operation.
+    InvalidCapability,
+    /// Destination slot is occupied.
+    DeleteFirst,
+    /// Operation not allowed on this capability, e.g. badging it twice.
+    IllegalOperation,
+    /// Slot index outside of the CNode.
+    RangeError,
+    Object(ObjectError),
+}
+
+impl From<ObjectError> for CapError {
+    fn from(e: ObjectError) -> CapError {
+        CapError::Object(e)
+    }
+}
+
+pub type Result<T> = ::core::result::Result<T, CapError>;
+
+/// A capability, as stored in a CNode slot.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Capability {
+    Null,
+    Untyped(Untyped),
+    Endpoint {
+        ptr: usize,
+        badge: u64,
+        rights: CapRights,
+    },
+    Notification {
+        ptr: usize,
+        badge: u64,
+        rights: CapRights,
+    },
+    CNode {
+        ptr: usize,
+        radix: u8,
+        guard: u64,
+        guard_bits: u8,
+    },
+    Tcb {
+        ptr: usize,
+    },
+    /// Translation table for a 2MiB block, `vspace` is 0 while unmapped.
+    PageTable {
+        ptr: usize,
+        vspace: usize,
+        asid: u8,
+        block: u16,
+    },
+    /// Protection domain, see `objects::vspace`.
+    VSpace {
+        ptr: usize,
+        asid: u8,
+    },
+    /// Memory at `base`, `vspace` is where it granted access or 0.
+    Frame {
+        base: usize,
+        size_bits: u8,
+        rights: CapRights,
+        device: bool,
+        vspace: usize,
+        asid: u8,
+    },
+    Domain {
+        ptr: usize,
+    },
+    /// Right to reply to a thread blocked in a call.
+    ///
+    /// The master capability lives in the thread's TCB, one-shot reply
+    /// capabilities derived from it are handed to the receiver of a call.
+    Reply {
+        tcb: usize,
+        master: bool,
+    },
+    /// Right to route interrupt lines to notifications.
+    IrqControl,
+}
+
+impl Capability {
+    /// Capability to an object freshly retyped from `parent`.
+    pub fn for_object(
+        object_type: ObjectType,
+        ptr: usize,
+        user_size_bits: u8,
+        parent: &Untyped,
+    ) -> Capability {
+        match object_type {
+            ObjectType::Untyped => {
+                Capability::Untyped(Untyped::new(ptr, user_size_bits, parent.is_device()))
+            }
+            ObjectType::Tcb => Capability::Tcb { ptr },
+            ObjectType::Endpoint => Capability::Endpoint {
+                ptr,
+                badge: 0,
+                rights: CapRights::all(),
+            },
+            ObjectType::Notification => Capability::Notification {
+                ptr,
+                badge: 0,
+                rights: CapRights::all(),
+            },
+            ObjectType::CNode => Capability::CNode {
+                ptr,
+                radix: user_size_bits,
+                guard: 0,
+                guard_bits: 0,
+            },
+            ObjectType::PageTable => Capability::PageTable {
+                ptr,
+                vspace: 0,
+                asid: 0,
+                block: 0,
+            },
+            // The ASID is assigned by `cspace::retype()`.
+            ObjectType::VSpace => Capability::VSpace { ptr, asid: 0 },
+            ObjectType::Frame => Capability::Frame {
+                base: ptr,
+                size_bits: user_size_bits,
+                rights: CapRights::all(),
+                device: parent.is_device(),
+                vspace: 0,
+                asid: 0,
+            },
+            ObjectType::Domain => Capability::Domain { ptr },
+        }
+    }
+
+    pub fn is_null(&self) -> bool {
+        *self == Capability::Null
+    }
+
+    /// Address of the referenced object.
+    pub fn object_ptr(&self) -> Option<usize> {
+        match *self {
+            Capability::Null | Capability::IrqControl => None,
+            Capability::Untyped(ref untyped) => Some(untyped.base()),
+            Capability::Endpoint { ptr, .. }
+            | Capability::Notification { ptr, .. }
+            | Capability::CNode { ptr, .. }
+            | Capability::Tcb { ptr }
+            | Capability::PageTable { ptr, .. }
+            | Capability::VSpace { ptr, .. }
+            | Capability::Domain { ptr } => Some(ptr),
+            Capability::Frame { base, .. } => Some(base),
+            Capability::Reply { tcb, .. } => Some(tcb),
+        }
+    }
+
+    /// Whether copies of this capability can be made.
+    ///
+    /// Reply capabilities are unique, they can only be moved. So are
+    /// Untyped ones: the watermark lives in the capability, a copy would
+    /// retype or reset memory already handed out through the original.
+    pub fn is_derivable(&self) -> bool {
+        match *self {
+            Capability::Null | Capability::Reply { .. } | Capability::Untyped(_) => false,
+            _ => true,
+        }
+    }
+
+    /// Whether both capabilities refer to the same object.
+    ///
+    /// An Untyped and the first object retyped from it share the address,
+    /// so the type must match too.
+    pub fn same_object(&self, other: &Capability) -> bool {
+        mem::discriminant(self) == mem::discriminant(other)
+            && self.object_ptr().is_some()
+            && self.object_ptr() == other.object_ptr()
+    }
+
+    pub fn rights(&self) -> CapRights {
+        match *self {
+            Capability::Null => CapRights::empty(),
+            Capability::Endpoint { rights, .. }
+            | Capability::Notification { rights, .. }
+            | Capability::Frame { rights, .. } => rights,
+            _ => CapRights::all(),
+        }
+    }
+
+    /// A copy with rights reduced to `mask`.
+    ///
+    /// Only capabilities carrying rights are affected.
+    pub fn with_rights(&self, mask: CapRights) -> Capability {
+        let mut cap = *self;
+        match cap {
+            Capability::Endpoint { ref mut rights, .. }
+            | Capability::Notification { ref mut rights, .. }
+            | Capability::Frame { ref mut rights, .. } => *rights &= mask,
+            _ => {}
+        }
+        cap
+    }
+
+    /// The capability without what it mapped or granted access to, which
+    /// stays with the original when it is copied.
+    pub fn unmapped(&self) -> Capability {
+        let mut cap = *self;
+        match cap {
+            Capability::Frame {
+                ref mut vspace,
+                ref mut asid,
+                ..
+            }
+            | Capability::PageTable {
+                ref mut vspace,
+                ref mut asid,
+                ..
+            } => {
+                *vspace = 0;
+                *asid = 0;
+            }
+            _ => {}
+        }
+        cap
+    }
+
+    /// A copy with badge or guard set from `data`.
+    ///
+    /// For endpoints and notifications `data` is the badge, which can
+    /// only be set once.
+    /// For CNodes the low 6 bits are the guard size and the rest the guard.
+    pub fn with_data(&self, data: u64) -> Result<Capability> {
+        let mut cap = *self;
+        match cap {
+            Capability::Endpoint { ref mut badge, .. }
+            | Capability::Notification { ref mut badge, .. } => {
+                if *badge != 0 {
+                    return Err(CapError::IllegalOperation);
+                }
+                *badge = data;
+            }
+            Capability::CNode {
+                radix,
+                ref mut guard,
+                ref mut guard_bits,
+                ..
+            } => {
+                let bits = (data & 0x3f) as u8;
+                if u32::from(bits) + u32::from(radix) > 64 {
+                    return Err(CapError::RangeError);
+                }
+                *guard_bits = bits;
+                *guard = if bits == 0 {
+                    0
+                } else {
+                    (data >> 6) & (!0u64 >> (64 - u32::from(bits)))
+                };
+            }
+            _ => return Err(CapError::InvalidCapability),
+        }
+        Ok(cap)
+    }
+}
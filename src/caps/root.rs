@@ -0,0 +1,78 @@
+// mod caps::root
+
+//! Root CSpace handed to the first user task.
+//!
+//! A single CNode of 2^ROOT_CNODE_RADIX slots with a guard covering the
+//! remaining CPtr bits, so slot N is simply CPtr N.
+
+use arch::sync::SpinLock;
+use caps::{cspace::CPTR_BITS, slot::CapSlot, CapError, Capability, Result};
+use objects::{cnode, untyped::BOOT_UNTYPEDS, ObjectError, ObjectType, CNODE_SLOT_BITS};
+
+pub const ROOT_CNODE_RADIX: u8 = 12;
+
+/// Well-known slots of the root CNode.
+pub mod slots {
+    pub const NULL: usize = 0;
+    /// The root CNode itself.
+    pub const ROOT_CNODE: usize = 1;
+    /// Interrupt routing.
+    pub const IRQ_CONTROL: usize = 2;
+    /// Untyped capabilities start here, one per boot Untyped.
+    pub const FIRST_UNTYPED: usize = 16;
+}
+
+/// What the root task gets to start with.
+#[derive(Clone, Copy)]
+pub struct RootCSpace {
+    pub cnode: Capability,
+    /// Untyped capabilities occupy slots `untyped_slots.0..untyped_slots.1`.
+    pub untyped_slots: (usize, usize),
+}
+
+pub static ROOT_CSPACE: SpinLock<Option<RootCSpace>> = SpinLock::new(None);
+
+/// Build the root CNode out of the boot Untyped and fill it with
+/// capabilities to all of them.
+pub fn init() -> Result<RootCSpace> {
+    let untypeds = BOOT_UNTYPEDS.lock();
+    let untypeds = untypeds.as_slice();
+
+    let max_untypeds = (1 << ROOT_CNODE_RADIX) - slots::FIRST_UNTYPED;
+    let count = untypeds.len().min(max_untypeds);
+
+    // Carve the CNode out of the first Untyped large enough.
+    let host = untypeds
+        .iter()
+        .position(|u| {
+            !u.is_device() && u.free_bytes() >= 1 << (ROOT_CNODE_RADIX + CNODE_SLOT_BITS)
+        })
+        .filter(|&index| index < count)
+        .ok_or(CapError::Object(ObjectError::NotEnoughMemory))?;
+
+    let mut host_untyped = untypeds[host];
+    let retyped = host_untyped.retype(ObjectType::CNode, ROOT_CNODE_RADIX, 1)?;
+
+    let root = Capability::CNode {
+        ptr: retyped.base,
+        radix: ROOT_CNODE_RADIX,
+        guard: 0,
+        guard_bits: CPTR_BITS - ROOT_CNODE_RADIX,
+    };
+
+    let slot = |index| -> &'static mut CapSlot { cnode::slot(retyped.base, index) };
+
+    for (i, untyped) in untypeds[..count].iter().enumerate() {
+        let untyped = if i == host { host_untyped } else { *untyped };
+        slot(slots::FIRST_UNTYPED + i).insert_root(Capability::Untyped(untyped));
+    }
+    slot(slots::FIRST_UNTYPED + host).insert_child(slot(slots::ROOT_CNODE), root);
+    slot(slots::IRQ_CONTROL).insert_root(Capability::IrqControl);
+
+    let cspace = RootCSpace {
+        cnode: root,
+        untyped_slots: (slots::FIRST_UNTYPED, slots::FIRST_UNTYPED + count),
+    };
+    *ROOT_CSPACE.lock() = Some(cspace);
+    Ok(cspace)
+}
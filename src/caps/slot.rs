@@ -0,0 +1,200 @@
+// mod caps::slot
+
+//! Capability slots and the derivation tree.
+//!
+//! Each slot links to its parent, first child and siblings, so derived
+//! capabilities form a tree spanning all CNodes. Deleting a capability
+//! hands its children over to its parent, revoking deletes them.
+
+use caps::Capability;
+use core::ptr;
+
+/// One entry of a CNode, 2^CNODE_SLOT_BITS bytes.
+#[repr(C)]
+pub struct CapSlot {
+    cap: Capability,
+    parent: *mut CapSlot,
+    first_child: *mut CapSlot,
+    next_sibling: *mut CapSlot,
+    prev_sibling: *mut CapSlot,
+}
+
+impl CapSlot {
+    pub const fn empty() -> CapSlot {
+        CapSlot {
+            cap: Capability::Null,
+            parent: ptr::null_mut(),
+            first_child: ptr::null_mut(),
+            next_sibling: ptr::null_mut(),
+            prev_sibling: ptr::null_mut(),
+        }
+    }
+
+    pub fn cap(&self) -> Capability {
+        self.cap
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.cap.is_null()
+    }
+
+    /// Replace the capability in place, keeping its position in the tree.
+    ///
+    /// Only for updates that keep referring to the same object,
+    /// e.g. the watermark of an Untyped.
+    pub fn update(&mut self, cap: Capability) {
+        debug_assert!(self.cap.same_object(&cap));
+        self.cap = cap;
+    }
+
+    // Slots live in CNode objects, which are never moved or freed
+    // while a capability to them exists.
+
+    pub fn parent(&self) -> Option<&'static mut CapSlot> {
+        unsafe { self.parent.as_mut() }
+    }
+
+    pub fn first_child(&self) -> Option<&'static mut CapSlot> {
+        unsafe { self.first_child.as_mut() }
+    }
+
+    pub fn has_children(&self) -> bool {
+        !self.first_child.is_null()
+    }
+
+    /// Direct descendants in the derivation tree.
+    pub fn children(&self) -> Children {
+        Children {
+            next: self.first_child,
+        }
+    }
+
+    /// Put `cap` into this empty slot without a parent, used at boot.
+    pub fn insert_root(&mut self, cap: Capability) {
+        debug_assert!(self.is_empty());
+        self.cap = cap;
+        self.parent = ptr::null_mut();
+        self.first_child = ptr::null_mut();
+        self.next_sibling = ptr::null_mut();
+        self.prev_sibling = ptr::null_mut();
+    }
+
+    /// Put `cap` into the empty slot `child` as a descendant of this one.
+    pub fn insert_child(&mut self, child: &mut CapSlot, cap: Capability) {
+        debug_assert!(child.is_empty());
+        child.cap = cap;
+        child.first_child = ptr::null_mut();
+        child.parent = self;
+        child.prev_sibling = ptr::null_mut();
+        child.next_sibling = self.first_child;
+        if let Some(next) = unsafe { self.first_child.as_mut() } {
+            next.prev_sibling = child;
+        }
+        self.first_child = child;
+    }
+
+    /// Take the capability out of the tree and empty the slot.
+    ///
+    /// Children move up to this slot's parent.
+    pub fn remove(&mut self) -> Capability {
+        let cap = self.cap;
+
+        self.unlink_siblings();
+
+        let mut child = self.first_child;
+        while let Some(slot) = unsafe { child.as_mut() } {
+            child = slot.next_sibling;
+            slot.parent = ptr::null_mut();
+            slot.next_sibling = ptr::null_mut();
+            slot.prev_sibling = ptr::null_mut();
+            if let Some(parent) = unsafe { self.parent.as_mut() } {
+                parent.insert_linked(slot);
+            }
+        }
+
+        *self = CapSlot::empty();
+        cap
+    }
+
+    /// Move the capability and its place in the tree to the empty slot `dest`.
+    pub fn move_to(&mut self, dest: &mut CapSlot) {
+        debug_assert!(dest.is_empty());
+        dest.cap = self.cap;
+        dest.parent = self.parent;
+        dest.first_child = self.first_child;
+        dest.next_sibling = self.next_sibling;
+        dest.prev_sibling = self.prev_sibling;
+
+        match unsafe { self.prev_sibling.as_mut() } {
+            Some(prev) => prev.next_sibling = dest,
+            None => {
+                if let Some(parent) = unsafe { self.parent.as_mut() } {
+                    parent.first_child = dest;
+                }
+            }
+        }
+        if let Some(next) = unsafe { self.next_sibling.as_mut() } {
+            next.prev_sibling = dest;
+        }
+        let mut child = dest.first_child;
+        while let Some(slot) = unsafe { child.as_mut() } {
+            slot.parent = dest;
+            child = slot.next_sibling;
+        }
+
+        *self = CapSlot::empty();
+    }
+
+    /// Whether no other capability in the tree refers to the same object.
+    ///
+    /// Copies of a capability are always its parent, siblings or children.
+    pub fn is_final(&self) -> bool {
+        let same = |slot: &CapSlot| slot.cap.same_object(&self.cap);
+
+        if let Some(parent) = self.parent() {
+            if same(parent) || parent.children().any(|s| !ptr::eq(s, self) && same(s)) {
+                return false;
+            }
+        }
+        !self.children().any(|s| same(s))
+    }
+
+    /// Link an already filled slot as a child of this one.
+    fn insert_linked(&mut self, child: &mut CapSlot) {
+        child.parent = self;
+        child.prev_sibling = ptr::null_mut();
+        child.next_sibling = self.first_child;
+        if let Some(next) = unsafe { self.first_child.as_mut() } {
+            next.prev_sibling = child;
+        }
+        self.first_child = child;
+    }
+
+    fn unlink_siblings(&mut self) {
+        match unsafe { self.prev_sibling.as_mut() } {
+            Some(prev) => prev.next_sibling = self.next_sibling,
+            None => {
+                if let Some(parent) = unsafe { self.parent.as_mut() } {
+                    parent.first_child = self.next_sibling;
+                }
+            }
+        }
+        if let Some(next) = unsafe { self.next_sibling.as_mut() } {
+            next.prev_sibling = self.prev_sibling;
+        }
+    }
+}
+
+pub struct Children {
+    next: *mut CapSlot,
+}
+
+impl Iterator for Children {
+    type Item = &'static mut CapSlot;
+
+    fn next(&mut self) -> Option<&'static mut CapSlot> {
+        let slot = unsafe { self.next.as_mut()? };
+        self.next = slot.next_sibling;
+        Some(slot)
+    }
+}
@@ -0,0 +1,152 @@
+// mod ipc
+
+//! Message transfer between threads.
+//!
+//! The message format and IPC buffer layout are part of the ABI and
+//! defined in vesper-user, see `vesper_user::message`.
+
+use caps::{
+    cspace::{self, CPTR_BITS},
+    slot::CapSlot,
+    Capability,
+};
+use core::cmp;
+use objects::tcb::{slots, Tcb};
+
+pub use vesper_user::message::{
+    IpcBuffer, MessageInfo, BADGE_REGISTER, FIRST_MSG_REGISTER, INFO_REGISTER, MSG_MAX_EXTRA_CAPS,
+    MSG_MAX_LENGTH, MSG_REGISTERS,
+};
+
+/// IPC buffer of the thread, gone with the frame it lives in.
+fn ipc_buffer(tcb: &Tcb) -> Option<&'static mut IpcBuffer> {
+    match tcb.slots[slots::IPC_BUFFER].cap() {
+        Capability::Frame { .. } => unsafe { (tcb.ipc_buffer as *mut IpcBuffer).as_mut() },
+        _ => None,
+    }
+}
+
+/// Read message register `index` of a thread about to send.
+pub fn get_mr(tcb: &Tcb, index: usize) -> Option<u64> {
+    if index < MSG_REGISTERS {
+        Some(tcb.context.gpr[FIRST_MSG_REGISTER + index])
+    } else {
+        ipc_buffer(tcb).map(|buffer| buffer.msg[index])
+    }
+}
+
+/// Write message register `index` of a thread receiving a message.
+///
+/// Returns false if the register doesn't exist for lack of an IPC buffer.
+pub fn set_mr(tcb: &mut Tcb, index: usize, value: u64) -> bool {
+    if index < MSG_REGISTERS {
+        tcb.context.gpr[FIRST_MSG_REGISTER + index] = value;
+        true
+    } else {
+        match ipc_buffer(tcb) {
+            Some(buffer) => {
+                buffer.msg[index] = value;
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+/// Message descriptor a thread is sending with.
+pub fn message_info(tcb: &Tcb) -> MessageInfo {
+    MessageInfo::from_raw(tcb.context.gpr[INFO_REGISTER])
+}
+
+/// Deliver a message without capabilities, e.g. a kernel reply.
+pub fn set_message(tcb: &mut Tcb, badge: u64, info: MessageInfo) {
+    tcb.context.gpr[BADGE_REGISTER] = badge;
+    tcb.context.gpr[INFO_REGISTER] = info.raw();
+}
+
+/// Copy the message of `sender` to `receiver`.
+///
+/// `endpoint` is the endpoint the message travels through, capabilities
+/// to it are unwrapped into their badges instead of being transferred.
+/// Capabilities are only transferred if the sender's capability
+/// carries the grant right.
+pub fn transfer(sender: &Tcb, receiver: &mut Tcb, endpoint: usize, badge: u64, can_grant: bool) {
+    let info = message_info(sender);
+
+    let mut length = info.length();
+    for index in 0..length {
+        let value = match get_mr(sender, index) {
+            Some(value) => value,
+            None => {
+                length = index;
+                break;
+            }
+        };
+        if !set_mr(receiver, index, value) {
+            length = index;
+            break;
+        }
+    }
+
+    let (extra_caps, caps_unwrapped) = if can_grant && info.extra_caps() > 0 {
+        transfer_caps(sender, receiver, endpoint, info.extra_caps())
+    } else {
+        (0, 0)
+    };
+
+    set_message(
+        receiver,
+        badge,
+        MessageInfo::new(info.label(), caps_unwrapped, extra_caps, length),
+    );
+}
+
+/// Capabilities received in a single slot, only the first one that isn't
+/// unwrapped gets delivered and stops the transfer.
+/// Capabilities that can't be copied, e.g. Untyped ones, stop it as well.
+fn transfer_caps(sender: &Tcb, receiver: &Tcb, endpoint: usize, count: usize) -> (usize, u8) {
+    let (send_buffer, receive_buffer) = match (ipc_buffer(sender), ipc_buffer(receiver)) {
+        (Some(send), Some(receive)) => (send, receive),
+        _ => return (0, 0),
+    };
+
+    let mut dest = receive_slot(receiver, receive_buffer);
+    let mut unwrapped = 0u8;
+    let mut transferred = 0;
+
+    for i in 0..count {
+        let cptr = send_buffer.caps_or_badges[i];
+        let src = match cspace::resolve(&sender.cspace_root(), cptr, CPTR_BITS) {
+            Ok(resolved) => resolved.slot,
+            Err(_) => break,
+        };
+
+        match src.cap() {
+            Capability::Endpoint { ptr, badge, .. } if ptr == endpoint => {
+                receive_buffer.caps_or_badges[i] = badge;
+                unwrapped |= 1 << i;
+            }
+            cap => {
+                let dest = match dest.take() {
+                    Some(dest) => dest,
+                    None => break,
+                };
+                if !cap.is_derivable() {
+                    break;
+                }
+                src.insert_child(dest, cap.unmapped());
+            }
+        }
+        transferred += 1;
+    }
+    (transferred, unwrapped)
+}
+
+/// Empty slot named by the receiver's IPC buffer, if any.
+fn receive_slot(receiver: &Tcb, buffer: &IpcBuffer) -> Option<&'static mut CapSlot> {
+    let cnode = cspace::lookup_cap(&receiver.cspace_root(), buffer.receive_cnode).ok()?;
+    let depth = cmp::min(buffer.receive_depth, u64::from(CPTR_BITS)) as u8;
+    cspace::lookup_slot(&cnode, buffer.receive_index, depth)
+        .ok()
+        .filter(|slot| slot.is_empty())
+}
@@ -2,6 +2,7 @@
 #![no_main]
 #![feature(asm)]
 #![feature(const_fn)]
+#![feature(global_asm)]
 #![feature(lang_items)]
 #![feature(ptr_internals)] // until we mark with PhantomData instead?
 #![feature(core_intrinsics)]
@@ -15,31 +16,37 @@ use architecture_not_supported_sorry;
 
 // use core::intrinsics::abort;
 
-#[macro_use]
-extern crate bitflags;
 #[macro_use]
 extern crate register;
 extern crate cortex_a;
 extern crate rlibc;
+extern crate vesper_user;
 
 use core::panic::PanicInfo;
 #[macro_use]
 pub mod arch;
 pub use arch::*;
+pub mod caps;
+pub mod ipc;
+pub mod mm;
+pub mod objects;
 pub mod platform;
+pub mod sched;
+pub mod syscall;
 
 use core::fmt::Write;
 use platform::{
+    devicetree,
     display::{Color, Size2d},
-    uart::MiniUart,
+    irq,
+    rpi3::BcmHost,
+    uart::{MiniUart, CONSOLE},
     vc::VC,
 };
 
-// User-facing kernel parts - syscalls and capability invocations.
-// pub mod vesper; -- no mod exported, because available through syscall interface
-
-// Actual interfaces to call these syscalls are in vesper-user (similar to libsel4)
-// pub mod vesper; -- exported from vesper-user
+// User-facing kernel parts - syscalls and capability invocations are in `syscall`.
+// Actual interfaces to call these syscalls are in vesper-user (similar to libsel4),
+// which also defines the ABI types shared with the kernel.
 
 #[panic_handler]
 fn panic(_info: &PanicInfo) -> ! {
@@ -50,11 +57,48 @@ fn panic(_info: &PanicInfo) -> ! {
 // Kernel entry point
 // arch crate is responsible for calling this
 pub fn kmain() -> ! {
+    let dt = unsafe { devicetree::init(boot::dtb_address()) };
+    BcmHost::probe();
+
     let mut uart = MiniUart::new();
     uart.init();
     writeln!(uart, "Hey there, mini uart talking!");
 
-    if let Some(mut display) = VC::init_fb(Size2d { x: 800, y: 600 }, &mut uart) {
+    match dt {
+        Ok(dt) => {
+            for region in dt.memory() {
+                writeln!(uart, "Memory {:#x}-{:#x}", region.address, region.end());
+            }
+            if let Some(args) = dt.bootargs() {
+                writeln!(uart, "Command line: {}", args);
+            }
+        }
+        Err(e) => {
+            writeln!(uart, "No device tree, using defaults: {:?}", e);
+        }
+    }
+
+    match mm::init() {
+        Ok(free) => writeln!(uart, "{} KiB of physical memory free", free / 1024),
+        Err(e) => writeln!(uart, "Failed to build physical memory map: {:?}", e),
+    };
+    // From now on other cores may print, use CONSOLE
+
+    irq::init();
+    sched::init_per_core();
+    enable_irqs();
+
+    match smp::start_secondary_cpus(smp::EnableMethod::SpinTable) {
+        Ok(count) => writeln!(CONSOLE.lock_irqsave(), "{} cores online", count),
+        Err(e) => writeln!(CONSOLE.lock_irqsave(), "Failed to start secondary cores: {:?}", e),
+    };
+
+    let display = VC::init_fb(Size2d { x: 800, y: 600 }, &mut CONSOLE.lock_irqsave());
+    if let Some(mut display) = display {
+        mm::FRAMES
+            .lock()
+            .reserve(mm::Region::new(display.address(), display.size()));
+
         display.rect(10, 10, 250, 250, Color::rgb(32, 96, 64).0);
         display.draw_text(50, 50, "Hello there!", Color::rgb(128, 192, 255).0);
         // display.draw_text(50, 150, core::fmt("Display width {}", display.width), Color::rgb(255,0,0).0);
@@ -62,12 +106,41 @@ pub fn kmain() -> ! {
         display.draw_text(150, 50, "RED", Color::rgb(255, 0, 0).0);
         display.draw_text(160, 60, "GREEN", Color::rgb(0, 255, 0).0);
         display.draw_text(170, 70, "BLUE", Color::rgb(0, 0, 255).0);
+        display.flush();
     }
 
-    writeln!(uart, "Bye, going to sleep now");
+    // From here on all memory belongs to user space.
+    let count = objects::untyped::init_boot_untypeds();
+    writeln!(CONSOLE.lock_irqsave(), "{} untyped regions for the root task", count);
+
+    match caps::root::init() {
+        Ok(root) => writeln!(
+            CONSOLE.lock_irqsave(),
+            "Root CNode at {:#x}, untyped in slots {}..{}",
+            root.cnode.object_ptr().unwrap_or(0),
+            root.untyped_slots.0,
+            root.untyped_slots.1
+        ),
+        Err(e) => writeln!(CONSOLE.lock_irqsave(), "Failed to create root CSpace: {:?}", e),
+    };
+
+    writeln!(CONSOLE.lock_irqsave(), "Bye, going to sleep now");
     qemu_aarch64_exit(); //endless_sleep()
 }
 
+// Secondary cores entry point
+// arch crate is responsible for calling this
+pub fn kmain_secondary() -> ! {
+    irq::init_per_core();
+    sched::init_per_core();
+    enable_irqs();
+
+    writeln!(CONSOLE.lock_irqsave(), "Core {} online", read_cpu_id());
+
+    // @todo pick up work once there is a scheduler
+    endless_sleep()
+}
+
 // From https://stackoverflow.com/a/49930361/895245
 // @todo specify exit value depending on tests result?
 fn qemu_aarch64_exit() -> ! {
@@ -0,0 +1,308 @@
+// mod mm::frames
+
+//! Physical frame allocator.
+//!
+//! Keeps a sorted list of free physical ranges. Allocations carve aligned
+//! chunks out of a range, the alignment slack stays in the list and is
+//! available for smaller allocations. Freed frames are merged back with
+//! their neighbours.
+
+use arch::mmu::{BLOCK_SIZE, PAGE_SIZE};
+
+/// Enough for RAM with a handful of holes and reservations.
+const MAX_REGIONS: usize = 32;
+
+#[derive(Debug)]
+pub enum FrameError {
+    /// Neither firmware nor device tree told us where RAM is.
+    NoMemoryMap,
+    /// The free list is too fragmented to track another hole.
+    TooManyRegions,
+    OutOfMemory,
+}
+
+pub type Result<T> = ::core::result::Result<T, FrameError>;
+
+/// Physical address range `start..end`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Region {
+    pub start: usize,
+    pub end: usize,
+}
+
+impl Region {
+    const EMPTY: Region = Region { start: 0, end: 0 };
+
+    pub fn new(start: usize, size: usize) -> Region {
+        Region {
+            start,
+            end: start + size,
+        }
+    }
+
+    pub fn size(&self) -> usize {
+        self.end - self.start
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.end <= self.start
+    }
+
+    fn overlaps(&self, other: &Region) -> bool {
+        self.start < other.end && other.start < self.end
+    }
+}
+
+pub struct FrameAllocator {
+    /// Free ranges sorted by address, non-overlapping and non-adjacent.
+    regions: [Region; MAX_REGIONS],
+    count: usize,
+}
+
+#[inline]
+fn align_up(address: usize, align: usize) -> usize {
+    (address + align - 1) & !(align - 1)
+}
+
+impl FrameAllocator {
+    pub const fn new() -> FrameAllocator {
+        FrameAllocator {
+            regions: [Region::EMPTY; MAX_REGIONS],
+            count: 0,
+        }
+    }
+
+    /// Free ranges, sorted by address.
+    pub fn regions(&self) -> &[Region] {
+        &self.regions[..self.count]
+    }
+
+    /// Total free memory in bytes.
+    pub fn free_bytes(&self) -> usize {
+        self.regions().iter().map(Region::size).sum()
+    }
+
+    fn insert(&mut self, index: usize, region: Region) -> Result<()> {
+        if self.count == MAX_REGIONS {
+            return Err(FrameError::TooManyRegions);
+        }
+        for i in (index..self.count).rev() {
+            self.regions[i + 1] = self.regions[i];
+        }
+        self.regions[index] = region;
+        self.count += 1;
+        Ok(())
+    }
+
+    fn remove(&mut self, index: usize) {
+        for i in index..self.count - 1 {
+            self.regions[i] = self.regions[i + 1];
+        }
+        self.count -= 1;
+    }
+
+    /// Add free memory, merging it with overlapping or adjacent ranges.
+    pub fn add(&mut self, region: Region) -> Result<()> {
+        if region.is_empty() {
+            return Ok(());
+        }
+
+        let index = self
+            .regions()
+            .iter()
+            .position(|r| r.start > region.start)
+            .unwrap_or(self.count);
+        self.insert(index, region)?;
+
+        // Coalesce with the previous range, then swallow following ones.
+        let mut index = index;
+        if index > 0 && self.regions[index - 1].end >= region.start {
+            index -= 1;
+            self.regions[index].end = self.regions[index].end.max(region.end);
+            self.remove(index + 1);
+        }
+        while index + 1 < self.count && self.regions[index + 1].start <= self.regions[index].end {
+            self.regions[index].end = self.regions[index].end.max(self.regions[index + 1].end);
+            self.remove(index + 1);
+        }
+        Ok(())
+    }
+
+    /// Remove `hole` from the free range at `index`, which must contain it.
+    fn cut(&mut self, index: usize, hole: Region) -> Result<()> {
+        let region = self.regions[index];
+        let below = Region {
+            start: region.start,
+            end: hole.start,
+        };
+        let above = Region {
+            start: hole.end,
+            end: region.end,
+        };
+
+        match (below.is_empty(), above.is_empty()) {
+            (true, true) => self.remove(index),
+            (false, true) => self.regions[index] = below,
+            (true, false) => self.regions[index] = above,
+            (false, false) => {
+                self.insert(index + 1, above)?;
+                self.regions[index] = below;
+            }
+        }
+        Ok(())
+    }
+
+    /// Take a range out of the free list, e.g. memory used by the kernel
+    /// image or firmware. Parts of it which aren't free are ignored.
+    pub fn reserve(&mut self, reserved: Region) -> Result<()> {
+        let mut index = 0;
+        while index < self.count {
+            let region = self.regions[index];
+            if !region.overlaps(&reserved) {
+                index += 1;
+                continue;
+            }
+            let hole = Region {
+                start: region.start.max(reserved.start),
+                end: region.end.min(reserved.end),
+            };
+            let count = self.count;
+            self.cut(index, hole)?;
+            // Skip the part below the hole if it stayed in the list.
+            if self.count >= count {
+                index += 1;
+            }
+        }
+        Ok(())
+    }
+
+    /// Allocate `size` bytes aligned to `align`, which must be a power of two.
+    ///
+    /// Memory contents are undefined.
+    pub fn alloc(&mut self, size: usize, align: usize) -> Result<usize> {
+        for index in 0..self.count {
+            let region = self.regions[index];
+            let start = align_up(region.start, align);
+            if start < region.end && region.end - start >= size {
+                self.cut(index, Region::new(start, size))?;
+                return Ok(start);
+            }
+        }
+        Err(FrameError::OutOfMemory)
+    }
+
+    /// Allocate a 4KiB page frame.
+    pub fn alloc_page(&mut self) -> Result<usize> {
+        self.alloc(PAGE_SIZE, PAGE_SIZE)
+    }
+
+    /// Allocate a 2MiB block frame.
+    pub fn alloc_block(&mut self) -> Result<usize> {
+        self.alloc(BLOCK_SIZE, BLOCK_SIZE)
+    }
+
+    /// Return memory obtained from `alloc()`.
+    pub fn free(&mut self, address: usize, size: usize) -> Result<()> {
+        self.add(Region::new(address, size))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn region(start: usize, end: usize) -> Region {
+        Region { start, end }
+    }
+
+    fn allocator(regions: &[Region]) -> FrameAllocator {
+        let mut frames = FrameAllocator::new();
+        for &region in regions {
+            frames.add(region).unwrap();
+        }
+        frames
+    }
+
+    #[test]
+    fn add_sorts_and_merges() {
+        let frames = allocator(&[
+            region(0x5000, 0x6000),
+            region(0x1000, 0x2000),
+            // Adjacent to the first, overlapping the second.
+            region(0x2000, 0x5800),
+            region(0x8000, 0x9000),
+        ]);
+        assert_eq!(
+            frames.regions(),
+            &[region(0x1000, 0x6000), region(0x8000, 0x9000)]
+        );
+        assert_eq!(frames.free_bytes(), 0x6000);
+    }
+
+    #[test]
+    fn reserve_splits_region() {
+        let mut frames = allocator(&[region(0x0, 0x10000)]);
+        frames.reserve(region(0x4000, 0x6000)).unwrap();
+        assert_eq!(
+            frames.regions(),
+            &[region(0x0, 0x4000), region(0x6000, 0x10000)]
+        );
+    }
+
+    #[test]
+    fn reserve_spans_regions_and_ignores_used_memory() {
+        let mut frames = allocator(&[
+            region(0x1000, 0x3000),
+            region(0x4000, 0x6000),
+            region(0x7000, 0x9000),
+        ]);
+        frames.reserve(region(0x2000, 0x8000)).unwrap();
+        assert_eq!(
+            frames.regions(),
+            &[region(0x1000, 0x2000), region(0x8000, 0x9000)]
+        );
+
+        // Nothing free there any more.
+        frames.reserve(region(0x3000, 0x7000)).unwrap();
+        assert_eq!(frames.free_bytes(), 0x2000);
+
+        frames.reserve(region(0x0, 0x10000)).unwrap();
+        assert!(frames.regions().is_empty());
+    }
+
+    #[test]
+    fn alloc_aligns_and_keeps_slack() {
+        let mut frames = allocator(&[region(0x1000, 0x50_0000)]);
+        let block = frames.alloc_block().unwrap();
+        assert_eq!(block, BLOCK_SIZE);
+        assert_eq!(
+            frames.regions(),
+            &[
+                region(0x1000, BLOCK_SIZE),
+                region(2 * BLOCK_SIZE, 0x50_0000),
+            ]
+        );
+
+        // The slack below the block serves smaller allocations.
+        assert_eq!(frames.alloc_page().unwrap(), 0x1000);
+        assert!(frames.alloc_block().is_err());
+
+        frames.free(block, BLOCK_SIZE).unwrap();
+        frames.free(0x1000, PAGE_SIZE).unwrap();
+        assert_eq!(frames.regions(), &[region(0x1000, 0x50_0000)]);
+    }
+
+    #[test]
+    fn too_many_holes() {
+        let mut frames = allocator(&[region(0x0, 0x100_0000)]);
+        for i in 0..MAX_REGIONS - 1 {
+            let start = (2 * i + 1) * PAGE_SIZE;
+            frames.reserve(Region::new(start, PAGE_SIZE)).unwrap();
+        }
+        assert_eq!(frames.regions().len(), MAX_REGIONS);
+        match frames.reserve(Region::new(0x80_0000, PAGE_SIZE)) {
+            Err(FrameError::TooManyRegions) => {}
+            other => panic!("expected TooManyRegions, got {:?}", other),
+        }
+    }
+}
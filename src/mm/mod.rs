@@ -0,0 +1,62 @@
+// mod mm
+
+//! Physical memory management.
+
+use arch::sync::SpinLock;
+use platform::{devicetree, vc::VC};
+
+pub mod frames;
+
+pub use self::frames::{FrameAllocator, FrameError, Region, Result};
+
+/// Free physical memory, shared by all cores.
+///
+/// Not used from interrupt handlers, so `lock()` is enough.
+pub static FRAMES: SpinLock<FrameAllocator> = SpinLock::new(FrameAllocator::new());
+
+/// End of the kernel image including .bss and boot stacks.
+fn kernel_end() -> usize {
+    extern "C" {
+        static __boot_stacks_end: u8;
+    }
+    unsafe { &__boot_stacks_end as *const _ as usize }
+}
+
+/// Build the free frame map from the firmware's ARM memory range,
+/// or the device tree memory nodes if the firmware doesn't answer.
+///
+/// Everything from address 0 to the end of the kernel image is taken out:
+/// firmware spin tables, the kernel image, .bss with page tables and the
+/// boot stacks, which also hold all mailbox buffers. So are the device
+/// tree blob and its reserved ranges.
+///
+/// Returns the number of free bytes.
+pub fn init() -> Result<usize> {
+    let mut frames = FRAMES.lock();
+
+    if let Some((base, size)) = VC::get_arm_memory() {
+        frames.add(Region::new(base as usize, size as usize))?;
+    } else if let Some(dt) = devicetree::get() {
+        for region in dt.memory() {
+            frames.add(Region::new(region.address as usize, region.size as usize))?;
+        }
+    }
+
+    if frames.free_bytes() == 0 {
+        return Err(FrameError::NoMemoryMap);
+    }
+
+    frames.reserve(Region {
+        start: 0,
+        end: kernel_end(),
+    })?;
+
+    if let Some(dt) = devicetree::get() {
+        frames.reserve(Region::new(dt.address(), dt.size()))?;
+        for region in dt.reserved() {
+            frames.reserve(Region::new(region.address as usize, region.size as usize))?;
+        }
+    }
+
+    Ok(frames.free_bytes())
+}
@@ -0,0 +1,46 @@
+// mod objects::cnode
+
+//! CNode objects: arrays of 2^radix capability slots.
+
+use caps::slot::CapSlot;
+use core::{mem, ptr};
+use objects::CNODE_SLOT_BITS;
+
+/// Fails to compile if a slot outgrows its stride in a CNode, which would
+/// make slots overlap.
+#[allow(dead_code)]
+const SLOT_FITS: [(); 0 - !(mem::size_of::<CapSlot>() <= 1 << CNODE_SLOT_BITS) as usize] = [];
+
+/// Fill a freshly retyped CNode with empty slots.
+///
+/// Retyped memory is zero, so this is only needed if empty slots aren't.
+pub fn init(ptr: usize, radix: u8) {
+    if empty_slot_is_zero() {
+        return;
+    }
+    for index in 0..1usize << radix {
+        unsafe {
+            ptr::write(slot_ptr(ptr, index), CapSlot::empty());
+        }
+    }
+}
+
+/// Whether an empty slot is all zeroes, which depends on the enum layout
+/// the compiler picks for capabilities.
+fn empty_slot_is_zero() -> bool {
+    let mut words = [0u64; (1usize << CNODE_SLOT_BITS) / 8];
+    unsafe {
+        ptr::write(words.as_mut_ptr() as *mut CapSlot, CapSlot::empty());
+    }
+    words.iter().all(|&word| word == 0)
+}
+
+#[inline]
+fn slot_ptr(cnode: usize, index: usize) -> *mut CapSlot {
+    (cnode + (index << CNODE_SLOT_BITS)) as *mut CapSlot
+}
+
+/// Slot `index` of the CNode at `cnode`, which must be in range.
+pub fn slot(cnode: usize, index: usize) -> &'static mut CapSlot {
+    unsafe { &mut *slot_ptr(cnode, index) }
+}
@@ -0,0 +1,232 @@
+// mod objects::domain
+
+//! Scheduling domains.
+//!
+//! A domain is run by one thread, its virtual processor, and picks its own
+//! user-level threads upon activation upcalls, see `vesper_user::domain`
+//! for the protocol. The kernel upcalls a domain when its thread gets the
+//! CPU back after preemption and the DCB has activations enabled.
+//!
+//! A domain may hold a contract reserving `slice` of CPU time in every
+//! `period`, as in Nemesis' Atropos scheduler. Within the period its
+//! thread runs ahead of threads without a reservation, domains with earlier
+//! deadlines first. Once the slice is used up the domain waits for the next
+//! period, unless the contract allows it extra time, which it then competes
+//! for like threads without a reservation. Admission control, keeping
+//! reservations below what the CPUs can deliver, is up to user space.
+//!
+//! The DCB lives in a frame the domain holds a capability to, so it goes
+//! away with the frame.
+//!
+//! An all-zero domain is unconfigured and unbound, only its frame slot
+//! needs initialization.
+
+use caps::{slot::CapSlot, CapError, Capability, Result};
+use core::{mem, ptr};
+use objects::{notification, tcb::Tcb};
+use vesper_user::domain::{ActivationReason, DomainControlBlock, SavedContext};
+
+/// Domain object, 2^DOMAIN_SIZE_BITS bytes.
+#[repr(C)]
+pub struct Domain {
+    /// Thread running the domain, null if unbound.
+    vcpu: *mut Tcb,
+    /// Kernel address of the domain control block, 0 until configured.
+    ///
+    /// Only valid while the frame in `dcb_frame` holding it is there.
+    dcb: usize,
+    /// Frame holding the domain control block.
+    pub dcb_frame: CapSlot,
+    /// Activation handler entry point.
+    entry: u64,
+    /// Stack pointer the activation handler starts with.
+    stack: u64,
+
+    /// Reservation in counter ticks, no reservation if `slice` is 0.
+    period: u64,
+    slice: u64,
+    /// May run beyond the slice when the CPU is otherwise unused.
+    extra: bool,
+    /// End of the current period.
+    deadline: u64,
+    /// Reserved time left in the current period.
+    remaining: u64,
+    /// CPU time used in total.
+    consumed: u64,
+}
+
+/// Bring a freshly zeroed domain into its initial state.
+pub fn init(ptr: usize) {
+    Domain::from_ptr(ptr).dcb_frame = CapSlot::empty();
+}
+
+impl Domain {
+    pub fn from_ptr(ptr: usize) -> &'static mut Domain {
+        unsafe { &mut *(ptr as *mut Domain) }
+    }
+
+    pub fn as_ptr(&self) -> usize {
+        self as *const Domain as usize
+    }
+
+    pub fn vcpu(&self) -> Option<&'static mut Tcb> {
+        unsafe { self.vcpu.as_mut() }
+    }
+
+    /// Set the DCB and the activation handler, the caller places the
+    /// frame holding the DCB into `dcb_frame`.
+    ///
+    /// The DCB must be aligned to its size rounded up to a power of two,
+    /// so it doesn't straddle pages.
+    pub fn configure(&mut self, dcb: usize, entry: u64, stack: u64) -> Result<()> {
+        let align = mem::size_of::<DomainControlBlock>().next_power_of_two();
+        if dcb == 0 || dcb % align != 0 {
+            return Err(CapError::RangeError);
+        }
+        self.dcb = dcb;
+        self.entry = entry;
+        self.stack = stack;
+        Ok(())
+    }
+
+    /// Make `thread` the domain's virtual processor, each may only have one binding.
+    pub fn bind(&mut self, thread: &mut Tcb) -> Result<()> {
+        if !self.vcpu.is_null() || thread.domain != 0 {
+            return Err(CapError::IllegalOperation);
+        }
+        self.vcpu = thread;
+        thread.domain = self.as_ptr();
+        Ok(())
+    }
+
+    pub fn unbind(&mut self) {
+        if let Some(thread) = self.vcpu() {
+            thread.domain = 0;
+            thread.preempted = false;
+        }
+        self.vcpu = ptr::null_mut();
+    }
+
+    /// Reserve `slice` of every `period` from `now` on, a zero slice
+    /// cancels the reservation.
+    pub fn set_contract(&mut self, period: u64, slice: u64, extra: bool, now: u64) -> Result<()> {
+        if slice > period || (slice != 0 && period == 0) {
+            return Err(CapError::RangeError);
+        }
+        self.period = period;
+        self.slice = slice;
+        self.extra = extra;
+        self.deadline = now + period;
+        self.remaining = slice;
+        Ok(())
+    }
+
+    /// Period, slice and extra time flag of the contract.
+    pub fn contract(&self) -> (u64, u64, bool) {
+        (self.period, self.slice, self.extra)
+    }
+
+    pub fn has_reservation(&self) -> bool {
+        self.slice != 0
+    }
+
+    pub fn may_run_extra(&self) -> bool {
+        self.extra
+    }
+
+    pub fn deadline(&self) -> u64 {
+        self.deadline
+    }
+
+    pub fn remaining(&self) -> u64 {
+        self.remaining
+    }
+
+    pub fn consumed(&self) -> u64 {
+        self.consumed
+    }
+
+    /// Start a new period if the current one is over.
+    pub fn replenish(&mut self, now: u64) {
+        if !self.has_reservation() || now < self.deadline {
+            return;
+        }
+        // Keep periods aligned unless a whole one was missed.
+        self.deadline += self.period;
+        if self.deadline <= now {
+            self.deadline = now + self.period;
+        }
+        self.remaining = self.slice;
+    }
+
+    /// Account `elapsed` ticks of CPU time used by the domain.
+    pub fn charge(&mut self, elapsed: u64) {
+        self.consumed += elapsed;
+        self.remaining = self.remaining.saturating_sub(elapsed);
+    }
+
+    fn dcb(&self) -> Option<&'static mut DomainControlBlock> {
+        match self.dcb_frame.cap() {
+            Capability::Frame { .. } => unsafe { (self.dcb as *mut DomainControlBlock).as_mut() },
+            _ => None,
+        }
+    }
+}
+
+/// Domain run by `thread`, if any.
+pub fn of(thread: &Tcb) -> Option<&'static mut Domain> {
+    match thread.domain {
+        0 => None,
+        ptr => Some(Domain::from_ptr(ptr)),
+    }
+}
+
+/// Unbind the domain of a thread being destroyed.
+pub fn unbind_tcb(thread: &mut Tcb) {
+    if thread.domain != 0 {
+        Domain::from_ptr(thread.domain).unbind();
+    }
+}
+
+/// Redirect `thread`, about to get the CPU back, to its domain's activation
+/// handler if it was preempted and the domain has activations enabled.
+pub fn upcall_if_preempted(thread: &mut Tcb) {
+    if !thread.preempted {
+        return;
+    }
+    thread.preempted = false;
+    let domain = match of(thread) {
+        Some(domain) => domain,
+        None => return,
+    };
+    let dcb = match domain.dcb() {
+        Some(dcb) => dcb,
+        None => return,
+    };
+    if !dcb.activations_enabled() {
+        return;
+    }
+
+    let events = notification::take_bound(thread);
+    let reason = if events != 0 {
+        ActivationReason::Event
+    } else {
+        ActivationReason::Preempted
+    };
+    dcb.reason = reason as u64;
+    dcb.events = events;
+    dcb.context = SavedContext {
+        gpr: thread.context.gpr,
+        sp: thread.context.sp_el0,
+        pc: thread.context.elr_el1,
+        pstate: thread.context.spsr_el1,
+    };
+    dcb.set_activations(false);
+
+    thread.context.elr_el1 = domain.entry;
+    thread.context.sp_el0 = domain.stack;
+    thread.context.gpr[0] = reason as u64;
+    thread.context.gpr[1] = domain.dcb as u64;
+    // EL0t, all interrupts unmasked.
+    thread.context.spsr_el1 = 0;
+}
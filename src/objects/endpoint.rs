@@ -0,0 +1,220 @@
+// mod objects::endpoint
+
+//! Synchronous IPC endpoints.
+//!
+//! An endpoint has no buffer: a message is copied directly from sender to
+//! receiver once both have arrived. Whoever comes first waits in the
+//! endpoint's queue, which holds either senders or receivers, never both.
+//!
+//! A call is a send followed by waiting for the reply. The receiver gets
+//! a one-shot reply capability in its TCB, replying through it consumes it.
+//!
+//! An all-zero endpoint is idle, so no initialization is needed.
+
+use caps::{cspace, Capability};
+use core::ptr;
+use ipc::{self, MessageInfo};
+use objects::{
+    self,
+    notification::{self, Notification},
+    tcb::{slots, ThreadState, Tcb},
+};
+use sched::{self, preemption};
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[repr(u8)]
+pub enum EndpointState {
+    Idle = 0,
+    /// Senders are queued.
+    Send,
+    /// Receivers are queued.
+    Recv,
+}
+
+/// Endpoint object, 2^ENDPOINT_SIZE_BITS bytes.
+#[repr(C)]
+pub struct Endpoint {
+    state: EndpointState,
+    head: *mut Tcb,
+    tail: *mut Tcb,
+}
+
+impl Endpoint {
+    pub fn from_ptr(ptr: usize) -> &'static mut Endpoint {
+        unsafe { &mut *(ptr as *mut Endpoint) }
+    }
+
+    pub fn as_ptr(&self) -> usize {
+        self as *const Endpoint as usize
+    }
+
+    pub fn state(&self) -> EndpointState {
+        self.state
+    }
+
+    fn enqueue(&mut self, tcb: &mut Tcb) {
+        tcb.ep_next = ptr::null_mut();
+        tcb.ep_prev = self.tail;
+        match unsafe { self.tail.as_mut() } {
+            Some(tail) => tail.ep_next = tcb,
+            None => self.head = tcb,
+        }
+        self.tail = tcb;
+    }
+
+    fn dequeue(&mut self) -> Option<&'static mut Tcb> {
+        let tcb = unsafe { self.head.as_mut()? };
+        self.remove(tcb);
+        Some(tcb)
+    }
+
+    fn remove(&mut self, tcb: &mut Tcb) {
+        match unsafe { tcb.ep_prev.as_mut() } {
+            Some(prev) => prev.ep_next = tcb.ep_next,
+            None => self.head = tcb.ep_next,
+        }
+        match unsafe { tcb.ep_next.as_mut() } {
+            Some(next) => next.ep_prev = tcb.ep_prev,
+            None => self.tail = tcb.ep_prev,
+        }
+        tcb.ep_next = ptr::null_mut();
+        tcb.ep_prev = ptr::null_mut();
+        if self.head.is_null() {
+            self.state = EndpointState::Idle;
+        }
+    }
+
+    /// Send the message in `thread`'s registers, as `badge` with the grant
+    /// right as given. Without `blocking` the message is dropped if nobody
+    /// is waiting for it.
+    pub fn send(
+        &mut self,
+        thread: &mut Tcb,
+        badge: u64,
+        can_grant: bool,
+        blocking: bool,
+        is_call: bool,
+    ) {
+        match self.state {
+            EndpointState::Recv => {
+                let receiver = self.dequeue().expect("receive queue is empty");
+                ipc::transfer(thread, receiver, self.as_ptr(), badge, can_grant);
+                receiver.set_state(ThreadState::Running);
+                sched::make_runnable(receiver);
+                if is_call {
+                    setup_reply(thread, receiver);
+                }
+            }
+            EndpointState::Idle | EndpointState::Send => {
+                if blocking {
+                    thread.block(ThreadState::BlockedOnSend, self.as_ptr());
+                    thread.blocked_badge = badge;
+                    thread.blocked_can_grant = can_grant;
+                    thread.blocked_is_call = is_call;
+                    self.enqueue(thread);
+                    self.state = EndpointState::Send;
+                }
+            }
+        }
+    }
+
+    /// Receive a message into `thread`. Without `blocking` an empty
+    /// message with badge 0 is delivered if no sender is waiting.
+    ///
+    /// A signalled bound notification takes precedence over senders.
+    pub fn receive(&mut self, thread: &mut Tcb, blocking: bool) {
+        if notification::receive_bound(thread) {
+            return;
+        }
+        match self.state {
+            EndpointState::Send => {
+                let sender = self.dequeue().expect("send queue is empty");
+                let badge = sender.blocked_badge;
+                ipc::transfer(sender, thread, self.as_ptr(), badge, sender.blocked_can_grant);
+                if sender.blocked_is_call {
+                    setup_reply(sender, thread);
+                } else {
+                    sender.set_state(ThreadState::Running);
+                    sched::make_runnable(sender);
+                }
+            }
+            EndpointState::Idle | EndpointState::Recv => {
+                if blocking {
+                    thread.block(ThreadState::BlockedOnReceive, self.as_ptr());
+                    self.enqueue(thread);
+                    self.state = EndpointState::Recv;
+                } else {
+                    ipc::set_message(thread, 0, MessageInfo::new(0, 0, 0, 0));
+                }
+            }
+        }
+    }
+}
+
+/// Block `caller` until `receiver` replies to it.
+fn setup_reply(caller: &mut Tcb, receiver: &mut Tcb) {
+    caller.block(ThreadState::BlockedOnReply, 0);
+
+    // Only the most recent caller can be replied to.
+    cspace::delete(&mut receiver.slots[slots::CALLER]);
+
+    let reply = Capability::Reply {
+        tcb: caller.as_ptr(),
+        master: false,
+    };
+    let caller_slot = &mut caller.slots[slots::REPLY];
+    caller_slot.insert_child(&mut receiver.slots[slots::CALLER], reply);
+}
+
+/// Send the message in `thread`'s registers to the thread that called it,
+/// consuming the reply capability. Does nothing if there is none.
+pub fn reply(thread: &mut Tcb) {
+    let cap = thread.slots[slots::CALLER].cap();
+    if let Capability::Reply { tcb, master: false } = cap {
+        let caller = Tcb::from_ptr(tcb);
+        if caller.state() == ThreadState::BlockedOnReply {
+            ipc::transfer(thread, caller, 0, 0, true);
+            caller.set_state(ThreadState::Running);
+            sched::make_runnable(caller);
+        }
+        cspace::delete(&mut thread.slots[slots::CALLER]);
+    }
+}
+
+/// Reply to the last caller and wait for the next message on `endpoint`,
+/// the usual server loop.
+pub fn reply_recv(endpoint: &mut Endpoint, thread: &mut Tcb) {
+    reply(thread);
+    endpoint.receive(thread, true);
+}
+
+/// Abort whatever IPC `thread` is blocked in, leaving it inactive.
+pub fn cancel_ipc(thread: &mut Tcb) {
+    match thread.state() {
+        ThreadState::BlockedOnSend | ThreadState::BlockedOnReceive => {
+            Endpoint::from_ptr(thread.blocked_on()).remove(thread);
+        }
+        ThreadState::BlockedOnNotification => {
+            Notification::from_ptr(thread.blocked_on()).remove(thread);
+        }
+        ThreadState::BlockedOnReply => {
+            // The callee's reply capability is the only child of the master.
+            cspace::revoke(&mut thread.slots[slots::REPLY]);
+        }
+        _ => return,
+    }
+    thread.block(ThreadState::Inactive, 0);
+}
+
+/// Release all threads queued on an endpoint being deleted.
+///
+/// They restart the interrupted system call, which then fails
+/// since the capability is gone. Stops at preemption points.
+pub fn cancel_all(endpoint: &mut Endpoint) -> objects::Result<()> {
+    while let Some(thread) = endpoint.dequeue() {
+        thread.block(ThreadState::Restart, 0);
+        sched::make_runnable(thread);
+        preemption::point()?;
+    }
+    Ok(())
+}
@@ -0,0 +1,54 @@
+// mod objects
+
+//! Kernel objects.
+//!
+//! After boot the kernel does not allocate memory itself: all free RAM is
+//! handed out as Untyped objects, which user code retypes into other
+//! kernel objects as needed.
+//!
+//! Freshly retyped memory is zero, Untyped clear memory for reuse when
+//! they are reset. It is initialized by `init_object()` for types where
+//! all zeroes isn't a valid state.
+//!
+//! Object types and sizes are part of the ABI and defined in vesper-user.
+
+pub mod cnode;
+pub mod domain;
+pub mod endpoint;
+pub mod notification;
+pub mod tcb;
+pub mod untyped;
+pub mod vspace;
+
+pub use vesper_user::object::{
+    ObjectType, CNODE_SLOT_BITS, DOMAIN_SIZE_BITS, ENDPOINT_SIZE_BITS, MAX_CNODE_RADIX,
+    MAX_UNTYPED_BITS, MIN_UNTYPED_BITS, NOTIFICATION_SIZE_BITS, PAGE_TABLE_SIZE_BITS,
+    TCB_SIZE_BITS, VSPACE_SIZE_BITS,
+};
+
+#[derive(Debug, PartialEq)]
+pub enum ObjectError {
+    /// Size parameter is out of range for the object type.
+    InvalidSize,
+    /// No room left for the requested objects.
+    NotEnoughMemory,
+    /// Zero objects requested, or Frames where they can't be mapped.
+    RangeError,
+    /// Kernel objects can't live in device memory.
+    DeviceMemory,
+    /// Stopped at a preemption point, the system call is restarted.
+    Preempted,
+}
+
+pub type Result<T> = ::core::result::Result<T, ObjectError>;
+
+/// Bring freshly zeroed memory at `ptr` into a valid state for `object_type`.
+pub fn init_object(object_type: ObjectType, ptr: usize, user_size_bits: u8) {
+    match object_type {
+        ObjectType::CNode => cnode::init(ptr, user_size_bits),
+        ObjectType::Tcb => tcb::init(ptr),
+        ObjectType::Domain => domain::init(ptr),
+        ObjectType::VSpace => vspace::init(ptr),
+        _ => {}
+    }
+}
@@ -0,0 +1,286 @@
+// mod objects::notification
+
+//! Asynchronous notifications.
+//!
+//! A notification is a word of badge bits. Signalling ORs the badge of the
+//! signalling capability into it and never blocks; waiting returns the
+//! accumulated word and clears it, blocking until it is non-zero.
+//!
+//! A notification can be bound to one thread. Signals then also wake that
+//! thread from a receive on any endpoint, so a server can wait for both
+//! messages and interrupts at once.
+//!
+//! Interrupt lines can be delivered to a notification. The line is masked
+//! when it fires and stays masked until the driver acknowledges it.
+//!
+//! An all-zero notification is idle and unbound, so no initialization
+//! is needed.
+
+use arch::sync::SpinLock;
+use caps::{CapError, Result};
+use core::ptr;
+use ipc::{self, MessageInfo};
+use objects::{
+    self, endpoint,
+    tcb::{ThreadState, Tcb},
+};
+use platform::{
+    armctrl::GPU_IRQ_COUNT,
+    irq::{self, Irq, IrqError},
+};
+use sched::{self, preemption};
+use syscall::KERNEL_LOCK;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[repr(u8)]
+pub enum NotificationState {
+    Idle = 0,
+    /// Threads are waiting, the word is zero.
+    Waiting,
+    /// Signalled, the word is non-zero.
+    Active,
+}
+
+/// Notification object, 2^NOTIFICATION_SIZE_BITS bytes.
+#[repr(C)]
+pub struct Notification {
+    state: NotificationState,
+    head: *mut Tcb,
+    tail: *mut Tcb,
+    word: u64,
+    /// Thread woken from endpoint receives, null if unbound.
+    bound_tcb: *mut Tcb,
+}
+
+/// Hand the badge word to a thread as an empty message.
+fn deliver(thread: &mut Tcb, word: u64) {
+    ipc::set_message(thread, word, MessageInfo::new(0, 0, 0, 0));
+}
+
+impl Notification {
+    pub fn from_ptr(ptr: usize) -> &'static mut Notification {
+        unsafe { &mut *(ptr as *mut Notification) }
+    }
+
+    pub fn as_ptr(&self) -> usize {
+        self as *const Notification as usize
+    }
+
+    pub fn state(&self) -> NotificationState {
+        self.state
+    }
+
+    pub fn bound_tcb(&self) -> Option<&'static mut Tcb> {
+        unsafe { self.bound_tcb.as_mut() }
+    }
+
+    fn enqueue(&mut self, tcb: &mut Tcb) {
+        tcb.ep_next = ptr::null_mut();
+        tcb.ep_prev = self.tail;
+        match unsafe { self.tail.as_mut() } {
+            Some(tail) => tail.ep_next = tcb,
+            None => self.head = tcb,
+        }
+        self.tail = tcb;
+    }
+
+    fn dequeue(&mut self) -> Option<&'static mut Tcb> {
+        let tcb = unsafe { self.head.as_mut()? };
+        self.remove(tcb);
+        Some(tcb)
+    }
+
+    pub(crate) fn remove(&mut self, tcb: &mut Tcb) {
+        match unsafe { tcb.ep_prev.as_mut() } {
+            Some(prev) => prev.ep_next = tcb.ep_next,
+            None => self.head = tcb.ep_next,
+        }
+        match unsafe { tcb.ep_next.as_mut() } {
+            Some(next) => next.ep_prev = tcb.ep_prev,
+            None => self.tail = tcb.ep_prev,
+        }
+        tcb.ep_next = ptr::null_mut();
+        tcb.ep_prev = ptr::null_mut();
+        if self.head.is_null() && self.state == NotificationState::Waiting {
+            self.state = NotificationState::Idle;
+        }
+    }
+
+    /// OR `badge` into the word, waking a waiter if there is one.
+    pub fn signal(&mut self, badge: u64) {
+        match self.state {
+            NotificationState::Idle => {
+                if let Some(tcb) = self.bound_tcb() {
+                    if tcb.state() == ThreadState::BlockedOnReceive {
+                        endpoint::cancel_ipc(tcb);
+                        deliver(tcb, badge);
+                        tcb.set_state(ThreadState::Running);
+                        sched::make_runnable(tcb);
+                        return;
+                    }
+                }
+                self.word = badge;
+                self.state = NotificationState::Active;
+            }
+            NotificationState::Waiting => {
+                let waiter = self.dequeue().expect("wait queue is empty");
+                deliver(waiter, badge);
+                waiter.block(ThreadState::Running, 0);
+                sched::make_runnable(waiter);
+            }
+            NotificationState::Active => self.word |= badge,
+        }
+    }
+
+    /// Take the word into `thread`. Without `blocking` a zero word is
+    /// delivered if the notification wasn't signalled.
+    pub fn wait(&mut self, thread: &mut Tcb, blocking: bool) {
+        match self.state {
+            NotificationState::Active => self.take(thread),
+            NotificationState::Idle | NotificationState::Waiting => {
+                if blocking {
+                    thread.block(ThreadState::BlockedOnNotification, self.as_ptr());
+                    self.enqueue(thread);
+                    self.state = NotificationState::Waiting;
+                } else {
+                    deliver(thread, 0);
+                }
+            }
+        }
+    }
+
+    fn take(&mut self, thread: &mut Tcb) {
+        deliver(thread, self.word);
+        self.word = 0;
+        self.state = NotificationState::Idle;
+    }
+
+    /// Bind to `thread`, each may only have one binding.
+    pub fn bind(&mut self, thread: &mut Tcb) -> Result<()> {
+        if !self.bound_tcb.is_null() || thread.bound_notification != 0 {
+            return Err(CapError::IllegalOperation);
+        }
+        self.bound_tcb = thread;
+        thread.bound_notification = self.as_ptr();
+        Ok(())
+    }
+
+    pub fn unbind(&mut self) {
+        if let Some(thread) = self.bound_tcb() {
+            thread.bound_notification = 0;
+        }
+        self.bound_tcb = ptr::null_mut();
+    }
+}
+
+/// Complete a receive of `thread` from its bound notification, if that
+/// was signalled. Returns true if the thread got the badge word.
+pub fn receive_bound(thread: &mut Tcb) -> bool {
+    if thread.bound_notification == 0 {
+        return false;
+    }
+    let ntfn = Notification::from_ptr(thread.bound_notification);
+    if ntfn.state != NotificationState::Active {
+        return false;
+    }
+    ntfn.take(thread);
+    true
+}
+
+/// Take the word of the notification bound to `thread`, 0 if not signalled.
+pub fn take_bound(thread: &Tcb) -> u64 {
+    if thread.bound_notification == 0 {
+        return 0;
+    }
+    let ntfn = Notification::from_ptr(thread.bound_notification);
+    if ntfn.state != NotificationState::Active {
+        return 0;
+    }
+    let word = ntfn.word;
+    ntfn.word = 0;
+    ntfn.state = NotificationState::Idle;
+    word
+}
+
+/// Release all waiters of a notification being deleted and unbind it.
+///
+/// Stops at preemption points.
+pub fn cancel_all(ntfn: &mut Notification) -> objects::Result<()> {
+    while let Some(thread) = ntfn.dequeue() {
+        thread.block(ThreadState::Restart, 0);
+        sched::make_runnable(thread);
+        preemption::point()?;
+    }
+    ntfn.unbind();
+    irq_detach_notification(ntfn.as_ptr());
+    Ok(())
+}
+
+/// Unbind the notification of a thread being destroyed.
+pub fn unbind_tcb(thread: &mut Tcb) {
+    if thread.bound_notification != 0 {
+        Notification::from_ptr(thread.bound_notification).unbind();
+    }
+}
+
+// Interrupt delivery.
+
+/// Notification and badge each GPU interrupt line is delivered to.
+static IRQ_NOTIFICATIONS: SpinLock<[Option<(usize, u64)>; GPU_IRQ_COUNT]> =
+    SpinLock::new([None; GPU_IRQ_COUNT]);
+
+fn gpu_line(irq: Irq) -> irq::Result<usize> {
+    match irq {
+        Irq::Gpu(line) if (line as usize) < GPU_IRQ_COUNT => Ok(line as usize),
+        _ => Err(IrqError::InvalidIrq),
+    }
+}
+
+fn handle_irq(irq: Irq) {
+    // Masked until the driver acknowledges it.
+    irq::disable(irq);
+
+    let _kernel = KERNEL_LOCK.lock();
+    let target = gpu_line(irq)
+        .ok()
+        .and_then(|line| IRQ_NOTIFICATIONS.lock_irqsave()[line]);
+    if let Some((ntfn, badge)) = target {
+        Notification::from_ptr(ntfn).signal(badge);
+    }
+}
+
+/// Deliver interrupt line `irq` to `ntfn` as `badge` and unmask it.
+pub fn irq_set_notification(irq: Irq, ntfn: &Notification, badge: u64) -> irq::Result<()> {
+    let line = gpu_line(irq)?;
+    irq::register(irq, handle_irq)?;
+    IRQ_NOTIFICATIONS.lock_irqsave()[line] = Some((ntfn.as_ptr(), badge));
+    irq::enable(irq)
+}
+
+/// Stop delivering `irq`, masking it.
+pub fn irq_clear_notification(irq: Irq) -> irq::Result<()> {
+    let line = gpu_line(irq)?;
+    IRQ_NOTIFICATIONS.lock_irqsave()[line] = None;
+    irq::unregister(irq)
+}
+
+/// Unmask `irq` after the driver has handled it.
+pub fn irq_ack(irq: Irq) -> irq::Result<()> {
+    let line = gpu_line(irq)?;
+    if IRQ_NOTIFICATIONS.lock_irqsave()[line].is_none() {
+        return Err(IrqError::NotRegistered);
+    }
+    irq::enable(irq)
+}
+
+fn irq_detach_notification(ntfn: usize) {
+    for line in 0..GPU_IRQ_COUNT {
+        let attached = match IRQ_NOTIFICATIONS.lock_irqsave()[line] {
+            Some((target, _)) => target == ntfn,
+            None => false,
+        };
+        if attached {
+            irq_clear_notification(Irq::Gpu(line as u32));
+        }
+    }
+}
@@ -0,0 +1,160 @@
+// mod objects::tcb
+
+//! Thread control blocks.
+
+use arch::{context::ExtendedContext, traps::TrapFrame};
+use caps::{slot::CapSlot, Capability};
+use core::ptr;
+
+pub use vesper_user::fault::Fault;
+
+/// Capability slots embedded in every TCB.
+pub mod slots {
+    /// Root CNode of the thread's CSpace.
+    pub const CSPACE: usize = 0;
+    /// Master reply capability, all reply capabilities to this thread derive from it.
+    pub const REPLY: usize = 1;
+    /// One-shot reply capability to the thread that called us last.
+    pub const CALLER: usize = 2;
+    /// Protection domain the thread runs in.
+    pub const VSPACE: usize = 3;
+    /// Frame holding the thread's IPC buffer.
+    pub const IPC_BUFFER: usize = 4;
+    /// Notification signalled when the thread faults.
+    pub const FAULT_HANDLER: usize = 5;
+    pub const COUNT: usize = 6;
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[repr(u8)]
+pub enum ThreadState {
+    Inactive = 0,
+    Running,
+    /// Runnable, re-executes the interrupted system call when scheduled.
+    Restart,
+    BlockedOnSend,
+    BlockedOnReceive,
+    BlockedOnReply,
+    BlockedOnNotification,
+}
+
+/// Thread control block, 2^TCB_SIZE_BITS bytes.
+#[repr(C)]
+pub struct Tcb {
+    /// User registers, saved on kernel entry.
+    pub context: TrapFrame,
+    /// User registers saved only when the thread leaves its core.
+    pub extended: ExtendedContext,
+    state: ThreadState,
+    /// Scheduling priority, higher runs first.
+    priority: u8,
+    /// Kernel address of the thread's IPC buffer, 0 if it has none.
+    ///
+    /// Only valid while the frame in the IPC_BUFFER slot holding it is there.
+    pub ipc_buffer: usize,
+    pub slots: [CapSlot; slots::COUNT],
+    /// Last fault, for the fault handler.
+    pub fault: Fault,
+
+    /// Object the thread is blocked on.
+    blocked_on: usize,
+    /// Badge of the capability a blocked send was made with.
+    pub(crate) blocked_badge: u64,
+    pub(crate) blocked_can_grant: bool,
+    pub(crate) blocked_is_call: bool,
+    /// Notification bound to this thread, 0 if none.
+    pub(crate) bound_notification: usize,
+    /// Domain this thread runs, 0 if none.
+    pub(crate) domain: usize,
+    /// Lost the CPU involuntarily, its domain may need an activation.
+    pub(crate) preempted: bool,
+
+    /// Links of the endpoint or notification queue the thread is blocked in.
+    pub(crate) ep_next: *mut Tcb,
+    pub(crate) ep_prev: *mut Tcb,
+
+    /// Links of the ready queue.
+    pub(crate) sched_next: *mut Tcb,
+    pub(crate) sched_prev: *mut Tcb,
+    pub(crate) queued: bool,
+}
+
+/// Bring a freshly zeroed TCB into its initial, inactive state.
+pub fn init(ptr: usize) {
+    let tcb = unsafe { &mut *(ptr as *mut Tcb) };
+    tcb.extended = ExtendedContext::new();
+    tcb.state = ThreadState::Inactive;
+    tcb.priority = 0;
+    tcb.ipc_buffer = 0;
+    tcb.fault = Fault::default();
+    tcb.blocked_on = 0;
+    tcb.blocked_badge = 0;
+    tcb.blocked_can_grant = false;
+    tcb.blocked_is_call = false;
+    tcb.bound_notification = 0;
+    tcb.domain = 0;
+    tcb.preempted = false;
+    tcb.ep_next = ptr::null_mut();
+    tcb.ep_prev = ptr::null_mut();
+    tcb.sched_next = ptr::null_mut();
+    tcb.sched_prev = ptr::null_mut();
+    tcb.queued = false;
+    for slot in tcb.slots.iter_mut() {
+        *slot = CapSlot::empty();
+    }
+    tcb.slots[slots::REPLY].insert_root(Capability::Reply { tcb: ptr, master: true });
+}
+
+impl Tcb {
+    /// The TCB at a kernel address taken from a capability.
+    pub fn from_ptr(ptr: usize) -> &'static mut Tcb {
+        unsafe { &mut *(ptr as *mut Tcb) }
+    }
+
+    pub fn as_ptr(&self) -> usize {
+        self as *const Tcb as usize
+    }
+
+    pub fn state(&self) -> ThreadState {
+        self.state
+    }
+
+    pub fn set_state(&mut self, state: ThreadState) {
+        self.state = state;
+    }
+
+    pub fn priority(&self) -> u8 {
+        self.priority
+    }
+
+    pub fn set_priority(&mut self, priority: u8) {
+        self.priority = priority;
+    }
+
+    pub fn is_runnable(&self) -> bool {
+        match self.state {
+            ThreadState::Running | ThreadState::Restart => true,
+            _ => false,
+        }
+    }
+
+    pub fn blocked_on(&self) -> usize {
+        self.blocked_on
+    }
+
+    /// Mark the thread blocked on the object at `object`.
+    pub fn block(&mut self, state: ThreadState, object: usize) {
+        self.state = state;
+        self.blocked_on = object;
+    }
+
+    /// Root of the thread's CSpace.
+    pub fn cspace_root(&self) -> Capability {
+        self.slots[slots::CSPACE].cap()
+    }
+
+    /// Protection domain of the thread, Null if it has none.
+    pub fn vspace_root(&self) -> Capability {
+        self.slots[slots::VSPACE].cap()
+    }
+}
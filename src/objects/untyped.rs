@@ -0,0 +1,299 @@
+// mod objects::untyped
+
+//! Untyped memory.
+//!
+//! An Untyped object describes a naturally aligned power-of-two region of
+//! physical memory. Retype carves objects out of it at increasing
+//! addresses, a watermark remembers how far it got. Once every object
+//! made from it is gone the watermark is reset and the memory reused.
+//!
+//! Memory above the watermark is kept zeroed, so retype only initializes
+//! objects. Reset clears the memory below the watermark, lowering it as it
+//! goes, so it can stop at preemption points and continue later. Free RAM
+//! is cleared once at boot, when it is turned into Untyped, so the first
+//! retype of a large boot Untyped doesn't clear all of it at once.
+
+use arch::{
+    mmu::{BLOCK_SIZE, PAGE_SIZE},
+    sync::SpinLock,
+};
+use core::{fmt::Write, ptr};
+use mm::{self, frames::Region};
+use objects::{
+    init_object, ObjectError, ObjectType, Result, MAX_UNTYPED_BITS, MIN_UNTYPED_BITS,
+};
+use platform::{rpi3::BcmHost, uart::CONSOLE};
+use sched::preemption;
+
+/// Upper bound on Untyped objects created at boot.
+pub const MAX_BOOT_UNTYPEDS: usize = 64;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Untyped {
+    base: usize,
+    size_bits: u8,
+    /// Bytes from base already handed out by retype.
+    watermark: usize,
+    /// Device memory is never touched by the kernel, only mapped.
+    device: bool,
+}
+
+/// Objects produced by a single retype.
+#[derive(Clone, Copy, Debug)]
+pub struct Retyped {
+    pub object_type: ObjectType,
+    pub base: usize,
+    pub size_bits: u8,
+    pub count: usize,
+}
+
+impl Retyped {
+    /// Addresses of the new objects.
+    pub fn objects(&self) -> impl Iterator<Item = usize> {
+        let (base, size) = (self.base, 1usize << self.size_bits);
+        (0..self.count).map(move |i| base + i * size)
+    }
+}
+
+#[inline]
+fn align_up(address: usize, align: usize) -> usize {
+    (address + align - 1) & !(align - 1)
+}
+
+impl Untyped {
+    pub const fn empty() -> Untyped {
+        Untyped {
+            base: 0,
+            size_bits: 0,
+            watermark: 0,
+            device: false,
+        }
+    }
+
+    /// Describe the region at `base`, which must be aligned to its size.
+    pub fn new(base: usize, size_bits: u8, device: bool) -> Untyped {
+        debug_assert!(base & ((1 << size_bits) - 1) == 0);
+        Untyped {
+            base,
+            size_bits,
+            watermark: 0,
+            device,
+        }
+    }
+
+    pub fn base(&self) -> usize {
+        self.base
+    }
+
+    pub fn size_bits(&self) -> u8 {
+        self.size_bits
+    }
+
+    pub fn size(&self) -> usize {
+        1 << self.size_bits
+    }
+
+    pub fn is_device(&self) -> bool {
+        self.device
+    }
+
+    pub fn watermark(&self) -> usize {
+        self.watermark
+    }
+
+    /// Bytes still available above the watermark.
+    pub fn free_bytes(&self) -> usize {
+        self.size() - self.watermark
+    }
+
+    /// Carve `count` objects of `object_type` out of the free part.
+    ///
+    /// Objects are aligned to their size, the padding is lost until reset.
+    /// Kernel objects are initialized, child Untyped are left as is.
+    /// Frames can't be placed in the first 2MiB block, which user space
+    /// can't map as it holds the kernel image.
+    pub fn retype(
+        &mut self,
+        object_type: ObjectType,
+        user_size_bits: u8,
+        count: usize,
+    ) -> Result<Retyped> {
+        if count == 0 {
+            return Err(ObjectError::RangeError);
+        }
+        if self.device && !object_type.allowed_in_device_memory() {
+            return Err(ObjectError::DeviceMemory);
+        }
+
+        let size_bits = object_type
+            .size_bits(user_size_bits)
+            .ok_or(ObjectError::InvalidSize)?;
+        if size_bits > self.size_bits {
+            return Err(ObjectError::InvalidSize);
+        }
+
+        let object_size = 1usize << size_bits;
+        let start = align_up(self.base + self.watermark, object_size);
+        let end = count
+            .checked_mul(object_size)
+            .and_then(|total| start.checked_add(total))
+            .ok_or(ObjectError::NotEnoughMemory)?;
+        if end > self.base + self.size() {
+            return Err(ObjectError::NotEnoughMemory);
+        }
+        if object_type == ObjectType::Frame && start < BLOCK_SIZE {
+            return Err(ObjectError::RangeError);
+        }
+
+        self.watermark = end - self.base;
+
+        let retyped = Retyped {
+            object_type,
+            base: start,
+            size_bits,
+            count,
+        };
+
+        if object_type != ObjectType::Untyped {
+            for object in retyped.objects() {
+                init_object(object_type, object, user_size_bits);
+            }
+        }
+
+        Ok(retyped)
+    }
+
+    /// Make the whole region available again, clearing it from the top
+    /// down a page at a time.
+    ///
+    /// Stops at preemption points with the watermark as far down as it got,
+    /// resetting again continues from there. Only valid once no object
+    /// retyped from it exists anymore.
+    pub fn reset(&mut self) -> Result<()> {
+        if self.device {
+            self.watermark = 0;
+            return Ok(());
+        }
+        while self.watermark > 0 {
+            let low = (self.watermark - 1) & !(PAGE_SIZE - 1);
+            unsafe {
+                ptr::write_bytes((self.base + low) as *mut u8, 0, self.watermark - low);
+            }
+            self.watermark = low;
+            if self.watermark > 0 {
+                preemption::point()?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Untyped objects covering all memory left after boot, for the root task.
+pub struct BootUntypeds {
+    untypeds: [Untyped; MAX_BOOT_UNTYPEDS],
+    count: usize,
+}
+
+impl BootUntypeds {
+    const fn new() -> BootUntypeds {
+        BootUntypeds {
+            untypeds: [Untyped::empty(); MAX_BOOT_UNTYPEDS],
+            count: 0,
+        }
+    }
+
+    pub fn as_slice(&self) -> &[Untyped] {
+        &self.untypeds[..self.count]
+    }
+
+    /// Split a region into the largest naturally aligned power-of-two chunks.
+    ///
+    /// Returns false if the table filled up, the rest of the region is lost.
+    fn add_region(&mut self, region: Region, device: bool) -> bool {
+        let mut start = region.start;
+        while start < region.end {
+            let remaining = region.end - start;
+            let align_bits = if start == 0 {
+                MAX_UNTYPED_BITS as u32
+            } else {
+                start.trailing_zeros()
+            };
+            let fit_bits = 63 - remaining.leading_zeros(); // floor(log2(remaining))
+            let size_bits = align_bits.min(fit_bits).min(MAX_UNTYPED_BITS as u32) as u8;
+
+            if size_bits >= MIN_UNTYPED_BITS {
+                if self.count == MAX_BOOT_UNTYPEDS {
+                    return false;
+                }
+                if !device {
+                    unsafe {
+                        ptr::write_bytes(start as *mut u8, 0, 1 << size_bits);
+                    }
+                }
+                self.untypeds[self.count] = Untyped::new(start, size_bits, device);
+                self.count += 1;
+            }
+            start += 1 << size_bits;
+        }
+        true
+    }
+}
+
+pub static BOOT_UNTYPEDS: SpinLock<BootUntypeds> = SpinLock::new(BootUntypeds::new());
+
+/// Turn all remaining free frames and the peripheral window into Untyped.
+///
+/// Peripherals the kernel drives itself are left out: the pages of the
+/// window it uses and the local peripherals, so user code can't map them.
+/// After this the frame allocator is empty and the kernel allocates nothing.
+/// Memory that doesn't fit into the table is reported on the console and
+/// left unused. Returns the number of Untyped objects created.
+pub fn init_boot_untypeds() -> usize {
+    let mut frames = mm::FRAMES.lock();
+    let mut untypeds = BOOT_UNTYPEDS.lock();
+
+    loop {
+        let region = match frames.regions().first() {
+            Some(&region) => region,
+            None => break,
+        };
+        if let Err(e) = frames.reserve(region) {
+            writeln!(
+                CONSOLE.lock_irqsave(),
+                "Can't take RAM {:#x}-{:#x} from the frame allocator: {:?}",
+                region.start,
+                region.end,
+                e
+            );
+            break;
+        }
+        if !untypeds.add_region(region, false) {
+            writeln!(
+                CONSOLE.lock_irqsave(),
+                "Boot untyped table full, RAM from {:#x} left unused",
+                region.start
+            );
+            break;
+        }
+    }
+
+    let mut start = BcmHost::get_peripheral_address();
+    let end = start + BcmHost::get_peripheral_size();
+    let mut complete = true;
+    for &page in BcmHost::get_kernel_peripheral_pages().iter() {
+        // Pages shared by several drivers come up more than once.
+        if page >= start {
+            complete &= untypeds.add_region(Region { start, end: page }, true);
+            start = page + PAGE_SIZE;
+        }
+    }
+    complete &= untypeds.add_region(Region { start, end }, true);
+    if !complete {
+        writeln!(
+            CONSOLE.lock_irqsave(),
+            "Boot untyped table full, peripherals left out"
+        );
+    }
+
+    untypeds.count
+}
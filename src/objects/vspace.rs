@@ -0,0 +1,556 @@
+// mod objects::vspace
+
+//! Protection domains in the single address space.
+//!
+//! Memory is found at its physical address in every protection domain, a
+//! VSpace only decides what its threads may access. It consists of a
+//! level 1 translation table followed by the level 2 table for the first
+//! GiB, whose first entry shares the kernel image with the kernel tables,
+//! see `arch::mmu`. Everything else starts out inaccessible.
+//!
+//! Frame capabilities grant access to ranges of their memory, in 2MiB
+//! blocks where the range covers them and otherwise in pages of a
+//! PageTable mapped for the block. A Frame capability records the VSpace
+//! it granted access in, deleting it revokes the access. Mapping a frame
+//! grants access to all of it and picks its cacheability, remapping
+//! changes access and cacheability wherever the frame granted some.
+//! PageTables are cleared when unmapped.
+//!
+//! Changes are broadcast to the TLBs of all cores, entries are replaced
+//! break-before-make.
+//!
+//! Each VSpace has its own ASID, so TLB entries of different protection
+//! domains coexist. The kernel always runs on its own tables: lower EL
+//! entries switch to them in `unload()`, and `load()` switches to the
+//! tables of the thread about to return to EL0. A VSpace is only destroyed
+//! once no core has it loaded, after which its ASID is flushed and reused.
+//!
+//! A VSpace must be initialized by `init()`, PageTables need no
+//! initialization.
+
+use arch::{
+    cache, dmb, loop_until,
+    mmu::{
+        self, Memory, PageTable, UserAccess, BLOCK_SHIFT, BLOCK_SIZE, ENTRIES_PER_TABLE, PAGE_SIZE,
+    },
+    read_cpu_id,
+    smp::MAX_CPUS,
+    sync::SpinLock,
+};
+use caps::{slot::CapSlot, CapError, CapRights, Capability, Result};
+use core::{cmp, ptr};
+use objects::tcb::{slots, Tcb};
+use platform::irq;
+use sched::preemption;
+
+pub use vesper_user::vspace::{Access, Cacheability};
+
+/// ASIDs are 8 bits, 0 is used by the kernel tables.
+const ASID_COUNT: usize = 256;
+
+/// Owners of the ASIDs.
+struct Asids {
+    /// VSpace using each ASID, 0 if free.
+    owners: [usize; ASID_COUNT],
+    /// Where to look for a free ASID next. Handing them out in turn keeps
+    /// stale records of destroyed VSpaces from matching for long.
+    next: usize,
+}
+
+impl Asids {
+    const fn new() -> Asids {
+        Asids {
+            owners: [0; ASID_COUNT],
+            next: 1,
+        }
+    }
+
+    fn free_count(&self) -> usize {
+        self.owners[1..].iter().filter(|&&owner| owner == 0).count()
+    }
+
+    fn assign(&mut self, vspace: usize) -> Option<u8> {
+        for i in 0..ASID_COUNT - 1 {
+            let asid = 1 + (self.next - 1 + i) % (ASID_COUNT - 1);
+            if self.owners[asid] == 0 {
+                self.owners[asid] = vspace;
+                self.next = asid % (ASID_COUNT - 1) + 1;
+                return Some(asid as u8);
+            }
+        }
+        None
+    }
+}
+
+static ASIDS: SpinLock<Asids> = SpinLock::new(Asids::new());
+
+/// VSpace whose tables each core translates with, 0 for the kernel tables.
+static mut LOADED: [usize; MAX_CPUS] = [0; MAX_CPUS];
+
+/// Level 1 and level 2 tables of the VSpace at `vspace`.
+fn tables(vspace: usize) -> (&'static mut PageTable, &'static mut PageTable) {
+    unsafe {
+        (
+            &mut *(vspace as *mut PageTable),
+            &mut *((vspace + PAGE_SIZE) as *mut PageTable),
+        )
+    }
+}
+
+/// Whether the VSpace at `vspace` still exists and uses `asid`.
+fn is_live(vspace: usize, asid: u8) -> bool {
+    vspace != 0 && ASIDS.lock().owners[asid as usize] == vspace
+}
+
+/// Set up the tables of a freshly retyped, zeroed VSpace.
+pub fn init(vspace: usize) {
+    let (l1, l2) = tables(vspace);
+    mmu::init_user_tables(l1, l2);
+}
+
+/// ASIDs left for new VSpaces.
+pub fn free_asids() -> usize {
+    ASIDS.lock().free_count()
+}
+
+/// Give a new VSpace its ASID, callers check `free_asids()` first.
+pub fn assign_asid(vspace: usize) -> u8 {
+    ASIDS.lock().assign(vspace).unwrap_or(0)
+}
+
+fn loaded_anywhere(vspace: usize) -> bool {
+    (0..MAX_CPUS).any(|cpu| unsafe { ptr::read_volatile(&LOADED[cpu]) == vspace })
+}
+
+/// Release the ASID of a VSpace whose last capability is gone, waiting
+/// for cores still running threads in it to enter the kernel.
+pub fn destroy(vspace: usize, asid: u8) {
+    for cpu in 0..MAX_CPUS {
+        if unsafe { ptr::read_volatile(&LOADED[cpu]) } == vspace {
+            irq::kick(cpu);
+        }
+    }
+    loop_until(|| !loaded_anywhere(vspace));
+    mmu::invalidate_asid(asid);
+    ASIDS.lock().owners[asid as usize] = 0;
+}
+
+/// Switch to the protection domain of `thread`, about to return to EL0.
+///
+/// Threads without a VSpace stay on the kernel tables, where EL0 has no
+/// access. Called with the kernel lock held, like `destroy()`.
+pub fn load(thread: &Tcb) {
+    if let Capability::VSpace { ptr, asid } = thread.slots[slots::VSPACE].cap() {
+        unsafe {
+            ptr::write_volatile(&mut LOADED[read_cpu_id() as usize], ptr);
+        }
+        dmb();
+        mmu::switch_tables(ptr, asid);
+    }
+}
+
+/// Switch back to the kernel tables on entry from EL0.
+pub fn unload() {
+    let cpu = read_cpu_id() as usize;
+    if unsafe { LOADED[cpu] } == 0 {
+        return;
+    }
+    mmu::use_kernel_tables();
+    dmb();
+    unsafe {
+        ptr::write_volatile(&mut LOADED[cpu], 0);
+    }
+}
+
+/// Addresses of `length` bytes from `offset` into the frame at `base`.
+///
+/// Only page aligned ranges of the first GiB past the kernel image's
+/// block can be granted.
+fn frame_range(base: usize, size_bits: u8, offset: usize, length: usize) -> Result<(usize, usize)> {
+    let end = offset.checked_add(length).ok_or(CapError::RangeError)?;
+    if length == 0 || end > 1 << size_bits {
+        return Err(CapError::RangeError);
+    }
+    if offset % PAGE_SIZE != 0 || length % PAGE_SIZE != 0 {
+        return Err(CapError::RangeError);
+    }
+    let (start, end) = (base + offset, base + end);
+    if start < BLOCK_SIZE || end > ENTRIES_PER_TABLE << BLOCK_SHIFT {
+        return Err(CapError::RangeError);
+    }
+    Ok((start, end))
+}
+
+fn is_table(descriptor: u64) -> bool {
+    descriptor & 0b11 == 0b11
+}
+
+fn table_at(descriptor: u64) -> &'static mut PageTable {
+    const ADDRESS_MASK: u64 = 0x0000_ffff_ffff_f000;
+    unsafe { &mut *((descriptor & ADDRESS_MASK) as *mut PageTable) }
+}
+
+/// What `set_access()` does to the entries of a range.
+#[derive(Clone, Copy)]
+enum Change {
+    /// Give access, replacing what there was.
+    Grant(UserAccess),
+    /// Change access where there is some.
+    Remap(UserAccess),
+    Revoke,
+}
+
+/// Update the entry for the page or block of `size` bytes at `address`.
+///
+/// Valid entries are replaced break-before-make: invalidated and flushed
+/// from the TLBs of all cores before the new one is written, as required
+/// when the memory type changes. RAM changing to or from uncached access
+/// is cleaned and invalidated from the caches in between, as the kernel
+/// accesses it cached.
+fn update_entry(entry: &mut u64, asid: u8, address: usize, size: usize, change: Change, ram: bool) {
+    let access = match change {
+        Change::Grant(access) => Some(access),
+        Change::Remap(access) if mmu::is_valid(*entry) => Some(access),
+        Change::Remap(_) => return,
+        Change::Revoke => None,
+    };
+    let new = access.map_or(0, |access| {
+        mmu::user_descriptor(address, size == BLOCK_SIZE, access)
+    });
+    let old = *entry;
+    if old == new {
+        return;
+    }
+    if mmu::is_valid(old) {
+        *entry = 0;
+        mmu::invalidate_page(asid, address);
+    }
+    if let Some(access) = access {
+        let was_uncached = mmu::is_valid(old) && !mmu::is_cached(old);
+        if ram && (access.memory != Memory::Normal || was_uncached) {
+            cache::clean_invalidate_range(address, size);
+        }
+        *entry = new;
+    }
+}
+
+/// Apply `change` to the VSpace's access to `start..end`, both page
+/// aligned, which is in RAM if `ram`.
+///
+/// Stops at preemption points, doing it again continues.
+fn set_access(
+    vspace: usize,
+    asid: u8,
+    start: usize,
+    end: usize,
+    change: Change,
+    ram: bool,
+) -> Result<()> {
+    let granting = match change {
+        Change::Grant(_) => true,
+        _ => false,
+    };
+    let (_, l2) = tables(vspace);
+    let mut address = start;
+    while address < end {
+        let index = address >> BLOCK_SHIFT;
+        let block_end = cmp::min((index + 1) << BLOCK_SHIFT, end);
+        let entry = &mut l2.entries[index];
+        if is_table(*entry) {
+            let table = table_at(*entry);
+            while address < block_end {
+                let page = (address >> mmu::PAGE_SHIFT) % ENTRIES_PER_TABLE;
+                update_entry(
+                    &mut table.entries[page],
+                    asid,
+                    address,
+                    PAGE_SIZE,
+                    change,
+                    ram,
+                );
+                address += PAGE_SIZE;
+            }
+        } else if block_end - address == BLOCK_SIZE {
+            update_entry(entry, asid, address, BLOCK_SIZE, change, ram);
+            address = block_end;
+        } else if granting || mmu::is_valid(*entry) {
+            // Part of a block needs a PageTable.
+            return Err(CapError::FailedLookup);
+        } else {
+            address = block_end;
+        }
+        mmu::publish_tables();
+        preemption::point()?;
+    }
+    Ok(())
+}
+
+/// Check that the frame capability `cap` may grant `access` in the VSpace,
+/// returning its base, size and whether it is device memory.
+///
+/// A frame capability grants access in one VSpace only.
+fn check_grant(
+    cap: Capability,
+    vspace: usize,
+    asid: u8,
+    access: Access,
+) -> Result<(usize, u8, bool)> {
+    match cap {
+        Capability::Frame {
+            base,
+            size_bits,
+            rights,
+            device,
+            vspace: granted,
+            asid: granted_asid,
+        } => {
+            if (granted, granted_asid) != (vspace, asid) && is_live(granted, granted_asid) {
+                return Err(CapError::IllegalOperation);
+            }
+            let needed = if access.contains(Access::WRITE) {
+                CapRights::READ | CapRights::WRITE
+            } else {
+                CapRights::READ
+            };
+            if !access.contains(Access::READ) || !rights.contains(needed) {
+                return Err(CapError::IllegalOperation);
+            }
+            Ok((base, size_bits, device))
+        }
+        _ => Err(CapError::InvalidCapability),
+    }
+}
+
+/// Record in the frame capability in `frame` that it granted access in the VSpace.
+///
+/// Done before changing any entries, so deleting the capability revokes
+/// whatever part of a grant happened if it stops early.
+fn record_grant(frame: &mut CapSlot, vspace: usize, asid: u8) {
+    let mut cap = frame.cap();
+    if let Capability::Frame {
+        vspace: ref mut granted,
+        asid: ref mut granted_asid,
+        ..
+    } = cap
+    {
+        *granted = vspace;
+        *granted_asid = asid;
+    }
+    frame.update(cap);
+}
+
+/// Memory type for mapping a frame as `cacheability`, device registers
+/// can't be cached.
+fn memory_type(cacheability: Cacheability, device: bool) -> Result<Memory> {
+    match cacheability {
+        Cacheability::Normal if device => Err(CapError::IllegalOperation),
+        Cacheability::Normal => Ok(Memory::Normal),
+        Cacheability::Device => Ok(Memory::Device),
+        Cacheability::WriteCombining => Ok(Memory::NonCacheable),
+    }
+}
+
+fn user_access(access: Access, memory: Memory) -> UserAccess {
+    UserAccess {
+        write: access.contains(Access::WRITE),
+        execute: access.contains(Access::EXECUTE),
+        memory,
+    }
+}
+
+/// Check that the frame capability `cap` holds the `size` bytes at
+/// `address`, for memory the kernel writes on behalf of the capability's
+/// holder like IPC buffers.
+///
+/// The holder must be allowed to write the frame itself, and the kernel
+/// doesn't write device memory.
+pub fn check_shared(cap: Capability, address: usize, size: usize) -> Result<()> {
+    match cap {
+        Capability::Frame {
+            base,
+            size_bits,
+            rights,
+            device,
+            ..
+        } => {
+            if device || !rights.contains(CapRights::READ | CapRights::WRITE) {
+                return Err(CapError::IllegalOperation);
+            }
+            let end = address.checked_add(size).ok_or(CapError::RangeError)?;
+            if address < base || end > base + (1 << size_bits) {
+                return Err(CapError::RangeError);
+            }
+            Ok(())
+        }
+        _ => Err(CapError::InvalidCapability),
+    }
+}
+
+/// Part of the frame at `base` that can have been granted.
+fn granted_range(base: usize, size_bits: u8) -> (usize, usize) {
+    let start = cmp::max(base, BLOCK_SIZE);
+    let end = cmp::min(base + (1 << size_bits), ENTRIES_PER_TABLE << BLOCK_SHIFT);
+    (start, cmp::max(start, end))
+}
+
+/// Grant the VSpace `access` to `length` bytes from `offset` of the
+/// frame in `frame`, as normal memory or device memory outside of RAM.
+pub fn grant(
+    vspace: usize,
+    asid: u8,
+    frame: &mut CapSlot,
+    offset: usize,
+    length: usize,
+    access: Access,
+) -> Result<()> {
+    let (base, size_bits, device) = check_grant(frame.cap(), vspace, asid, access)?;
+    let (start, end) = frame_range(base, size_bits, offset, length)?;
+    record_grant(frame, vspace, asid);
+    let memory = if device {
+        Memory::Device
+    } else {
+        Memory::Normal
+    };
+    let change = Change::Grant(user_access(access, memory));
+    set_access(vspace, asid, start, end, change, !device)
+}
+
+/// Grant the VSpace `access` to all of the frame in `frame` as
+/// `cacheability` memory.
+pub fn map_frame(
+    vspace: usize,
+    asid: u8,
+    frame: &mut CapSlot,
+    access: Access,
+    cacheability: Cacheability,
+) -> Result<()> {
+    let (base, size_bits, device) = check_grant(frame.cap(), vspace, asid, access)?;
+    let memory = memory_type(cacheability, device)?;
+    let (start, end) = frame_range(base, size_bits, 0, 1 << size_bits)?;
+    record_grant(frame, vspace, asid);
+    let change = Change::Grant(user_access(access, memory));
+    set_access(vspace, asid, start, end, change, !device)
+}
+
+/// Change the access the frame in `frame` granted to `access` as
+/// `cacheability` memory, wherever it granted some.
+pub fn remap_frame(frame: &mut CapSlot, access: Access, cacheability: Cacheability) -> Result<()> {
+    let cap = frame.cap();
+    let (vspace, asid) = match cap {
+        Capability::Frame { vspace, asid, .. } if is_live(vspace, asid) => (vspace, asid),
+        Capability::Frame { .. } => return Err(CapError::IllegalOperation),
+        _ => return Err(CapError::InvalidCapability),
+    };
+    let (base, size_bits, device) = check_grant(cap, vspace, asid, access)?;
+    let memory = memory_type(cacheability, device)?;
+    let (start, end) = granted_range(base, size_bits);
+    let change = Change::Remap(user_access(access, memory));
+    set_access(vspace, asid, start, end, change, !device)
+}
+
+/// Revoke access the frame in `frame` granted to `length` bytes from
+/// `offset` in the VSpace.
+pub fn revoke(
+    vspace: usize,
+    asid: u8,
+    frame: &mut CapSlot,
+    offset: usize,
+    length: usize,
+) -> Result<()> {
+    match frame.cap() {
+        Capability::Frame {
+            base,
+            size_bits,
+            device,
+            vspace: granted,
+            asid: granted_asid,
+            ..
+        } => {
+            if (granted, granted_asid) != (vspace, asid) {
+                return Err(CapError::IllegalOperation);
+            }
+            let (start, end) = frame_range(base, size_bits, offset, length)?;
+            set_access(vspace, asid, start, end, Change::Revoke, !device)
+        }
+        _ => Err(CapError::InvalidCapability),
+    }
+}
+
+/// Use the PageTable in `table` for the 2MiB block at `address` in the VSpace.
+///
+/// The block must not be accessible as a whole, and the capability must be
+/// the only one to the table so no other copy can map it too.
+pub fn map_page_table(table: &mut CapSlot, vspace: usize, asid: u8, address: usize) -> Result<()> {
+    let ptr = match table.cap() {
+        Capability::PageTable { ptr, vspace: 0, .. } => ptr,
+        Capability::PageTable { .. } => return Err(CapError::IllegalOperation),
+        _ => return Err(CapError::InvalidCapability),
+    };
+    if !table.is_final() {
+        return Err(CapError::IllegalOperation);
+    }
+    let block = address >> BLOCK_SHIFT;
+    if block == 0 || block >= ENTRIES_PER_TABLE {
+        return Err(CapError::RangeError);
+    }
+    let (_, l2) = tables(vspace);
+    if mmu::is_valid(l2.entries[block]) {
+        return Err(CapError::DeleteFirst);
+    }
+    // Replacing an invalid entry needs no TLB maintenance.
+    l2.entries[block] = mmu::table_descriptor(ptr as *const PageTable);
+    mmu::publish_tables();
+    table.update(Capability::PageTable {
+        ptr,
+        vspace,
+        asid,
+        block: block as u16,
+    });
+    Ok(())
+}
+
+/// Undo what the capability in `slot` did to a VSpace, before it is
+/// deleted or on request: revoke the access a frame granted, or unmap and
+/// clear a PageTable.
+///
+/// Stops at preemption points, doing it again continues.
+pub fn unmap(slot: &mut CapSlot) -> Result<()> {
+    let cap = slot.cap();
+    match cap {
+        Capability::Frame {
+            base,
+            size_bits,
+            device,
+            vspace,
+            asid,
+            ..
+        } if vspace != 0 => {
+            if is_live(vspace, asid) {
+                let (start, end) = granted_range(base, size_bits);
+                set_access(vspace, asid, start, end, Change::Revoke, !device)?;
+            }
+            slot.update(cap.unmapped());
+        }
+        Capability::PageTable {
+            ptr,
+            vspace,
+            asid,
+            block,
+        } if vspace != 0 => {
+            if is_live(vspace, asid) {
+                let (_, l2) = tables(vspace);
+                let entry = &mut l2.entries[block as usize];
+                if *entry == mmu::table_descriptor(ptr as *const PageTable) {
+                    *entry = 0;
+                    mmu::invalidate_asid(asid);
+                }
+            }
+            unsafe {
+                ptr::write_bytes(ptr as *mut PageTable, 0, 1);
+            }
+            slot.update(cap.unmapped());
+        }
+        _ => {}
+    }
+    Ok(())
+}
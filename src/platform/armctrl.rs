@@ -0,0 +1,98 @@
+use core::ops;
+use platform::rpi3::BcmHost;
+use register::mmio::*;
+
+// BCM2835 ARM interrupt controller for GPU peripheral interrupts.
+//
+// See BCM2837-ARM-Peripherals.pdf, chapter 7 "Interrupts".
+
+// Offset from the peripheral base.
+pub const ARMCTRL_OFFSET: usize = 0xb200;
+
+#[allow(non_snake_case)]
+#[repr(C)]
+pub struct RegisterBlock {
+    IRQ_BASIC_PENDING: ReadOnly<u32>, // 0x00
+    IRQ_PENDING: [ReadOnly<u32>; 2],  // 0x04 - IRQs 0-31, 32-63
+    FIQ_CONTROL: ReadWrite<u32>,      // 0x0C
+    ENABLE_IRQS: [WriteOnly<u32>; 2], // 0x10
+    ENABLE_BASIC_IRQS: WriteOnly<u32>, // 0x18
+    DISABLE_IRQS: [WriteOnly<u32>; 2], // 0x1C
+    DISABLE_BASIC_IRQS: WriteOnly<u32>, // 0x24
+}
+
+/// Number of GPU peripheral interrupt lines.
+pub const GPU_IRQ_COUNT: usize = 64;
+
+/// Some well-known GPU interrupt numbers.
+pub mod gpu_irq {
+    pub const SYSTEM_TIMER_1: u32 = 1;
+    pub const SYSTEM_TIMER_3: u32 = 3;
+    pub const USB: u32 = 9;
+    pub const AUX: u32 = 29; // Mini UART and SPI1/2
+    pub const I2C_SPI_SLAVE: u32 = 43;
+    pub const PWA0: u32 = 45;
+    pub const PWA1: u32 = 46;
+    pub const SMI: u32 = 48;
+    pub const GPIO_0: u32 = 49;
+    pub const GPIO_1: u32 = 50;
+    pub const GPIO_2: u32 = 51;
+    pub const GPIO_3: u32 = 52;
+    pub const I2C: u32 = 53;
+    pub const SPI: u32 = 54;
+    pub const PCM: u32 = 55;
+    pub const UART: u32 = 57; // PL011
+}
+
+pub struct ArmInterruptController;
+
+/// Deref to RegisterBlock
+///
+/// Allows writing
+/// ```
+/// self.IRQ_BASIC_PENDING.get()
+/// ```
+/// instead of something along the lines of
+/// ```
+/// unsafe { (*ArmInterruptController::ptr()).IRQ_BASIC_PENDING.get() }
+/// ```
+impl ops::Deref for ArmInterruptController {
+    type Target = RegisterBlock;
+
+    fn deref(&self) -> &Self::Target {
+        unsafe { &*Self::ptr() }
+    }
+}
+
+impl ArmInterruptController {
+    pub fn new() -> ArmInterruptController {
+        ArmInterruptController
+    }
+
+    /// Returns a pointer to the register block
+    fn ptr() -> *const RegisterBlock {
+        (BcmHost::get_peripheral_address() + ARMCTRL_OFFSET) as *const _
+    }
+
+    /// Mask everything, used once at boot.
+    pub fn disable_all(&self) {
+        self.DISABLE_IRQS[0].set(!0);
+        self.DISABLE_IRQS[1].set(!0);
+        self.DISABLE_BASIC_IRQS.set(!0);
+    }
+
+    /// Unmask a GPU interrupt line.
+    pub fn enable(&self, irq: u32) {
+        self.ENABLE_IRQS[(irq / 32) as usize].set(1 << (irq % 32));
+    }
+
+    /// Mask a GPU interrupt line.
+    pub fn disable(&self, irq: u32) {
+        self.DISABLE_IRQS[(irq / 32) as usize].set(1 << (irq % 32));
+    }
+
+    /// Pending GPU interrupts as a 64-bit mask.
+    pub fn pending(&self) -> u64 {
+        u64::from(self.IRQ_PENDING[1].get()) << 32 | u64::from(self.IRQ_PENDING[0].get())
+    }
+}
@@ -0,0 +1,799 @@
+// Flattened device tree parser.
+//
+// The firmware passes the DTB address in x0, arch boot code keeps it
+// for us. The blob is parsed in place, nothing is copied.
+//
+// See https://www.devicetree.org/specifications/ v0.2, chapter 5
+// "Flattened Devicetree (DTB) Format".
+
+use core::{slice, str};
+
+const FDT_MAGIC: u32 = 0xd00d_feed;
+/// Oldest format with size_dt_struct in the header.
+const FDT_MIN_VERSION: u32 = 17;
+const FDT_HEADER_SIZE: usize = 40;
+
+const FDT_BEGIN_NODE: u32 = 0x1;
+const FDT_END_NODE: u32 = 0x2;
+const FDT_PROP: u32 = 0x3;
+const FDT_NOP: u32 = 0x4;
+const FDT_END: u32 = 0x9;
+
+/// Defaults when a node doesn't specify them, per the spec.
+const DEFAULT_ADDRESS_CELLS: u32 = 2;
+const DEFAULT_SIZE_CELLS: u32 = 1;
+
+/// Node nesting supported by `DeviceTree::nodes()`.
+const MAX_DEPTH: usize = 16;
+
+#[derive(Debug)]
+pub enum DtError {
+    NoDeviceTree,
+    BadMagic(u32),
+    /// Blob version older than we parse.
+    UnsupportedVersion(u32),
+    /// Blob only readable by parsers of this newer version.
+    UnsupportedLastCompatibleVersion(u32),
+    Truncated,
+    BadToken(u32),
+}
+
+pub type Result<T> = ::core::result::Result<T, DtError>;
+
+static mut DEVICE_TREE: Option<DeviceTree<'static>> = None;
+
+/// Validate the blob at `address` and make it available via `get()`.
+///
+/// Called once on the boot core, before other cores are started.
+pub unsafe fn init(address: usize) -> Result<&'static DeviceTree<'static>> {
+    DEVICE_TREE = Some(DeviceTree::from_raw(address)?);
+    Ok(get().unwrap())
+}
+
+/// The device tree passed by firmware, if any.
+pub fn get() -> Option<&'static DeviceTree<'static>> {
+    unsafe { DEVICE_TREE.as_ref() }
+}
+
+#[inline]
+fn be32(bytes: &[u8], offset: usize) -> Option<u32> {
+    let b = bytes.get(offset..offset + 4)?;
+    Some(u32::from(b[0]) << 24 | u32::from(b[1]) << 16 | u32::from(b[2]) << 8 | u32::from(b[3]))
+}
+
+#[inline]
+fn be64(bytes: &[u8], offset: usize) -> Option<u64> {
+    Some(u64::from(be32(bytes, offset)?) << 32 | u64::from(be32(bytes, offset + 4)?))
+}
+
+/// Read a number spanning `cells` 32-bit cells, at most two are supported.
+fn read_cells(bytes: &[u8], offset: usize, cells: u32) -> Option<u64> {
+    match cells {
+        1 => be32(bytes, offset).map(u64::from),
+        2 => be64(bytes, offset),
+        _ => None,
+    }
+}
+
+#[inline]
+fn align4(offset: usize) -> usize {
+    (offset + 3) & !3
+}
+
+/// NUL-terminated string starting at `offset`.
+fn c_str(bytes: &[u8], offset: usize) -> Option<&str> {
+    let tail = bytes.get(offset..)?;
+    let len = tail.iter().position(|&b| b == 0)?;
+    str::from_utf8(&tail[..len]).ok()
+}
+
+/// A parsed flattened device tree.
+#[derive(Clone, Copy)]
+pub struct DeviceTree<'a> {
+    blob: &'a [u8],
+    structs: &'a [u8],
+    strings: &'a [u8],
+    reserved: &'a [u8],
+    boot_cpu: u32,
+}
+
+enum Token<'a> {
+    BeginNode(&'a str),
+    EndNode,
+    Prop(Property<'a>),
+    End,
+}
+
+impl<'a> DeviceTree<'a> {
+    /// Parse the blob at a physical address.
+    ///
+    /// The memory must stay mapped and unmodified for as long as the tree is used.
+    pub unsafe fn from_raw(address: usize) -> Result<DeviceTree<'static>> {
+        if address == 0 {
+            return Err(DtError::NoDeviceTree);
+        }
+        let header = slice::from_raw_parts(address as *const u8, FDT_HEADER_SIZE);
+        let magic = be32(header, 0).unwrap();
+        if magic != FDT_MAGIC {
+            return Err(DtError::BadMagic(magic));
+        }
+        let size = be32(header, 4).unwrap() as usize;
+        DeviceTree::new(slice::from_raw_parts(address as *const u8, size))
+    }
+
+    pub fn new(blob: &'a [u8]) -> Result<DeviceTree<'a>> {
+        let field = |offset| be32(blob, offset).ok_or(DtError::Truncated);
+
+        let magic = field(0)?;
+        if magic != FDT_MAGIC {
+            return Err(DtError::BadMagic(magic));
+        }
+        let version = field(20)?;
+        if version < FDT_MIN_VERSION {
+            return Err(DtError::UnsupportedVersion(version));
+        }
+        let last_compatible = field(24)?;
+        if last_compatible > FDT_MIN_VERSION {
+            return Err(DtError::UnsupportedLastCompatibleVersion(last_compatible));
+        }
+
+        let section = |offset: u32, size: u32| {
+            blob.get(offset as usize..offset as usize + size as usize)
+                .ok_or(DtError::Truncated)
+        };
+
+        Ok(DeviceTree {
+            blob,
+            structs: section(field(8)?, field(36)?)?,
+            strings: section(field(12)?, field(32)?)?,
+            reserved: blob.get(field(16)? as usize..).ok_or(DtError::Truncated)?,
+            boot_cpu: field(28)?,
+        })
+    }
+
+    /// Physical address of the blob.
+    pub fn address(&self) -> usize {
+        self.blob.as_ptr() as usize
+    }
+
+    /// Size of the blob in bytes.
+    pub fn size(&self) -> usize {
+        self.blob.len()
+    }
+
+    /// Physical id of the core we booted on.
+    pub fn boot_cpu(&self) -> u32 {
+        self.boot_cpu
+    }
+
+    fn token(&self, offset: &mut usize) -> Result<Token<'a>> {
+        loop {
+            let token = be32(self.structs, *offset).ok_or(DtError::Truncated)?;
+            *offset += 4;
+            match token {
+                FDT_BEGIN_NODE => {
+                    let name = c_str(self.structs, *offset).ok_or(DtError::Truncated)?;
+                    *offset = align4(*offset + name.len() + 1);
+                    return Ok(Token::BeginNode(name));
+                }
+                FDT_END_NODE => return Ok(Token::EndNode),
+                FDT_PROP => {
+                    let len = be32(self.structs, *offset).ok_or(DtError::Truncated)? as usize;
+                    let name_offset = be32(self.structs, *offset + 4).ok_or(DtError::Truncated)?;
+                    let value = self
+                        .structs
+                        .get(*offset + 8..*offset + 8 + len)
+                        .ok_or(DtError::Truncated)?;
+                    let name = c_str(self.strings, name_offset as usize).ok_or(DtError::Truncated)?;
+                    *offset = align4(*offset + 8 + len);
+                    return Ok(Token::Prop(Property { name, value }));
+                }
+                FDT_NOP => continue,
+                FDT_END => return Ok(Token::End),
+                other => return Err(DtError::BadToken(other)),
+            }
+        }
+    }
+
+    /// The root node.
+    pub fn root(&'a self) -> Result<Node<'a>> {
+        let mut offset = 0;
+        match self.token(&mut offset)? {
+            Token::BeginNode(name) => Ok(Node {
+                tree: self,
+                name,
+                offset,
+                address_cells: DEFAULT_ADDRESS_CELLS,
+                size_cells: DEFAULT_SIZE_CELLS,
+            }),
+            _ => Err(DtError::BadToken(be32(self.structs, 0).unwrap_or(0))),
+        }
+    }
+
+    /// Look a node up by its full path, e.g. "/soc" or "/cpus/cpu@1".
+    ///
+    /// Path components may omit the unit address if it's unambiguous.
+    pub fn find_node(&'a self, path: &str) -> Option<Node<'a>> {
+        path.split('/')
+            .filter(|component| !component.is_empty())
+            .fold(self.root().ok(), |node, component| node?.child(component))
+    }
+
+    /// All nodes in depth-first order.
+    pub fn nodes(&'a self) -> Nodes<'a> {
+        Nodes {
+            tree: self,
+            offset: 0,
+            depth: 0,
+            cells: [(DEFAULT_ADDRESS_CELLS, DEFAULT_SIZE_CELLS); MAX_DEPTH],
+            done: false,
+        }
+    }
+
+    /// First node compatible with `compatible`.
+    pub fn find_compatible(&'a self, compatible: &str) -> Option<Node<'a>> {
+        self.nodes().find(|node| node.is_compatible(compatible))
+    }
+
+    /// RAM regions from all memory nodes.
+    pub fn memory(&'a self) -> impl Iterator<Item = Region> + 'a {
+        self.nodes()
+            .filter(|node| {
+                node.property("device_type")
+                    .and_then(|p| p.as_str())
+                    .map_or(false, |t| t == "memory")
+            })
+            .flat_map(|node| node.reg().into_iter().flat_map(|reg| reg))
+    }
+
+    /// Memory reservation block entries, ranges the kernel must not use.
+    pub fn reserved(&'a self) -> impl Iterator<Item = Region> + 'a {
+        let reserved = self.reserved;
+        (0..)
+            .map(move |i| {
+                Some(Region {
+                    address: be64(reserved, i * 16)?,
+                    size: be64(reserved, i * 16 + 8)?,
+                })
+            })
+            .take_while(|entry| entry.map_or(false, |r| r.address != 0 || r.size != 0))
+            .filter_map(|entry| entry)
+    }
+
+    /// Kernel command line from /chosen.
+    pub fn bootargs(&'a self) -> Option<&'a str> {
+        self.find_node("/chosen")?.property("bootargs")?.as_str()
+    }
+
+    /// Translate a /soc bus address into a CPU physical address.
+    pub fn translate_soc_address(&'a self, address: u64) -> Option<u64> {
+        self.find_node("/soc")?
+            .ranges()?
+            .filter_map(|range| range.translate(address))
+            .next()
+    }
+
+    /// Size of the /soc range containing `address`.
+    pub fn soc_range_size(&'a self, address: u64) -> Option<u64> {
+        self.find_node("/soc")?
+            .ranges()?
+            .find(|range| range.translate(address).is_some())
+            .map(|range| range.size)
+    }
+}
+
+/// Depth-first iterator over all nodes of a tree.
+pub struct Nodes<'a> {
+    tree: &'a DeviceTree<'a>,
+    offset: usize,
+    depth: usize,
+    /// #address-cells and #size-cells declared at each depth.
+    cells: [(u32, u32); MAX_DEPTH],
+    done: bool,
+}
+
+impl<'a> Iterator for Nodes<'a> {
+    type Item = Node<'a>;
+
+    fn next(&mut self) -> Option<Node<'a>> {
+        while !self.done {
+            match self.tree.token(&mut self.offset) {
+                Ok(Token::BeginNode(name)) if self.depth < MAX_DEPTH => {
+                    // A node's reg is interpreted with its parent's cells.
+                    let (address_cells, size_cells) = if self.depth > 0 {
+                        self.cells[self.depth - 1]
+                    } else {
+                        (DEFAULT_ADDRESS_CELLS, DEFAULT_SIZE_CELLS)
+                    };
+                    self.cells[self.depth] = (DEFAULT_ADDRESS_CELLS, DEFAULT_SIZE_CELLS);
+                    self.depth += 1;
+                    return Some(Node {
+                        tree: self.tree,
+                        name,
+                        offset: self.offset,
+                        address_cells,
+                        size_cells,
+                    });
+                }
+                Ok(Token::Prop(prop)) if self.depth > 0 => match prop.name {
+                    "#address-cells" => {
+                        self.cells[self.depth - 1].0 = prop.as_u32().unwrap_or(DEFAULT_ADDRESS_CELLS)
+                    }
+                    "#size-cells" => {
+                        self.cells[self.depth - 1].1 = prop.as_u32().unwrap_or(DEFAULT_SIZE_CELLS)
+                    }
+                    _ => {}
+                },
+                Ok(Token::EndNode) if self.depth > 0 => self.depth -= 1,
+                Ok(Token::Prop(_)) => {}
+                // End of tree, malformed tree or nesting too deep.
+                _ => self.done = true,
+            }
+        }
+        None
+    }
+}
+
+/// A node of the tree.
+#[derive(Clone, Copy)]
+pub struct Node<'a> {
+    tree: &'a DeviceTree<'a>,
+    name: &'a str,
+    /// Offset of the first token after the node name.
+    offset: usize,
+    /// Cells used by this node's reg, declared by the parent.
+    address_cells: u32,
+    size_cells: u32,
+}
+
+impl<'a> Node<'a> {
+    /// Full node name, including the unit address.
+    pub fn name(&self) -> &'a str {
+        self.name
+    }
+
+    /// Node name without the unit address.
+    pub fn base_name(&self) -> &'a str {
+        self.name.split('@').next().unwrap_or(self.name)
+    }
+
+    pub fn properties(&self) -> Properties<'a> {
+        Properties {
+            tree: self.tree,
+            offset: self.offset,
+        }
+    }
+
+    pub fn property(&self, name: &str) -> Option<Property<'a>> {
+        self.properties().find(|p| p.name == name)
+    }
+
+    /// Direct children of the node.
+    pub fn children(&self) -> Children<'a> {
+        let cells = |name, default| self.property(name).and_then(|p| p.as_u32()).unwrap_or(default);
+        Children {
+            tree: self.tree,
+            offset: self.offset,
+            depth: 0,
+            address_cells: cells("#address-cells", DEFAULT_ADDRESS_CELLS),
+            size_cells: cells("#size-cells", DEFAULT_SIZE_CELLS),
+        }
+    }
+
+    /// Child matching `name` exactly or by base name.
+    pub fn child(&self, name: &str) -> Option<Node<'a>> {
+        self.children()
+            .find(|node| node.name == name || (!name.contains('@') && node.base_name() == name))
+    }
+
+    pub fn is_compatible(&self, compatible: &str) -> bool {
+        self.property("compatible")
+            .map_or(false, |p| p.strings().any(|s| s == compatible))
+    }
+
+    /// (address, size) pairs of the reg property.
+    pub fn reg(&self) -> Option<Reg<'a>> {
+        let value = self.property("reg")?.value;
+        Some(Reg {
+            value,
+            offset: 0,
+            address_cells: self.address_cells,
+            size_cells: self.size_cells,
+        })
+    }
+
+    /// First region of the reg property.
+    pub fn first_reg(&self) -> Option<Region> {
+        self.reg()?.next()
+    }
+
+    /// Address translations to the parent bus from the ranges property.
+    ///
+    /// Empty ranges means identity mapping and yields nothing.
+    pub fn ranges(&self) -> Option<Ranges<'a>> {
+        let value = self.property("ranges")?.value;
+        let children = self.children();
+        Some(Ranges {
+            value,
+            offset: 0,
+            child_cells: children.address_cells,
+            parent_cells: self.address_cells,
+            size_cells: children.size_cells,
+        })
+    }
+}
+
+pub struct Children<'a> {
+    tree: &'a DeviceTree<'a>,
+    offset: usize,
+    depth: usize,
+    address_cells: u32,
+    size_cells: u32,
+}
+
+impl<'a> Iterator for Children<'a> {
+    type Item = Node<'a>;
+
+    fn next(&mut self) -> Option<Node<'a>> {
+        loop {
+            match self.tree.token(&mut self.offset).ok()? {
+                Token::BeginNode(name) => {
+                    self.depth += 1;
+                    if self.depth == 1 {
+                        return Some(Node {
+                            tree: self.tree,
+                            name,
+                            offset: self.offset,
+                            address_cells: self.address_cells,
+                            size_cells: self.size_cells,
+                        });
+                    }
+                }
+                Token::EndNode if self.depth == 0 => return None, // end of our node
+                Token::EndNode => self.depth -= 1,
+                Token::Prop(_) => {}
+                Token::End => return None,
+            }
+        }
+    }
+}
+
+pub struct Properties<'a> {
+    tree: &'a DeviceTree<'a>,
+    offset: usize,
+}
+
+impl<'a> Iterator for Properties<'a> {
+    type Item = Property<'a>;
+
+    fn next(&mut self) -> Option<Property<'a>> {
+        // Properties always precede child nodes.
+        match self.tree.token(&mut self.offset).ok()? {
+            Token::Prop(prop) => Some(prop),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
+pub struct Property<'a> {
+    pub name: &'a str,
+    pub value: &'a [u8],
+}
+
+impl<'a> Property<'a> {
+    /// Value as a single string.
+    pub fn as_str(&self) -> Option<&'a str> {
+        c_str(self.value, 0)
+    }
+
+    /// Value as a list of strings, e.g. compatible.
+    pub fn strings(&self) -> impl Iterator<Item = &'a str> {
+        self.value
+            .split(|&b| b == 0)
+            .filter(|s| !s.is_empty())
+            .filter_map(|s| str::from_utf8(s).ok())
+    }
+
+    pub fn as_u32(&self) -> Option<u32> {
+        be32(self.value, 0)
+    }
+
+    pub fn as_u64(&self) -> Option<u64> {
+        match self.value.len() {
+            4 => self.as_u32().map(u64::from),
+            _ => be64(self.value, 0),
+        }
+    }
+
+    /// The `index`-th 32-bit cell.
+    pub fn cell(&self, index: usize) -> Option<u32> {
+        be32(self.value, index * 4)
+    }
+}
+
+/// A physical memory region.
+#[derive(Clone, Copy, Debug)]
+pub struct Region {
+    pub address: u64,
+    pub size: u64,
+}
+
+impl Region {
+    pub fn end(&self) -> u64 {
+        self.address + self.size
+    }
+}
+
+pub struct Reg<'a> {
+    value: &'a [u8],
+    offset: usize,
+    address_cells: u32,
+    size_cells: u32,
+}
+
+impl<'a> Iterator for Reg<'a> {
+    type Item = Region;
+
+    fn next(&mut self) -> Option<Region> {
+        let address = read_cells(self.value, self.offset, self.address_cells)?;
+        self.offset += self.address_cells as usize * 4;
+        let size = match self.size_cells {
+            0 => 0,
+            cells => read_cells(self.value, self.offset, cells)?,
+        };
+        self.offset += self.size_cells as usize * 4;
+        Some(Region { address, size })
+    }
+}
+
+/// One entry of a ranges property.
+#[derive(Clone, Copy, Debug)]
+pub struct Range {
+    pub child: u64,
+    pub parent: u64,
+    pub size: u64,
+}
+
+impl Range {
+    /// Parent bus address for a child bus address inside this range.
+    pub fn translate(&self, address: u64) -> Option<u64> {
+        if address >= self.child && address - self.child < self.size {
+            Some(self.parent + (address - self.child))
+        } else {
+            None
+        }
+    }
+}
+
+pub struct Ranges<'a> {
+    value: &'a [u8],
+    offset: usize,
+    child_cells: u32,
+    parent_cells: u32,
+    size_cells: u32,
+}
+
+impl<'a> Iterator for Ranges<'a> {
+    type Item = Range;
+
+    fn next(&mut self) -> Option<Range> {
+        let child = read_cells(self.value, self.offset, self.child_cells)?;
+        self.offset += self.child_cells as usize * 4;
+        let parent = read_cells(self.value, self.offset, self.parent_cells)?;
+        self.offset += self.parent_cells as usize * 4;
+        let size = read_cells(self.value, self.offset, self.size_cells)?;
+        self.offset += self.size_cells as usize * 4;
+        Some(Range {
+            child,
+            parent,
+            size,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use self::std::vec::Vec;
+    use super::*;
+
+    fn push32(bytes: &mut Vec<u8>, value: u32) {
+        bytes.extend_from_slice(&[
+            (value >> 24) as u8,
+            (value >> 16) as u8,
+            (value >> 8) as u8,
+            value as u8,
+        ]);
+    }
+
+    fn push64(bytes: &mut Vec<u8>, value: u64) {
+        push32(bytes, (value >> 32) as u32);
+        push32(bytes, value as u32);
+    }
+
+    fn pad4(bytes: &mut Vec<u8>) {
+        let len = align4(bytes.len());
+        bytes.resize(len, 0);
+    }
+
+    /// Writes blobs the way dtc lays them out.
+    #[derive(Default)]
+    struct Builder {
+        structs: Vec<u8>,
+        strings: Vec<u8>,
+        reserved: Vec<(u64, u64)>,
+    }
+
+    impl Builder {
+        fn begin(&mut self, name: &str) -> &mut Builder {
+            push32(&mut self.structs, FDT_BEGIN_NODE);
+            self.structs.extend_from_slice(name.as_bytes());
+            self.structs.push(0);
+            pad4(&mut self.structs);
+            self
+        }
+
+        fn prop(&mut self, name: &str, value: &[u8]) -> &mut Builder {
+            let name_offset = self.strings.len() as u32;
+            self.strings.extend_from_slice(name.as_bytes());
+            self.strings.push(0);
+            push32(&mut self.structs, FDT_PROP);
+            push32(&mut self.structs, value.len() as u32);
+            push32(&mut self.structs, name_offset);
+            self.structs.extend_from_slice(value);
+            pad4(&mut self.structs);
+            self
+        }
+
+        fn prop_u32(&mut self, name: &str, value: u32) -> &mut Builder {
+            let mut bytes = Vec::new();
+            push32(&mut bytes, value);
+            self.prop(name, &bytes)
+        }
+
+        fn end(&mut self) -> &mut Builder {
+            push32(&mut self.structs, FDT_END_NODE);
+            self
+        }
+
+        fn build(&mut self, version: u32, last_compatible: u32) -> Vec<u8> {
+            push32(&mut self.structs, FDT_END);
+            let reserved_offset = FDT_HEADER_SIZE;
+            let structs_offset = reserved_offset + (self.reserved.len() + 1) * 16;
+            let strings_offset = structs_offset + self.structs.len();
+            let total = strings_offset + self.strings.len();
+
+            let mut blob = Vec::new();
+            for &field in [
+                FDT_MAGIC,
+                total as u32,
+                structs_offset as u32,
+                strings_offset as u32,
+                reserved_offset as u32,
+                version,
+                last_compatible,
+                2, // boot cpu
+                self.strings.len() as u32,
+                self.structs.len() as u32,
+            ]
+            .iter()
+            {
+                push32(&mut blob, field);
+            }
+            for &(address, size) in self.reserved.iter().chain(&[(0, 0)]) {
+                push64(&mut blob, address);
+                push64(&mut blob, size);
+            }
+            blob.extend_from_slice(&self.structs);
+            blob.extend_from_slice(&self.strings);
+            blob
+        }
+    }
+
+    /// RPi3-like tree: one memory node with 1-cell addresses and sizes.
+    fn rpi3_blob() -> Vec<u8> {
+        let mut reg = Vec::new();
+        push32(&mut reg, 0);
+        push32(&mut reg, 0x3b40_0000);
+
+        let mut builder = Builder::default();
+        builder.reserved.push((0, 0x1000));
+        builder
+            .begin("")
+            .prop_u32("#address-cells", 1)
+            .prop_u32("#size-cells", 1)
+            .begin("chosen")
+            .prop("bootargs", b"console=serial0\0")
+            .end()
+            .begin("memory@0")
+            .prop("device_type", b"memory\0")
+            .prop("reg", &reg)
+            .end()
+            .end()
+            .build(17, 16)
+    }
+
+    #[test]
+    fn header() {
+        let blob = rpi3_blob();
+        let dt = DeviceTree::new(&blob).unwrap();
+        assert_eq!(dt.size(), blob.len());
+        assert_eq!(dt.boot_cpu(), 2);
+        assert_eq!(dt.bootargs(), Some("console=serial0"));
+    }
+
+    #[test]
+    fn bad_magic() {
+        let mut blob = rpi3_blob();
+        blob[0] = 0;
+        match DeviceTree::new(&blob) {
+            Err(DtError::BadMagic(magic)) => assert_eq!(magic, 0x000d_feed),
+            _ => panic!("bad magic accepted"),
+        }
+    }
+
+    #[test]
+    fn unsupported_versions() {
+        let blob = Builder::default().begin("").end().build(16, 16);
+        match DeviceTree::new(&blob) {
+            Err(DtError::UnsupportedVersion(16)) => {}
+            _ => panic!("version 16 accepted"),
+        }
+
+        let blob = Builder::default().begin("").end().build(18, 18);
+        match DeviceTree::new(&blob) {
+            Err(DtError::UnsupportedLastCompatibleVersion(18)) => {}
+            _ => panic!("last compatible version 18 accepted"),
+        }
+    }
+
+    #[test]
+    fn truncated() {
+        let blob = rpi3_blob();
+        for &len in [0, 4, FDT_HEADER_SIZE - 1, blob.len() - 1].iter() {
+            match DeviceTree::new(&blob[..len]) {
+                Err(DtError::Truncated) => {}
+                _ => panic!("blob cut to {} bytes accepted", len),
+            }
+        }
+    }
+
+    #[test]
+    fn memory_and_reserved() {
+        let blob = rpi3_blob();
+        let dt = DeviceTree::new(&blob).unwrap();
+
+        let memory: Vec<Region> = dt.memory().collect();
+        assert_eq!(memory.len(), 1);
+        assert_eq!((memory[0].address, memory[0].size), (0, 0x3b40_0000));
+
+        let reserved: Vec<Region> = dt.reserved().collect();
+        assert_eq!(reserved.len(), 1);
+        assert_eq!((reserved[0].address, reserved[0].size), (0, 0x1000));
+    }
+
+    #[test]
+    fn memory_with_default_cells() {
+        // Without #address-cells and #size-cells reg uses 2 and 1 cells.
+        let mut reg = Vec::new();
+        push64(&mut reg, 0x1_0000_0000);
+        push32(&mut reg, 0x4000_0000);
+
+        let blob = Builder::default()
+            .begin("")
+            .begin("memory@100000000")
+            .prop("device_type", b"memory\0")
+            .prop("reg", &reg)
+            .end()
+            .end()
+            .build(17, 16);
+        let dt = DeviceTree::new(&blob).unwrap();
+
+        let memory: Vec<Region> = dt.memory().collect();
+        assert_eq!(memory.len(), 1);
+        assert_eq!(memory[0].address, 0x1_0000_0000);
+        assert_eq!(memory[0].end(), 0x1_4000_0000);
+    }
+}
@@ -1,3 +1,5 @@
+use arch::cache;
+
 /* Character cells are 8x8 */
 pub const CHARSIZE_X: u32 = 8;
 pub const CHARSIZE_Y: u32 = 8;
@@ -123,6 +125,21 @@ impl Display {
         self.write_pixel_component(x, y, 2, (color >> 16) & 0xff);
     }
 
+    /// Physical address of the framebuffer.
+    pub fn address(&self) -> usize {
+        self.base as usize
+    }
+
+    /// Size of the framebuffer in bytes.
+    pub fn size(&self) -> usize {
+        self.size as usize
+    }
+
+    /// Write drawn pixels back to memory for the VideoCore to scan out.
+    pub fn flush(&self) {
+        cache::clean_range(self.base as usize, self.size as usize);
+    }
+
     pub fn rect(&mut self, x1: u32, y1: u32, x2: u32, y2: u32, color: u32) {
         for y in y1..y2 {
             for x in x1..x2 {
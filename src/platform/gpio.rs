@@ -1,7 +1,12 @@
-use platform::rpi3::PERIPHERAL_BASE;
+use platform::rpi3::BcmHost;
 use register::mmio::*;
 
-const GPIO_BASE: u32 = PERIPHERAL_BASE + 0x20_0000;
+// Offset from the peripheral base.
+const GPIO_OFFSET: usize = 0x20_0000;
+
+fn gpio_base() -> usize {
+    BcmHost::get_peripheral_address() + GPIO_OFFSET
+}
 
 // The offsets for reach register.
 // From https://wiki.osdev.org/Raspberry_Pi_Bare_Bones
@@ -86,12 +91,16 @@ register_bitfields! {
     ]
 }
 
-pub const GPFSEL1: *const ReadWrite<u32, GPFSEL1::Register> =
-    (GPIO_BASE + 0x04) as *const ReadWrite<u32, GPFSEL1::Register>;
+pub fn gpfsel1() -> *const ReadWrite<u32, GPFSEL1::Register> {
+    (gpio_base() + 0x04) as *const ReadWrite<u32, GPFSEL1::Register>
+}
 
 /// Controls actuation of pull up/down to ALL GPIO pins.
-pub const GPPUD: *const ReadWrite<u32> = (GPIO_BASE + 0x94) as *const ReadWrite<u32>;
+pub fn gppud() -> *const ReadWrite<u32> {
+    (gpio_base() + 0x94) as *const ReadWrite<u32>
+}
 
 /// Controls actuation of pull up/down for specific GPIO pin.
-pub const GPPUDCLK0: *const ReadWrite<u32, GPPUDCLK0::Register> =
-    (GPIO_BASE + 0x98) as *const ReadWrite<u32, GPPUDCLK0::Register>;
+pub fn gppudclk0() -> *const ReadWrite<u32, GPPUDCLK0::Register> {
+    (gpio_base() + 0x98) as *const ReadWrite<u32, GPPUDCLK0::Register>
+}
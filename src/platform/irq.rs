@@ -0,0 +1,182 @@
+use arch::{read_cpu_id, sync::SpinLock, timer};
+use platform::{
+    armctrl::{ArmInterruptController, GPU_IRQ_COUNT},
+    local_ic::{LocalInterruptController, LocalIrq, LOCAL_IRQ_COUNT},
+};
+
+// Interrupt handler registration and dispatch for RPi3.
+//
+// Interrupts arrive at each core through the BCM2836 local controller.
+// One of its sources is the BCM2835 ARMCTRL, which multiplexes all
+// GPU peripheral interrupts and is routed to the boot core.
+
+/// An interrupt line a handler can be attached to.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Irq {
+    /// Per-core source, handled on the core it fired on.
+    Local(LocalIrq),
+    /// GPU peripheral line 0-63.
+    Gpu(u32),
+}
+
+pub type Handler = fn(Irq);
+
+#[derive(Debug)]
+pub enum IrqError {
+    InvalidIrq,
+    AlreadyRegistered,
+    NotRegistered,
+}
+
+pub type Result<T> = ::core::result::Result<T, IrqError>;
+
+/// Core that receives GPU interrupts.
+const GPU_IRQ_CORE: usize = 0;
+
+/// Core mailbox used to interrupt other cores.
+const KICK_MAILBOX: usize = 0;
+
+// Handler tables, filled before the lines are unmasked.
+// Local handlers are shared by all cores.
+struct Handlers {
+    local: [Option<Handler>; LOCAL_IRQ_COUNT],
+    gpu: [Option<Handler>; GPU_IRQ_COUNT],
+}
+
+impl Handlers {
+    fn slot(&mut self, irq: Irq) -> Result<&mut Option<Handler>> {
+        match irq {
+            Irq::Local(LocalIrq::Gpu) => Err(IrqError::InvalidIrq), // cascaded, not a device
+            Irq::Local(local) => Ok(&mut self.local[local as usize]),
+            Irq::Gpu(line) if (line as usize) < GPU_IRQ_COUNT => Ok(&mut self.gpu[line as usize]),
+            Irq::Gpu(_) => Err(IrqError::InvalidIrq),
+        }
+    }
+}
+
+// Changed from system calls on any core while others dispatch interrupts.
+static HANDLERS: SpinLock<Handlers> = SpinLock::new(Handlers {
+    local: [None; LOCAL_IRQ_COUNT],
+    gpu: [None; GPU_IRQ_COUNT],
+});
+
+/// Handler attached to the line, copied out so it runs without the lock.
+fn handler(irq: Irq) -> Result<Option<Handler>> {
+    Ok(*HANDLERS.lock_irqsave().slot(irq)?)
+}
+
+/// Mask all interrupts, route GPU interrupts to the boot core and
+/// attach the generic timer. Called once on the boot core.
+pub fn init() {
+    let armctrl = ArmInterruptController::new();
+    armctrl.disable_all();
+
+    let local = LocalInterruptController::new();
+    local.route_gpu_irqs(GPU_IRQ_CORE);
+
+    register(Irq::Local(LocalIrq::CntPns), |_| timer::handle_interrupt());
+    // Kicks only make the core enter the kernel.
+    register(Irq::Local(LocalIrq::Mailbox0), |_| {
+        LocalInterruptController::new().mailbox_take(read_cpu_id() as usize, KICK_MAILBOX);
+    });
+
+    init_per_core();
+}
+
+/// Unmask per-core lines with attached handlers on the calling core.
+pub fn init_per_core() {
+    enable(Irq::Local(LocalIrq::CntPns));
+    enable(Irq::Local(LocalIrq::Mailbox0));
+}
+
+/// Interrupt `core`, making it enter the kernel if it runs at EL0.
+pub fn kick(core: usize) {
+    LocalInterruptController::new().mailbox_send(core, KICK_MAILBOX, 1);
+}
+
+/// Attach a handler to an interrupt line. The line stays masked until `enable()`.
+pub fn register(irq: Irq, handler: Handler) -> Result<()> {
+    let mut handlers = HANDLERS.lock_irqsave();
+    let slot = handlers.slot(irq)?;
+    if slot.is_some() {
+        return Err(IrqError::AlreadyRegistered);
+    }
+    *slot = Some(handler);
+    Ok(())
+}
+
+/// Detach the handler, masking the line first.
+pub fn unregister(irq: Irq) -> Result<()> {
+    disable(irq)?;
+    let mut handlers = HANDLERS.lock_irqsave();
+    let removed = handlers.slot(irq)?.take();
+    removed.ok_or(IrqError::NotRegistered).map(|_| ())
+}
+
+/// Unmask the line. Local lines are unmasked on the calling core only.
+pub fn enable(irq: Irq) -> Result<()> {
+    if handler(irq)?.is_none() {
+        return Err(IrqError::NotRegistered);
+    }
+    match irq {
+        Irq::Local(local) => {
+            if !LocalInterruptController::new().enable(read_cpu_id() as usize, local) {
+                return Err(IrqError::InvalidIrq);
+            }
+        }
+        Irq::Gpu(line) => ArmInterruptController::new().enable(line),
+    }
+    Ok(())
+}
+
+/// Mask the line. Local lines are masked on the calling core only.
+pub fn disable(irq: Irq) -> Result<()> {
+    handler(irq)?;
+    match irq {
+        Irq::Local(local) => {
+            if !LocalInterruptController::new().disable(read_cpu_id() as usize, local) {
+                return Err(IrqError::InvalidIrq);
+            }
+        }
+        Irq::Gpu(line) => ArmInterruptController::new().disable(line),
+    }
+    Ok(())
+}
+
+fn handle(irq: Irq) {
+    match handler(irq) {
+        Ok(Some(handler)) => handler(irq),
+        // Nobody is listening, mask it to avoid an interrupt storm.
+        _ => {
+            disable(irq);
+        }
+    }
+}
+
+/// Call handlers of all pending interrupts of the calling core.
+///
+/// Invoked from the IRQ exception vector.
+pub fn dispatch() {
+    let core = read_cpu_id() as usize;
+
+    let mut sources = LocalInterruptController::new().pending(core);
+    while sources != 0 {
+        let bit = sources.trailing_zeros();
+        sources &= !(1 << bit);
+
+        match LocalIrq::from_source_bit(bit) {
+            Some(LocalIrq::Gpu) => dispatch_gpu(),
+            Some(local) => handle(Irq::Local(local)),
+            None => {}
+        }
+    }
+}
+
+fn dispatch_gpu() {
+    let mut pending = ArmInterruptController::new().pending();
+    while pending != 0 {
+        let line = pending.trailing_zeros();
+        pending &= !(1 << line);
+        handle(Irq::Gpu(line));
+    }
+}
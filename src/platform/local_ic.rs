@@ -0,0 +1,180 @@
+use core::ops;
+use platform::rpi3::BcmHost;
+use register::mmio::*;
+
+// BCM2836 ARM local peripherals: per-core interrupt routing for the
+// generic timers, core mailboxes, PMU and the local timer.
+//
+// See https://www.raspberrypi.org/documentation/hardware/raspberrypi/bcm2836/QA7_rev3.4.pdf
+
+#[allow(non_snake_case)]
+#[repr(C)]
+pub struct RegisterBlock {
+    CONTROL: ReadWrite<u32>,                               // 0x00
+    __reserved_0: u32,                                     // 0x04
+    CORE_TIMER_PRESCALER: ReadWrite<u32>,                  // 0x08
+    GPU_INTERRUPTS_ROUTING: ReadWrite<u32>,                // 0x0C
+    PMU_INTERRUPTS_ROUTING_SET: WriteOnly<u32>,            // 0x10
+    PMU_INTERRUPTS_ROUTING_CLEAR: WriteOnly<u32>,          // 0x14
+    __reserved_1: u32,                                     // 0x18
+    CORE_TIMER_LS: ReadOnly<u32>,                          // 0x1C
+    CORE_TIMER_MS: ReadOnly<u32>,                          // 0x20
+    LOCAL_INTERRUPT_ROUTING: ReadWrite<u32>,               // 0x24
+    __reserved_2: u32,                                     // 0x28
+    AXI_OUTSTANDING_COUNTERS: ReadOnly<u32>,               // 0x2C
+    AXI_OUTSTANDING_IRQ: ReadWrite<u32>,                   // 0x30
+    LOCAL_TIMER_CONTROL: ReadWrite<u32>,                   // 0x34
+    LOCAL_TIMER_WRITE_FLAGS: WriteOnly<u32>,               // 0x38
+    __reserved_3: u32,                                     // 0x3C
+    CORE_TIMERS_INTERRUPT_CONTROL: [ReadWrite<u32>; 4],    // 0x40
+    CORE_MAILBOXES_INTERRUPT_CONTROL: [ReadWrite<u32>; 4], // 0x50
+    CORE_IRQ_SOURCE: [ReadOnly<u32>; 4],                   // 0x60
+    CORE_FIQ_SOURCE: [ReadOnly<u32>; 4],                   // 0x70
+    CORE_MAILBOX_WRITE_SET: [[WriteOnly<u32>; 4]; 4],      // 0x80
+    CORE_MAILBOX_READ_CLEAR: [[ReadWrite<u32>; 4]; 4],     // 0xC0
+}
+
+/// Per-core interrupt sources, bit numbers in the core IRQ source register.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum LocalIrq {
+    /// Secure physical timer
+    CntPs = 0,
+    /// Non-secure physical timer, used by arch::timer
+    CntPns = 1,
+    /// Hypervisor physical timer
+    CntHp = 2,
+    /// Virtual timer
+    CntV = 3,
+    Mailbox0 = 4,
+    Mailbox1 = 5,
+    Mailbox2 = 6,
+    Mailbox3 = 7,
+    /// One or more ARMCTRL interrupts pending, see platform::armctrl
+    Gpu = 8,
+    Pmu = 9,
+    AxiOutstanding = 10,
+    LocalTimer = 11,
+}
+
+pub const LOCAL_IRQ_COUNT: usize = 12;
+
+impl LocalIrq {
+    pub fn from_source_bit(bit: u32) -> Option<LocalIrq> {
+        use self::LocalIrq::*;
+        Some(match bit {
+            0 => CntPs,
+            1 => CntPns,
+            2 => CntHp,
+            3 => CntV,
+            4 => Mailbox0,
+            5 => Mailbox1,
+            6 => Mailbox2,
+            7 => Mailbox3,
+            8 => Gpu,
+            9 => Pmu,
+            10 => AxiOutstanding,
+            11 => LocalTimer,
+            _ => return None,
+        })
+    }
+}
+
+pub struct LocalInterruptController;
+
+/// Deref to RegisterBlock
+///
+/// Allows writing
+/// ```
+/// self.CONTROL.get()
+/// ```
+/// instead of something along the lines of
+/// ```
+/// unsafe { (*LocalInterruptController::ptr()).CONTROL.get() }
+/// ```
+impl ops::Deref for LocalInterruptController {
+    type Target = RegisterBlock;
+
+    fn deref(&self) -> &Self::Target {
+        unsafe { &*Self::ptr() }
+    }
+}
+
+impl LocalInterruptController {
+    pub fn new() -> LocalInterruptController {
+        LocalInterruptController
+    }
+
+    /// Returns a pointer to the register block
+    fn ptr() -> *const RegisterBlock {
+        BcmHost::get_local_peripheral_address() as *const _
+    }
+
+    /// Route all GPU interrupts as IRQ to the given core.
+    pub fn route_gpu_irqs(&self, core: usize) {
+        self.GPU_INTERRUPTS_ROUTING.set(core as u32 & 0x3);
+    }
+
+    /// Pending interrupt sources of the given core.
+    pub fn pending(&self, core: usize) -> u32 {
+        self.CORE_IRQ_SOURCE[core].get()
+    }
+
+    /// Unmask a per-core interrupt as IRQ on the given core.
+    ///
+    /// Returns false if the source cannot be masked here.
+    pub fn enable(&self, core: usize, irq: LocalIrq) -> bool {
+        let bit = irq as u32;
+        match irq {
+            LocalIrq::CntPs | LocalIrq::CntPns | LocalIrq::CntHp | LocalIrq::CntV => {
+                let reg = &self.CORE_TIMERS_INTERRUPT_CONTROL[core];
+                reg.set(reg.get() | (1 << bit));
+                true
+            }
+            LocalIrq::Mailbox0 | LocalIrq::Mailbox1 | LocalIrq::Mailbox2 | LocalIrq::Mailbox3 => {
+                let reg = &self.CORE_MAILBOXES_INTERRUPT_CONTROL[core];
+                reg.set(reg.get() | (1 << (bit - 4)));
+                true
+            }
+            LocalIrq::Pmu => {
+                self.PMU_INTERRUPTS_ROUTING_SET.set(1 << core);
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Mask a per-core interrupt on the given core.
+    pub fn disable(&self, core: usize, irq: LocalIrq) -> bool {
+        let bit = irq as u32;
+        match irq {
+            LocalIrq::CntPs | LocalIrq::CntPns | LocalIrq::CntHp | LocalIrq::CntV => {
+                let reg = &self.CORE_TIMERS_INTERRUPT_CONTROL[core];
+                reg.set(reg.get() & !(1 << bit));
+                true
+            }
+            LocalIrq::Mailbox0 | LocalIrq::Mailbox1 | LocalIrq::Mailbox2 | LocalIrq::Mailbox3 => {
+                let reg = &self.CORE_MAILBOXES_INTERRUPT_CONTROL[core];
+                reg.set(reg.get() & !(1 << (bit - 4)));
+                true
+            }
+            LocalIrq::Pmu => {
+                self.PMU_INTERRUPTS_ROUTING_CLEAR.set(1 << core);
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Set bits in a core mailbox, raising its interrupt if enabled.
+    pub fn mailbox_send(&self, core: usize, mailbox: usize, bits: u32) {
+        self.CORE_MAILBOX_WRITE_SET[core][mailbox].set(bits);
+    }
+
+    /// Read and clear all bits of a core mailbox.
+    pub fn mailbox_take(&self, core: usize, mailbox: usize) -> u32 {
+        let reg = &self.CORE_MAILBOX_READ_CLEAR[core][mailbox];
+        let bits = reg.get();
+        reg.set(bits);
+        bits
+    }
+}
@@ -1,24 +1,25 @@
 use arch::*;
 
-use core::{fmt::Write, ops::Deref};
+use core::{fmt::Write, mem, ops::Deref};
 use platform::{
     display::Size2d,
-    rpi3::{phys2bus, PERIPHERAL_BASE},
+    rpi3::{phys2bus, BcmHost},
     uart::MiniUart,
 };
 use register::mmio::*;
 
 // Public interface to the mailbox
 #[repr(C)]
-#[repr(align(16))]
+#[repr(align(64))]
 pub struct Mailbox {
     // The address for buffer needs to be 16-byte aligned
     // so that the VideoCore can handle it properly.
+    // Cache line alignment keeps invalidation from touching neighbours.
     pub buffer: [u32; 36],
 }
 
-// Identity mapped first 1Gb by u-boot
-const MAILBOX_BASE: u32 = PERIPHERAL_BASE + 0xb880;
+// Offset from the peripheral base, identity mapped first 1Gb by arch::mmu
+pub const MAILBOX_OFFSET: usize = 0xb880;
 /* Lower 4-bits are channel ID */
 const CHANNEL_MASK: u32 = 0xf;
 
@@ -83,7 +84,7 @@ pub mod channel {
 
 // FrameBuffer channel supported structure - use with channel::FrameBuffer
 #[repr(C)]
-#[repr(align(16))]
+#[repr(align(64))]
 pub struct GpuFb {
     pub width: u32,
     pub height: u32,
@@ -190,7 +191,9 @@ pub mod alpha_mode {
     pub const IGNORED: u32 = 2;
 }
 
-fn write(regs: &RegisterBlock, buf_ptr: u32, channel: u32) -> Result<()> {
+// The VideoCore doesn't snoop our caches: buffers are cleaned before
+// being handed over and invalidated before reading the response.
+fn write(regs: &RegisterBlock, buf_ptr: u32, buf_len: usize, channel: u32) -> Result<()> {
     let mut count: u32 = 0;
 
 //    {
@@ -205,7 +208,7 @@ fn write(regs: &RegisterBlock, buf_ptr: u32, channel: u32) -> Result<()> {
             return Err(MboxError::Timeout);
         }
     }
-    dmb();
+    cache::clean_invalidate_range(buf_ptr as usize, buf_len);
     regs.WRITE
         .set(phys2bus(buf_ptr & !CHANNEL_MASK) | (channel & CHANNEL_MASK));
     Ok(())
@@ -279,15 +282,16 @@ impl Mailbox {
 
     /// Returns a pointer to the register block
     fn ptr() -> *const RegisterBlock {
-        MAILBOX_BASE as *const _
+        (BcmHost::get_peripheral_address() + MAILBOX_OFFSET) as *const _
     }
 
     pub fn write(&self, channel: u32) -> Result<()> {
-        write(self, self.buffer.as_ptr() as u32, channel)
+        write(self, self.buffer.as_ptr() as u32, mem::size_of_val(&self.buffer), channel)
     }
 
     pub fn read(&self, channel: u32) -> Result<()> {
         read(self, phys2bus(self.buffer.as_ptr() as u32), channel)?;
+        cache::invalidate_range(self.buffer.as_ptr() as usize, mem::size_of_val(&self.buffer));
 
         //let mut uart = MiniUart::new();
         //uart.init();
@@ -369,15 +373,17 @@ impl GpuFb {
 
     /// Returns a pointer to the register block
     fn ptr() -> *const RegisterBlock {
-        MAILBOX_BASE as *const _
+        (BcmHost::get_peripheral_address() + MAILBOX_OFFSET) as *const _
     }
 
     pub fn write(&self) -> Result<()> {
-        write(self, &self.width as *const u32 as u32, channel::FrameBuffer)
+        write(self, self as *const GpuFb as u32, mem::size_of::<GpuFb>(), channel::FrameBuffer)
     }
 
     pub fn read(&mut self) -> Result<()> {
-        read(self, 0, channel::FrameBuffer)
+        read(self, 0, channel::FrameBuffer)?;
+        cache::invalidate_range(self as *const GpuFb as usize, mem::size_of::<GpuFb>());
+        Ok(())
     }
 
     pub fn call(&mut self) -> Result<()> {
@@ -1,5 +1,9 @@
+pub mod armctrl;
+pub mod devicetree;
 pub mod display;
 pub mod gpio;
+pub mod irq;
+pub mod local_ic;
 pub mod mailbox;
 pub mod rpi3;
 pub mod uart;
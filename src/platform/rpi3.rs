@@ -1,7 +1,11 @@
+use arch::mmu::{HUGE_BLOCK_SIZE, PAGE_SIZE};
+use platform::{armctrl, devicetree, mailbox, uart};
+
 // See BCM2835-ARM-Peripherals.pdf
 // See https://www.raspberrypi.org/forums/viewtopic.php?t=186090 for more details.
 
 // Physical memory is 0x0000_0000 to 0x4000_0000
+// and is identity mapped by arch::mmu
 const fn phys2virt(address: u32) -> u32 {
     address // + 0x8000_0000;
 }
@@ -16,21 +20,104 @@ pub fn bus2phys(address: u32) -> u32 {
     address.wrapping_sub(0xC000_0000) // L2 cache disabled
 }
 
-// @todo use BcmHost::get_peripheral_address() instead
+// Defaults for RPi3, used until the device tree is probed or if there is none.
 pub const PERIPHERAL_BASE: u32 = phys2virt(0x3F00_0000); // Base address for all peripherals
+const PERIPHERAL_SIZE: u32 = 0x0100_0000;
+const LOCAL_PERIPHERAL_BASE: u32 = phys2virt(0x4000_0000);
+
+// VideoCore bus address of the peripherals, as used in /soc nodes.
+const PERIPHERAL_BUS_BASE: u64 = 0x7E00_0000;
+
+struct Peripherals {
+    base: usize,
+    size: usize,
+    local_base: usize,
+}
+
+// Written once by BcmHost::probe() on the boot core.
+static mut PERIPHERALS: Peripherals = Peripherals {
+    base: PERIPHERAL_BASE as usize,
+    size: PERIPHERAL_SIZE as usize,
+    local_base: LOCAL_PERIPHERAL_BASE as usize,
+};
 
 pub struct BcmHost;
 
 impl BcmHost {
+    /// Pick up peripheral addresses from the device tree.
+    ///
+    /// Must run before other cores start. Without a device tree
+    /// the RPi3 defaults stay in place, as they do for addresses outside
+    /// of what arch::mmu mapped as device memory from those defaults:
+    /// the rest of the first GiB and the GiB of local peripherals.
+    pub fn probe() {
+        let dt = match devicetree::get() {
+            Some(dt) => dt,
+            None => return,
+        };
+
+        let base = dt
+            .translate_soc_address(PERIPHERAL_BUS_BASE)
+            .unwrap_or(u64::from(PERIPHERAL_BASE));
+        let size = dt
+            .soc_range_size(PERIPHERAL_BUS_BASE)
+            .unwrap_or(u64::from(PERIPHERAL_SIZE));
+        let in_window = base >= u64::from(PERIPHERAL_BASE)
+            && base
+                .checked_add(size)
+                .map_or(false, |end| end <= HUGE_BLOCK_SIZE as u64);
+
+        let local_base = dt
+            .find_compatible("brcm,bcm2836-l1-intc")
+            .and_then(|node| node.first_reg())
+            .map(|local| local.address)
+            .and_then(|local| {
+                let block = u64::from(LOCAL_PERIPHERAL_BASE);
+                if local >= block && local < block + HUGE_BLOCK_SIZE as u64 {
+                    Some(local)
+                } else {
+                    None
+                }
+            });
+
+        unsafe {
+            if in_window {
+                PERIPHERALS.base = phys2virt(base as u32) as usize;
+                PERIPHERALS.size = size as usize;
+            }
+            if let Some(local_base) = local_base {
+                PERIPHERALS.local_base = phys2virt(local_base as u32) as usize;
+            }
+        }
+    }
+
     // As per https://www.raspberrypi.org/documentation/hardware/raspberrypi/peripheral_addresses.md
     /// This returns the ARM-side physical address where peripherals are mapped.
     pub fn get_peripheral_address() -> usize {
-        0x3f00_0000
+        unsafe { PERIPHERALS.base }
     }
 
     /// This returns the size of the peripherals' space.
     pub fn get_peripheral_size() -> usize {
-        0x0100_0000
+        unsafe { PERIPHERALS.size }
+    }
+
+    /// This returns the ARM-side physical address of BCM2836 local peripherals:
+    /// per-core interrupt controller, mailboxes and timers.
+    pub fn get_local_peripheral_address() -> usize {
+        unsafe { PERIPHERALS.local_base }
+    }
+
+    /// Pages of the peripheral window the kernel drives itself, in
+    /// ascending order: the interrupt controller, the VideoCore mailbox
+    /// in the same page, and the mini UART console.
+    pub fn get_kernel_peripheral_pages() -> [usize; 3] {
+        let page = |offset: usize| Self::get_peripheral_address() + (offset & !(PAGE_SIZE - 1));
+        [
+            page(armctrl::ARMCTRL_OFFSET),
+            page(mailbox::MAILBOX_OFFSET),
+            page(uart::UART1_OFFSET),
+        ]
     }
 
     /// This returns the bus address of the SDRAM.
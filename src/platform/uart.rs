@@ -1,33 +1,33 @@
-use arch::*;
+use arch::{sync::SpinLock, *};
 use core::ops;
-use platform::{gpio, rpi3::PERIPHERAL_BASE};
+use platform::{gpio, rpi3::BcmHost};
 use register::mmio::*;
 
-// The base address for UART.
-const UART0_BASE: u32 = PERIPHERAL_BASE + 0x20_1000;
-
-// The offsets for reach register for the UART.
-const UART0_DR: u32 = UART0_BASE + 0x00;
-const UART0_RSRECR: u32 = UART0_BASE + 0x04;
-const UART0_FR: u32 = UART0_BASE + 0x18;
-const UART0_ILPR: u32 = UART0_BASE + 0x20;
-const UART0_IBRD: u32 = UART0_BASE + 0x24;
-const UART0_FBRD: u32 = UART0_BASE + 0x28;
-const UART0_LCRH: u32 = UART0_BASE + 0x2C;
-const UART0_CR: u32 = UART0_BASE + 0x30;
-const UART0_IFLS: u32 = UART0_BASE + 0x34;
-const UART0_IMSC: u32 = UART0_BASE + 0x38;
-const UART0_RIS: u32 = UART0_BASE + 0x3C;
-const UART0_MIS: u32 = UART0_BASE + 0x40;
-const UART0_ICR: u32 = UART0_BASE + 0x44;
-const UART0_DMACR: u32 = UART0_BASE + 0x48;
-const UART0_ITCR: u32 = UART0_BASE + 0x80;
-const UART0_ITIP: u32 = UART0_BASE + 0x84;
-const UART0_ITOP: u32 = UART0_BASE + 0x88;
-const UART0_TDR: u32 = UART0_BASE + 0x8C;
-
-// Mini UART
-const UART1_BASE: u32 = PERIPHERAL_BASE + 0x21_5000;
+// The offset of the UART from the peripheral base.
+const UART0_OFFSET: u32 = 0x20_1000;
+
+// The offsets for reach register for the UART, from the peripheral base.
+const UART0_DR: u32 = UART0_OFFSET + 0x00;
+const UART0_RSRECR: u32 = UART0_OFFSET + 0x04;
+const UART0_FR: u32 = UART0_OFFSET + 0x18;
+const UART0_ILPR: u32 = UART0_OFFSET + 0x20;
+const UART0_IBRD: u32 = UART0_OFFSET + 0x24;
+const UART0_FBRD: u32 = UART0_OFFSET + 0x28;
+const UART0_LCRH: u32 = UART0_OFFSET + 0x2C;
+const UART0_CR: u32 = UART0_OFFSET + 0x30;
+const UART0_IFLS: u32 = UART0_OFFSET + 0x34;
+const UART0_IMSC: u32 = UART0_OFFSET + 0x38;
+const UART0_RIS: u32 = UART0_OFFSET + 0x3C;
+const UART0_MIS: u32 = UART0_OFFSET + 0x40;
+const UART0_ICR: u32 = UART0_OFFSET + 0x44;
+const UART0_DMACR: u32 = UART0_OFFSET + 0x48;
+const UART0_ITCR: u32 = UART0_OFFSET + 0x80;
+const UART0_ITIP: u32 = UART0_OFFSET + 0x84;
+const UART0_ITOP: u32 = UART0_OFFSET + 0x88;
+const UART0_TDR: u32 = UART0_OFFSET + 0x8C;
+
+// Mini UART, offset from the peripheral base
+pub const UART1_OFFSET: usize = 0x21_5000;
 
 #[allow(non_snake_case)]
 #[repr(C)]
@@ -121,6 +121,11 @@ register_bitfields! {
 
 pub struct MiniUart;
 
+/// Console shared between cores and interrupt handlers.
+///
+/// Lock with `lock_irqsave()` before writing.
+pub static CONSOLE: SpinLock<MiniUart> = SpinLock::new(MiniUart);
+
 /// Deref to RegisterBlock
 ///
 /// Allows writing
@@ -146,7 +151,7 @@ impl MiniUart {
 
     /// Returns a pointer to the register block
     fn ptr() -> *const RegisterBlock {
-        UART1_BASE as *const _
+        (BcmHost::get_peripheral_address() + UART1_OFFSET) as *const _
     }
 
     ///Set baud rate and characteristics (115200 8N1) and map to GPIO
@@ -163,17 +168,17 @@ impl MiniUart {
 
         // map UART1 to GPIO pins
         unsafe {
-            (*gpio::GPFSEL1).modify(gpio::GPFSEL1::FSEL14::TXD1 + gpio::GPFSEL1::FSEL15::RXD1);
+            (*gpio::gpfsel1()).modify(gpio::GPFSEL1::FSEL14::TXD1 + gpio::GPFSEL1::FSEL15::RXD1);
 
-            (*gpio::GPPUD).set(0); // enable pins 14 and 15
-            loop_delay(150);
+            (*gpio::gppud()).set(0); // enable pins 14 and 15
+            timer::delay_us(1); // at least 150 cycles for the control signal to settle
 
-            (*gpio::GPPUDCLK0).write(
+            (*gpio::gppudclk0()).write(
                 gpio::GPPUDCLK0::PUDCLK14::AssertClock + gpio::GPPUDCLK0::PUDCLK15::AssertClock,
             );
-            loop_delay(150);
+            timer::delay_us(1);
 
-            (*gpio::GPPUDCLK0).set(0);
+            (*gpio::gppudclk0()).set(0);
         }
 
         self.AUX_MU_CNTL
@@ -76,6 +76,33 @@ impl VC {
             order,
         ))
     }
+
+    /// Ask the firmware which part of RAM belongs to the ARM side,
+    /// the rest up to the end of SDRAM is VideoCore memory.
+    ///
+    /// Returns (base, size) in ARM physical addresses.
+    pub fn get_arm_memory() -> Option<(u32, u32)> {
+        let mut mbox = Mailbox::new();
+
+        mbox.buffer[0] = 8 * 4;
+        mbox.buffer[1] = mailbox::REQUEST;
+
+        mbox.buffer[2] = tag::GetArmMemory;
+        mbox.buffer[3] = 8;
+        mbox.buffer[4] = 0;
+        mbox.buffer[5] = 0; // Base address
+        mbox.buffer[6] = 0; // Size
+
+        mbox.buffer[7] = tag::End;
+
+        mbox.call(channel::PropertyTagsArmToVc).ok()?;
+
+        if (mbox.buffer[4] & VAL_LEN_FLAG) == 0 || mbox.buffer[6] == 0 {
+            return None;
+        }
+
+        Some((mbox.buffer[5], mbox.buffer[6]))
+    }
     /*
         fn get_display_size() -> Option<Size2d> {
             let mut mbox = Mbox::new();
@@ -0,0 +1,60 @@
+// mod sched::class
+
+//! Scheduling classes and their order.
+//!
+//! Threads of domains within their CPU reservation are guaranteed time and
+//! run earliest deadline first. Other threads run by priority, and so do
+//! domains which used up their reservation but may take extra time.
+//! Domains out of reserved time without extra time wait for their next
+//! period. See `objects::domain` for reservations.
+
+use objects::{domain, tcb::Tcb};
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Class {
+    Guaranteed { deadline: u64 },
+    BestEffort { priority: u8 },
+    Ineligible,
+}
+
+impl Class {
+    /// Class of `thread` at counter value `now`.
+    ///
+    /// Starts a new period of the thread's domain if the current one is over.
+    pub fn of(thread: &Tcb, now: u64) -> Class {
+        if !thread.is_runnable() {
+            return Class::Ineligible;
+        }
+        if let Some(domain) = domain::of(thread) {
+            if domain.has_reservation() {
+                domain.replenish(now);
+                if domain.remaining() > 0 {
+                    return Class::Guaranteed {
+                        deadline: domain.deadline(),
+                    };
+                }
+                if !domain.may_run_extra() {
+                    return Class::Ineligible;
+                }
+            }
+        }
+        Class::BestEffort {
+            priority: thread.priority(),
+        }
+    }
+
+    pub fn is_eligible(self) -> bool {
+        self != Class::Ineligible
+    }
+
+    /// Whether threads of this class run before those of `other`.
+    pub fn precedes(self, other: Class) -> bool {
+        match (self, other) {
+            (Class::Guaranteed { deadline: a }, Class::Guaranteed { deadline: b }) => a < b,
+            (Class::Guaranteed { .. }, _) => true,
+            (Class::BestEffort { priority: a }, Class::BestEffort { priority: b }) => a > b,
+            (Class::BestEffort { .. }, Class::Ineligible) => true,
+            _ => false,
+        }
+    }
+}
@@ -0,0 +1,453 @@
+// mod sched
+
+//! Thread scheduling and switching.
+//!
+//! A single queue of runnable threads is shared by all cores. Threads of
+//! domains within their CPU reservation run first, earliest deadline
+//! first, then the others by priority, see `class`. Threads of the same
+//! class take turns every TIME_SLICE_MS. Each core remembers the thread
+//! it is running, which is not queued.
+//!
+//! Each core's timer fires at the end of the running thread's time slice
+//! or reservation, whichever comes first. Periods of waiting domains are
+//! renewed when looked at, so a domain may start its new period up to a
+//! time slice late.
+//!
+//! Threads may run scheduling domains, which are upcalled when they get
+//! the CPU back after preemption, see `objects::domain`.
+//!
+//! The general purpose registers of a thread are saved into its TCB on
+//! every kernel entry. Its extended context is saved only when another
+//! thread is chosen to run on the core, FP/SIMD registers only if the
+//! thread used them, see `arch::context`. Its protection domain is loaded
+//! on every return to EL0, see `objects::vspace`.
+
+use arch::{
+    context::{disable_fpu_access, enable_fpu_access},
+    disable_irqs, enable_irqs,
+    fault::{FaultReport, Syndrome},
+    read_cpu_id,
+    smp::MAX_CPUS,
+    sync::SpinLock,
+    timer,
+    traps::{ExceptionOrigin, TrapFrame},
+    wait_for_interrupt, write_tpidrro_el0,
+};
+use caps::Capability;
+use core::{cmp, fmt::Write, ptr, time::Duration};
+use objects::{
+    domain,
+    notification::Notification,
+    tcb::{slots, Fault, Tcb, ThreadState},
+    vspace,
+};
+use platform::{irq, uart::CONSOLE};
+use syscall::KERNEL_LOCK;
+
+use self::class::Class;
+
+pub mod class;
+pub mod preemption;
+
+pub struct RunQueue {
+    head: *mut Tcb,
+    tail: *mut Tcb,
+}
+
+// Threads are only touched with the queue lock held.
+unsafe impl Send for RunQueue {}
+
+impl RunQueue {
+    pub const fn new() -> RunQueue {
+        RunQueue {
+            head: ptr::null_mut(),
+            tail: ptr::null_mut(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.head.is_null()
+    }
+
+    pub fn push_back(&mut self, tcb: &mut Tcb) {
+        debug_assert!(!tcb.queued);
+        tcb.queued = true;
+        tcb.sched_next = ptr::null_mut();
+        tcb.sched_prev = self.tail;
+        match unsafe { self.tail.as_mut() } {
+            Some(tail) => tail.sched_next = tcb,
+            None => self.head = tcb,
+        }
+        self.tail = tcb;
+    }
+
+    /// First queued thread of the class that runs first at `now`, null if
+    /// none is eligible to run.
+    fn best(&self, now: u64) -> (*mut Tcb, Class) {
+        let mut best = (ptr::null_mut(), Class::Ineligible);
+        let mut tcb = self.head;
+        while let Some(candidate) = unsafe { tcb.as_ref() } {
+            let class = Class::of(candidate, now);
+            if class.precedes(best.1) {
+                best = (tcb, class);
+            }
+            tcb = candidate.sched_next;
+        }
+        best
+    }
+
+    /// Class of the thread `pop_best()` would return.
+    pub fn best_class(&self, now: u64) -> Option<Class> {
+        match self.best(now) {
+            (_, Class::Ineligible) => None,
+            (_, class) => Some(class),
+        }
+    }
+
+    pub fn pop_best(&mut self, now: u64) -> Option<&'static mut Tcb> {
+        let tcb = unsafe { self.best(now).0.as_mut()? };
+        self.remove(tcb);
+        Some(tcb)
+    }
+
+    pub fn remove(&mut self, tcb: &mut Tcb) {
+        if !tcb.queued {
+            return;
+        }
+        match unsafe { tcb.sched_prev.as_mut() } {
+            Some(prev) => prev.sched_next = tcb.sched_next,
+            None => self.head = tcb.sched_next,
+        }
+        match unsafe { tcb.sched_next.as_mut() } {
+            Some(next) => next.sched_prev = tcb.sched_prev,
+            None => self.tail = tcb.sched_prev,
+        }
+        tcb.sched_next = ptr::null_mut();
+        tcb.sched_prev = ptr::null_mut();
+        tcb.queued = false;
+    }
+}
+
+pub static RUN_QUEUE: SpinLock<RunQueue> = SpinLock::new(RunQueue::new());
+
+/// How long a thread runs before others of its priority get their turn.
+pub const TIME_SLICE_MS: u64 = 10;
+
+/// Thread running on each core, 0 if idle.
+static mut CURRENT: [usize; MAX_CPUS] = [0; MAX_CPUS];
+
+/// Thread whose TPIDR_EL0 is live in each core's registers, 0 if none.
+static mut LOADED: [usize; MAX_CPUS] = [0; MAX_CPUS];
+
+/// Thread whose FP/SIMD registers are live on each core, 0 if none.
+static mut FPU_OWNER: [usize; MAX_CPUS] = [0; MAX_CPUS];
+
+/// Whether the time slice of the thread running on each core is used up.
+static mut SLICE_EXPIRED: [bool; MAX_CPUS] = [false; MAX_CPUS];
+
+/// Counter value when the thread running on each core was last charged.
+static mut CHARGED_AT: [u64; MAX_CPUS] = [0; MAX_CPUS];
+
+/// Start time slicing on the calling core.
+pub fn init_per_core() {
+    start_slice(None, timer::ticks());
+}
+
+fn tick() {
+    unsafe {
+        SLICE_EXPIRED[read_cpu_id() as usize] = true;
+    }
+}
+
+/// Arm this core's timer for the end of the time slice of `thread`, or
+/// of its reservation if that comes first.
+fn start_slice(thread: Option<&Tcb>, now: u64) {
+    let mut length = timer::duration_to_ticks(Duration::from_millis(TIME_SLICE_MS));
+    if let Some(thread) = thread {
+        if let Class::Guaranteed { .. } = Class::of(thread, now) {
+            let remaining = domain::of(thread).map_or(length, |domain| domain.remaining());
+            length = cmp::min(length, remaining);
+        }
+    }
+    let cpu = read_cpu_id() as usize;
+    unsafe {
+        SLICE_EXPIRED[cpu] = false;
+        CHARGED_AT[cpu] = now;
+    }
+    timer::set_oneshot(now + length, tick);
+}
+
+/// Charge the CPU time `thread` used since it was last charged to its domain.
+fn charge(thread: &Tcb, now: u64) {
+    let cpu = read_cpu_id() as usize;
+    let elapsed = unsafe { now - CHARGED_AT[cpu] };
+    unsafe {
+        CHARGED_AT[cpu] = now;
+    }
+    if let Some(domain) = domain::of(thread) {
+        domain.charge(elapsed);
+    }
+}
+
+/// Thread running on this core.
+pub fn current() -> Option<&'static mut Tcb> {
+    let ptr = unsafe { CURRENT[read_cpu_id() as usize] };
+    unsafe { (ptr as *mut Tcb).as_mut() }
+}
+
+pub fn set_current(tcb: Option<&Tcb>) {
+    let ptr = tcb.map_or(0, |tcb| tcb.as_ptr());
+    unsafe {
+        CURRENT[read_cpu_id() as usize] = ptr;
+    }
+}
+
+fn is_current_anywhere(tcb: &Tcb) -> bool {
+    unsafe { CURRENT.iter().any(|&ptr| ptr == tcb.as_ptr()) }
+}
+
+/// Make other cores running `thread` enter the kernel, e.g. so it
+/// continues with a changed protection domain.
+pub fn kick(thread: &Tcb) {
+    let this = read_cpu_id() as usize;
+    for cpu in 0..MAX_CPUS {
+        if cpu != this && unsafe { CURRENT[cpu] } == thread.as_ptr() {
+            irq::kick(cpu);
+        }
+    }
+}
+
+/// Queue a thread which became runnable, unless it is already queued or running.
+pub fn make_runnable(tcb: &mut Tcb) {
+    if !tcb.is_runnable() || is_current_anywhere(tcb) {
+        return;
+    }
+    let mut queue = RUN_QUEUE.lock_irqsave();
+    if !tcb.queued {
+        queue.push_back(tcb);
+    }
+}
+
+/// Take a thread out of scheduling, e.g. before destroying it.
+pub fn remove(tcb: &mut Tcb) {
+    RUN_QUEUE.lock_irqsave().remove(tcb);
+    for cpu in 0..MAX_CPUS {
+        unsafe {
+            if CURRENT[cpu] == tcb.as_ptr() {
+                CURRENT[cpu] = 0;
+            }
+            if LOADED[cpu] == tcb.as_ptr() {
+                LOADED[cpu] = 0;
+            }
+            if FPU_OWNER[cpu] == tcb.as_ptr() {
+                FPU_OWNER[cpu] = 0;
+            }
+        }
+    }
+}
+
+/// Pick the thread to run next on this core and make it current.
+///
+/// A still runnable current thread goes to the back of the queue. Its
+/// extended context is saved first, as another core may pick it up as
+/// soon as it is queued.
+pub fn choose_next() -> Option<&'static mut Tcb> {
+    let now = timer::ticks();
+    let mut queue = RUN_QUEUE.lock_irqsave();
+    if let Some(current) = current() {
+        charge(current, now);
+        save_extended(current);
+        if current.is_runnable() {
+            queue.push_back(current);
+        }
+    }
+    let next = queue.pop_best(now);
+    set_current(next.as_ref().map(|tcb| &**tcb));
+    start_slice(next.as_ref().map(|tcb| &**tcb), now);
+    next
+}
+
+/// Whether the current `thread` has to make way for another one.
+///
+/// Once its time slice is used up, that includes threads of the same
+/// class. If it keeps running it starts a new time slice.
+pub fn should_switch(thread: &Tcb) -> bool {
+    let now = timer::ticks();
+    charge(thread, now);
+    let class = Class::of(thread, now);
+    if !class.is_eligible() {
+        return true;
+    }
+    let expired = unsafe { SLICE_EXPIRED[read_cpu_id() as usize] };
+    let switch = match RUN_QUEUE.lock_irqsave().best_class(now) {
+        Some(best) => best.precedes(class) || (expired && !class.precedes(best)),
+        None => false,
+    };
+    if expired && !switch {
+        start_slice(Some(thread), now);
+    }
+    switch
+}
+
+/// Move the extended context of `thread` from this core's registers into
+/// its TCB, e.g. when it leaves the core or before changing it there.
+pub fn save_extended(thread: &mut Tcb) {
+    let cpu = read_cpu_id() as usize;
+    unsafe {
+        if LOADED[cpu] == thread.as_ptr() {
+            thread.extended.save_tls();
+            LOADED[cpu] = 0;
+        }
+        if FPU_OWNER[cpu] == thread.as_ptr() {
+            thread.extended.save_fpu();
+            FPU_OWNER[cpu] = 0;
+        }
+    }
+}
+
+/// Change the TPIDR_EL0 of `thread`, which may be live on any core.
+///
+/// Cores it is live on drop it and load the new value on their way back
+/// to EL0, other cores running the thread are kicked to get there.
+pub fn set_tls_base(thread: &mut Tcb, tls_base: u64) {
+    for cpu in 0..MAX_CPUS {
+        unsafe {
+            if LOADED[cpu] == thread.as_ptr() {
+                LOADED[cpu] = 0;
+            }
+        }
+    }
+    thread.extended.tpidr_el0 = tls_base;
+    kick(thread);
+}
+
+/// Make the TPIDR_EL0 of `thread` live on this core.
+fn load_tls(thread: &Tcb) {
+    let cpu = read_cpu_id() as usize;
+    unsafe {
+        if LOADED[cpu] != thread.as_ptr() {
+            thread.extended.restore_tls();
+            LOADED[cpu] = thread.as_ptr();
+        }
+    }
+}
+
+/// Give `thread` the FP/SIMD registers of this core.
+fn load_fpu(thread: &Tcb) {
+    let cpu = read_cpu_id() as usize;
+    unsafe {
+        if FPU_OWNER[cpu] != thread.as_ptr() {
+            // Owners save their registers when leaving the core.
+            debug_assert!(FPU_OWNER[cpu] == 0);
+            thread.extended.restore_fpu();
+            FPU_OWNER[cpu] = thread.as_ptr();
+        }
+    }
+    enable_fpu_access();
+}
+
+/// Load the registers and protection domain of the current thread for the
+/// return to EL0 in `frame`.
+pub fn activate(thread: &mut Tcb, frame: &mut TrapFrame) {
+    if thread.state() == ThreadState::Restart {
+        // Execute the interrupted svc again.
+        thread.context.elr_el1 -= 4;
+        thread.set_state(ThreadState::Running);
+    }
+    domain::upcall_if_preempted(thread);
+    load_tls(thread);
+    let cpu = read_cpu_id() as usize;
+    unsafe {
+        if cfg!(feature = "eager_fpu") {
+            load_fpu(thread);
+        } else if FPU_OWNER[cpu] != thread.as_ptr() {
+            // First FP/SIMD instruction traps, see `handle_fpu_trap()`.
+            disable_fpu_access();
+        }
+    }
+    *frame = thread.context;
+    write_tpidrro_el0(thread.ipc_buffer as u64);
+    vspace::load(thread);
+}
+
+/// Entry for FP/SIMD instructions trapped at EL0: load the current
+/// thread's registers and let it retry the instruction.
+pub fn handle_fpu_trap(frame: &mut TrapFrame) {
+    {
+        let _kernel = KERNEL_LOCK.lock();
+        if let Some(thread) = current() {
+            load_fpu(thread);
+            load_tls(thread);
+            vspace::load(thread);
+            return;
+        }
+    }
+    // The thread was destroyed from another core.
+    schedule(frame);
+}
+
+/// Entry for other exceptions taken from EL0, like aborts or undefined
+/// instructions: suspend the current thread and signal its fault handler.
+///
+/// Faults of threads without a fault handler are reported on the console.
+pub fn handle_fault(frame: &mut TrapFrame, fault: Fault) {
+    {
+        let _kernel = KERNEL_LOCK.lock();
+        if let Some(thread) = current() {
+            thread.context = *frame;
+            thread.fault = fault;
+            thread.set_state(ThreadState::Inactive);
+            match thread.slots[slots::FAULT_HANDLER].cap() {
+                Capability::Notification { ptr, badge, .. } => {
+                    Notification::from_ptr(ptr).signal(badge)
+                }
+                _ => {
+                    let report = FaultReport {
+                        origin: ExceptionOrigin::LowerAArch64,
+                        syndrome: Syndrome(fault.syndrome as u32),
+                        far: fault.address,
+                        frame: &thread.context,
+                    };
+                    write!(CONSOLE.lock_irqsave(), "{}", report);
+                }
+            }
+        }
+    }
+    schedule(frame);
+}
+
+/// Switch to the next runnable thread, idling until there is one.
+pub fn schedule(frame: &mut TrapFrame) {
+    loop {
+        {
+            let _kernel = KERNEL_LOCK.lock();
+            if let Some(next) = choose_next() {
+                activate(next, frame);
+                return;
+            }
+        }
+        // Interrupts may make a thread runnable.
+        enable_irqs();
+        wait_for_interrupt();
+        disable_irqs();
+    }
+}
+
+/// Called on return to EL0 after an interrupt: switch away from the
+/// interrupted thread if the interrupt woke one that runs first or its
+/// time slice or reservation is used up.
+pub fn preempt(frame: &mut TrapFrame) {
+    {
+        let _kernel = KERNEL_LOCK.lock();
+        if let Some(current) = current() {
+            if !should_switch(current) {
+                load_tls(current);
+                vspace::load(current);
+                return;
+            }
+            current.context = *frame;
+            current.preempted = current.is_runnable();
+        }
+    }
+    schedule(frame);
+}
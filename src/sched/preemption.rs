@@ -0,0 +1,75 @@
+// mod sched::preemption
+
+//! Preemption points.
+//!
+//! The kernel runs with interrupts masked. Built with the `realtime`
+//! feature, operations taking time proportional to object sizes check for
+//! pending interrupts at preemption points in between units of work and
+//! stop with `ObjectError::Preempted` when there is one. They keep their
+//! progress in the objects they work on, so `syscall::handle_syscall()`
+//! only has to restart the system call: the interrupt is taken on the way
+//! back to EL0 and the operation continues when the thread runs again.
+//!
+//! Preemption points are only taken within operations run by
+//! `preemptible()`, and not where there would be nothing to restart from,
+//! see `disable()`.
+
+use arch::{irq_pending, read_cpu_id, smp::MAX_CPUS};
+use objects::{ObjectError, Result};
+
+/// Preemption points are taken on each core while this is 0.
+static mut DISABLED: [u32; MAX_CPUS] = [1; MAX_CPUS];
+
+/// Whether each core stopped at a preemption point since last asked.
+static mut PREEMPTED: [bool; MAX_CPUS] = [false; MAX_CPUS];
+
+/// Stop if an interrupt is pending and preemption is enabled.
+pub fn point() -> Result<()> {
+    if !cfg!(feature = "realtime") {
+        return Ok(());
+    }
+    let cpu = read_cpu_id() as usize;
+    unsafe {
+        if DISABLED[cpu] != 0 || !irq_pending() {
+            return Ok(());
+        }
+        PREEMPTED[cpu] = true;
+    }
+    Err(ObjectError::Preempted)
+}
+
+/// Run `operation` with preemption points taken.
+///
+/// Stopping early must leave the system call safe to run again, e.g. when
+/// all the operation changes is deleting capabilities or clearing memory.
+pub fn preemptible<T, E>(
+    operation: impl FnOnce() -> ::core::result::Result<T, E>,
+) -> ::core::result::Result<T, E> {
+    enable();
+    let result = operation();
+    disable();
+    result
+}
+
+/// Ignore preemption points until the matching `enable()`, calls nest.
+pub fn disable() {
+    unsafe {
+        DISABLED[read_cpu_id() as usize] += 1;
+    }
+}
+
+pub fn enable() {
+    unsafe {
+        DISABLED[read_cpu_id() as usize] -= 1;
+    }
+}
+
+/// Whether a preemption point stopped an operation since the last call.
+pub fn take_preempted() -> bool {
+    let cpu = read_cpu_id() as usize;
+    unsafe {
+        let preempted = PREEMPTED[cpu];
+        PREEMPTED[cpu] = false;
+        preempted
+    }
+}
@@ -0,0 +1,470 @@
+// mod syscall::invocation
+
+//! Decoding of kernel object invocations.
+//!
+//! The label of the message selects the operation, message registers carry
+//! the arguments. CPtr arguments are looked up in the invoking thread's
+//! CSpace, slot arguments are index and depth relative to the invoked CNode.
+
+use arch::timer;
+use caps::{cspace, slot::CapSlot, CapRights, Capability};
+use core::{mem, time::Duration};
+use ipc::{self, IpcBuffer, MessageInfo};
+use objects::{
+    domain::Domain,
+    endpoint,
+    notification::{self, Notification},
+    tcb::{slots, Tcb, ThreadState},
+    vspace::{self, Access, Cacheability},
+    ObjectType,
+};
+use platform::irq::Irq;
+use sched::{self, preemption::preemptible};
+use syscall::{Result, SyscallError};
+use vesper_user::domain::{DomainControlBlock, MAX_PERIOD_MICROS};
+
+pub use vesper_user::InvocationLabel;
+
+/// Message arguments of an invocation.
+struct Args<'a> {
+    thread: &'a Tcb,
+    info: MessageInfo,
+}
+
+impl<'a> Args<'a> {
+    fn get(&self, index: usize) -> Result<u64> {
+        if index >= self.info.length() {
+            return Err(SyscallError::TruncatedMessage);
+        }
+        ipc::get_mr(self.thread, index).ok_or(SyscallError::TruncatedMessage)
+    }
+
+    fn depth(&self, index: usize) -> Result<u8> {
+        match self.get(index)? {
+            depth @ 1..=64 => Ok(depth as u8),
+            _ => Err(SyscallError::RangeError),
+        }
+    }
+
+    fn cap(&self, index: usize) -> Result<Capability> {
+        Ok(cspace::lookup_cap(
+            &self.thread.cspace_root(),
+            self.get(index)?,
+        )?)
+    }
+
+    /// Slot holding the capability, for operations that update it.
+    fn slot(&self, index: usize) -> Result<&'static mut CapSlot> {
+        let root = self.thread.cspace_root();
+        Ok(cspace::resolve(&root, self.get(index)?, cspace::CPTR_BITS)?.slot)
+    }
+}
+
+/// Perform the operation `info` asks for on `cap`, held in `slot`.
+///
+/// Returns the number of message registers in the reply.
+pub fn invoke(
+    cap: Capability,
+    slot: &mut CapSlot,
+    thread: &mut Tcb,
+    info: MessageInfo,
+) -> Result<usize> {
+    let label = InvocationLabel::from_raw(info.label()).ok_or(SyscallError::IllegalOperation)?;
+    match cap {
+        Capability::Untyped(_) => invoke_untyped(label, slot, thread, info),
+        Capability::CNode { .. } => invoke_cnode(label, &cap, thread, info),
+        Capability::Tcb { ptr } => invoke_tcb(label, ptr, thread, info),
+        Capability::Domain { ptr } => invoke_domain(label, Domain::from_ptr(ptr), thread, info),
+        Capability::VSpace { ptr, asid } => invoke_vspace(label, ptr, asid, thread, info),
+        Capability::Frame { .. } => invoke_frame(label, slot, thread, info),
+        Capability::PageTable { .. } => invoke_page_table(label, slot, thread, info),
+        Capability::IrqControl => invoke_irq_control(label, thread, info),
+        _ => Err(SyscallError::InvalidCapability),
+    }
+}
+
+fn invoke_untyped(
+    label: InvocationLabel,
+    slot: &mut CapSlot,
+    thread: &Tcb,
+    info: MessageInfo,
+) -> Result<usize> {
+    if label != InvocationLabel::UntypedRetype {
+        return Err(SyscallError::IllegalOperation);
+    }
+    let args = Args { thread, info };
+    let object_type = ObjectType::from_raw(args.get(0)?).ok_or(SyscallError::InvalidArgument)?;
+    let size_bits = args.get(1)?;
+    if size_bits > 64 {
+        return Err(SyscallError::RangeError);
+    }
+    let dest = args.cap(2)?;
+    let index = args.get(3)? as usize;
+    let count = args.get(4)? as usize;
+    preemptible(|| cspace::retype(slot, object_type, size_bits as u8, &dest, index, count))?;
+    Ok(0)
+}
+
+fn invoke_cnode(
+    label: InvocationLabel,
+    cnode: &Capability,
+    thread: &Tcb,
+    info: MessageInfo,
+) -> Result<usize> {
+    let args = Args { thread, info };
+    let slot_at = |index: usize| -> Result<&'static mut CapSlot> {
+        Ok(cspace::lookup_slot(
+            cnode,
+            args.get(index)?,
+            args.depth(index + 1)?,
+        )?)
+    };
+
+    match label {
+        InvocationLabel::CNodeCopy => {
+            let rights = CapRights::from_bits_truncate(args.get(4)? as u8);
+            cspace::copy(slot_at(2)?, slot_at(0)?, rights)?;
+        }
+        InvocationLabel::CNodeMint => {
+            let rights = CapRights::from_bits_truncate(args.get(4)? as u8);
+            cspace::mint(slot_at(2)?, slot_at(0)?, rights, args.get(5)?)?;
+        }
+        InvocationLabel::CNodeMove => cspace::move_cap(slot_at(2)?, slot_at(0)?)?,
+        InvocationLabel::CNodeMutate => cspace::mutate(slot_at(2)?, slot_at(0)?, args.get(4)?)?,
+        InvocationLabel::CNodeDelete => {
+            let slot = slot_at(0)?;
+            preemptible(|| cspace::delete(slot))?
+        }
+        InvocationLabel::CNodeRevoke => {
+            let slot = slot_at(0)?;
+            preemptible(|| cspace::revoke(slot))?
+        }
+        _ => return Err(SyscallError::IllegalOperation),
+    }
+    Ok(0)
+}
+
+/// A TCB invocation with its arguments decoded, so applying it doesn't
+/// need the invoking thread, which may be the target itself.
+enum TcbOperation {
+    SetSpace(&'static mut CapSlot, Option<&'static mut CapSlot>),
+    SetIpcBuffer(usize, Option<&'static mut CapSlot>),
+    SetPriority(u8),
+    WriteRegisters { pc: u64, sp: u64, arg: u64 },
+    SetTlsBase(u64),
+    Resume,
+    Suspend,
+    BindNotification(usize),
+    UnbindNotification,
+    SetFaultHandler(Option<&'static mut CapSlot>),
+}
+
+fn decode_tcb(label: InvocationLabel, args: &Args) -> Result<TcbOperation> {
+    Ok(match label {
+        InvocationLabel::TcbSetSpace => {
+            let cspace_slot = args.slot(0)?;
+            match cspace_slot.cap() {
+                Capability::CNode { .. } => {}
+                _ => return Err(SyscallError::InvalidCapability),
+            }
+            let vspace_slot = match args.get(1)? {
+                0 => None,
+                _ => {
+                    let slot = args.slot(1)?;
+                    match slot.cap() {
+                        Capability::VSpace { .. } => Some(slot),
+                        _ => return Err(SyscallError::InvalidCapability),
+                    }
+                }
+            };
+            TcbOperation::SetSpace(cspace_slot, vspace_slot)
+        }
+        InvocationLabel::TcbSetIpcBuffer => {
+            let address = args.get(0)? as usize;
+            let frame_slot = match address {
+                0 => None,
+                _ => {
+                    let size = mem::size_of::<IpcBuffer>();
+                    if address % size != 0 {
+                        return Err(SyscallError::AlignmentError);
+                    }
+                    let slot = args.slot(1)?;
+                    vspace::check_shared(slot.cap(), address, size)?;
+                    Some(slot)
+                }
+            };
+            TcbOperation::SetIpcBuffer(address, frame_slot)
+        }
+        InvocationLabel::TcbSetPriority => {
+            let priority = args.get(0)?;
+            if priority > u64::from(u8::max_value()) {
+                return Err(SyscallError::RangeError);
+            }
+            TcbOperation::SetPriority(priority as u8)
+        }
+        InvocationLabel::TcbWriteRegisters => TcbOperation::WriteRegisters {
+            pc: args.get(0)?,
+            sp: args.get(1)?,
+            arg: args.get(2)?,
+        },
+        InvocationLabel::TcbSetTlsBase => TcbOperation::SetTlsBase(args.get(0)?),
+        InvocationLabel::TcbResume => TcbOperation::Resume,
+        InvocationLabel::TcbSuspend => TcbOperation::Suspend,
+        InvocationLabel::TcbBindNotification => match args.cap(0)? {
+            Capability::Notification { ptr, .. } => TcbOperation::BindNotification(ptr),
+            _ => return Err(SyscallError::InvalidCapability),
+        },
+        InvocationLabel::TcbUnbindNotification => TcbOperation::UnbindNotification,
+        InvocationLabel::TcbSetFaultHandler => {
+            let handler_slot = match args.get(0)? {
+                0 => None,
+                _ => {
+                    let slot = args.slot(0)?;
+                    match slot.cap() {
+                        Capability::Notification { rights, .. }
+                            if rights.contains(CapRights::WRITE) =>
+                        {
+                            Some(slot)
+                        }
+                        _ => return Err(SyscallError::InvalidCapability),
+                    }
+                }
+            };
+            TcbOperation::SetFaultHandler(handler_slot)
+        }
+        _ => return Err(SyscallError::IllegalOperation),
+    })
+}
+
+/// Invoke the TCB at `ptr`, which may be the invoking `thread` itself.
+fn invoke_tcb(
+    label: InvocationLabel,
+    ptr: usize,
+    thread: &mut Tcb,
+    info: MessageInfo,
+) -> Result<usize> {
+    if label == InvocationLabel::TcbReadFault {
+        let fault = if ptr == thread.as_ptr() {
+            thread.fault
+        } else {
+            Tcb::from_ptr(ptr).fault
+        };
+        let reply = [fault.syndrome, fault.address, fault.pc];
+        for (index, &value) in reply.iter().enumerate() {
+            ipc::set_mr(thread, index, value);
+        }
+        return Ok(reply.len());
+    }
+
+    let operation = decode_tcb(label, &Args { thread, info })?;
+    let target = if ptr == thread.as_ptr() {
+        thread
+    } else {
+        Tcb::from_ptr(ptr)
+    };
+    match operation {
+        TcbOperation::SetSpace(cspace_slot, vspace_slot) => {
+            // Dropping the old spaces may take long, when preempted
+            // the call starts over with whatever is installed by then.
+            let dest = &mut target.slots[slots::CSPACE];
+            preemptible(|| cspace::delete(dest))?;
+            cspace::copy(cspace_slot, dest, CapRights::all())?;
+            let dest = &mut target.slots[slots::VSPACE];
+            preemptible(|| cspace::delete(dest))?;
+            if let Some(vspace_slot) = vspace_slot {
+                cspace::copy(vspace_slot, dest, CapRights::all())?;
+            }
+            // Cores running the target switch on their next kernel entry.
+            sched::kick(target);
+        }
+        TcbOperation::SetIpcBuffer(address, frame_slot) => {
+            // The thread keeps a copy of the frame capability, so the
+            // buffer goes away with the frame.
+            let dest = &mut target.slots[slots::IPC_BUFFER];
+            cspace::delete(dest)?;
+            target.ipc_buffer = 0;
+            if let Some(frame_slot) = frame_slot {
+                cspace::copy(frame_slot, dest, CapRights::all())?;
+                target.ipc_buffer = address;
+            }
+        }
+        TcbOperation::SetPriority(priority) => target.set_priority(priority),
+        TcbOperation::WriteRegisters { pc, sp, arg } => {
+            target.context.elr_el1 = pc;
+            target.context.sp_el0 = sp;
+            target.context.gpr[0] = arg;
+            // EL0t, all interrupts unmasked.
+            target.context.spsr_el1 = 0;
+        }
+        TcbOperation::SetTlsBase(tls_base) => sched::set_tls_base(target, tls_base),
+        TcbOperation::Resume => {
+            if target.state() == ThreadState::Inactive {
+                target.set_state(ThreadState::Running);
+                sched::make_runnable(target);
+            }
+        }
+        TcbOperation::Suspend => {
+            endpoint::cancel_ipc(target);
+            target.set_state(ThreadState::Inactive);
+            sched::remove(target);
+        }
+        TcbOperation::BindNotification(ptr) => Notification::from_ptr(ptr).bind(target)?,
+        TcbOperation::UnbindNotification => notification::unbind_tcb(target),
+        TcbOperation::SetFaultHandler(handler_slot) => {
+            let dest = &mut target.slots[slots::FAULT_HANDLER];
+            cspace::delete(dest)?;
+            if let Some(handler_slot) = handler_slot {
+                cspace::copy(handler_slot, dest, CapRights::all())?;
+            }
+        }
+    }
+    Ok(0)
+}
+
+fn invoke_domain(
+    label: InvocationLabel,
+    domain: &mut Domain,
+    thread: &mut Tcb,
+    info: MessageInfo,
+) -> Result<usize> {
+    if label == InvocationLabel::DomainGetContract {
+        let (period, slice, extra) = domain.contract();
+        let reply = [
+            micros(period),
+            micros(slice),
+            extra as u64,
+            micros(domain.consumed()),
+        ];
+        for (index, &value) in reply.iter().enumerate() {
+            ipc::set_mr(thread, index, value);
+        }
+        return Ok(reply.len());
+    }
+
+    let args = Args { thread, info };
+    match label {
+        InvocationLabel::DomainConfigure => {
+            let (dcb, entry, stack) = (args.get(0)? as usize, args.get(1)?, args.get(2)?);
+            let frame_slot = args.slot(3)?;
+            vspace::check_shared(frame_slot.cap(), dcb, mem::size_of::<DomainControlBlock>())?;
+            domain.configure(dcb, entry, stack)?;
+            cspace::delete(&mut domain.dcb_frame)?;
+            cspace::copy(frame_slot, &mut domain.dcb_frame, CapRights::all())?;
+        }
+        InvocationLabel::DomainBindTcb => match args.cap(0)? {
+            Capability::Tcb { ptr } => domain.bind(Tcb::from_ptr(ptr))?,
+            _ => return Err(SyscallError::InvalidCapability),
+        },
+        InvocationLabel::DomainUnbindTcb => domain.unbind(),
+        InvocationLabel::DomainSetContract => {
+            let (period, slice) = (args.get(0)?, args.get(1)?);
+            // Keeps the deadline arithmetic far from overflowing.
+            if period > MAX_PERIOD_MICROS || slice > period {
+                return Err(SyscallError::RangeError);
+            }
+            let period = timer::duration_to_ticks(Duration::from_micros(period));
+            let slice = timer::duration_to_ticks(Duration::from_micros(slice));
+            let extra = args.get(2)? != 0;
+            domain.set_contract(period, slice, extra, timer::ticks())?;
+        }
+        _ => return Err(SyscallError::IllegalOperation),
+    }
+    Ok(0)
+}
+
+fn invoke_vspace(
+    label: InvocationLabel,
+    vspace: usize,
+    asid: u8,
+    thread: &Tcb,
+    info: MessageInfo,
+) -> Result<usize> {
+    let args = Args { thread, info };
+    match label {
+        InvocationLabel::VSpaceGrant => {
+            let frame = args.slot(0)?;
+            let (offset, length) = (args.get(1)? as usize, args.get(2)? as usize);
+            let access = Access::from_bits_truncate(args.get(3)? as u8);
+            preemptible(|| vspace::grant(vspace, asid, frame, offset, length, access))?
+        }
+        InvocationLabel::VSpaceRevoke => {
+            let frame = args.slot(0)?;
+            let (offset, length) = (args.get(1)? as usize, args.get(2)? as usize);
+            preemptible(|| vspace::revoke(vspace, asid, frame, offset, length))?
+        }
+        _ => return Err(SyscallError::IllegalOperation),
+    }
+    Ok(0)
+}
+
+fn invoke_frame(
+    label: InvocationLabel,
+    slot: &mut CapSlot,
+    thread: &Tcb,
+    info: MessageInfo,
+) -> Result<usize> {
+    let args = Args { thread, info };
+    let cacheability_at = |index: usize| -> Result<Cacheability> {
+        Cacheability::from_raw(args.get(index)?).ok_or(SyscallError::InvalidArgument)
+    };
+    match label {
+        InvocationLabel::FrameMap => match args.cap(0)? {
+            Capability::VSpace { ptr, asid } => {
+                let access = Access::from_bits_truncate(args.get(1)? as u8);
+                let cacheability = cacheability_at(2)?;
+                preemptible(|| vspace::map_frame(ptr, asid, slot, access, cacheability))?
+            }
+            _ => return Err(SyscallError::InvalidCapability),
+        },
+        InvocationLabel::FrameRemap => {
+            let access = Access::from_bits_truncate(args.get(0)? as u8);
+            let cacheability = cacheability_at(1)?;
+            preemptible(|| vspace::remap_frame(slot, access, cacheability))?
+        }
+        InvocationLabel::FrameUnmap => preemptible(|| vspace::unmap(slot))?,
+        _ => return Err(SyscallError::IllegalOperation),
+    }
+    Ok(0)
+}
+
+fn invoke_page_table(
+    label: InvocationLabel,
+    slot: &mut CapSlot,
+    thread: &Tcb,
+    info: MessageInfo,
+) -> Result<usize> {
+    let args = Args { thread, info };
+    match label {
+        InvocationLabel::PageTableMap => match args.cap(0)? {
+            Capability::VSpace { ptr, asid } => {
+                vspace::map_page_table(slot, ptr, asid, args.get(1)? as usize)?
+            }
+            _ => return Err(SyscallError::InvalidCapability),
+        },
+        InvocationLabel::PageTableUnmap => vspace::unmap(slot)?,
+        _ => return Err(SyscallError::IllegalOperation),
+    }
+    Ok(0)
+}
+
+/// Counter ticks in microseconds, for replies.
+fn micros(ticks: u64) -> u64 {
+    timer::duration_to_micros(timer::ticks_to_duration(ticks))
+}
+
+fn invoke_irq_control(label: InvocationLabel, thread: &Tcb, info: MessageInfo) -> Result<usize> {
+    let args = Args { thread, info };
+    let irq = Irq::Gpu(args.get(0)? as u32);
+    match label {
+        InvocationLabel::IrqSetNotification => match args.cap(1)? {
+            Capability::Notification { ptr, badge, .. } => {
+                notification::irq_set_notification(irq, Notification::from_ptr(ptr), badge)?
+            }
+            _ => return Err(SyscallError::InvalidCapability),
+        },
+        InvocationLabel::IrqAck => notification::irq_ack(irq)?,
+        InvocationLabel::IrqClear => notification::irq_clear_notification(irq)?,
+        _ => return Err(SyscallError::IllegalOperation),
+    }
+    Ok(0)
+}
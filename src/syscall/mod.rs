@@ -0,0 +1,195 @@
+// mod syscall
+
+//! System call entry and dispatch.
+//!
+//! User code enters the kernel with `svc #0`, the register ABI is
+//! documented in `vesper_user::syscall`.
+//!
+//! Send and call on a kernel object other than an endpoint or notification
+//! invoke it: the MessageInfo label selects the operation, see
+//! `invocation`. A call then returns a message whose label is 0 on success
+//! or a `SyscallError` code.
+//!
+//! The kernel runs under a single big lock, taken on every entry that
+//! touches kernel objects. It is only preempted at preemption points of
+//! long-running operations when built with the `realtime` feature, which
+//! are then restarted, see `sched::preemption`.
+
+use arch::{sync::SpinLock, timer, traps::TrapFrame};
+use caps::{cspace, CapError, CapRights, Capability};
+use ipc::{self, MessageInfo};
+use objects::{
+    endpoint::{self, Endpoint},
+    notification::Notification,
+    tcb::{Tcb, ThreadState},
+    ObjectError,
+};
+use platform::{irq::IrqError, uart::CONSOLE};
+use sched;
+
+pub mod invocation;
+
+pub use vesper_user::{
+    syscall::{Syscall, SYSCALL_REGISTER},
+    Error as SyscallError,
+};
+
+/// Serializes all kernel object manipulation.
+pub static KERNEL_LOCK: SpinLock<()> = SpinLock::new(());
+
+impl From<CapError> for SyscallError {
+    fn from(e: CapError) -> SyscallError {
+        match e {
+            CapError::FailedLookup => SyscallError::FailedLookup,
+            CapError::InvalidCapability => SyscallError::InvalidCapability,
+            CapError::DeleteFirst => SyscallError::DeleteFirst,
+            CapError::IllegalOperation => SyscallError::IllegalOperation,
+            CapError::RangeError => SyscallError::RangeError,
+            CapError::Object(e) => SyscallError::from(e),
+        }
+    }
+}
+
+impl From<ObjectError> for SyscallError {
+    fn from(e: ObjectError) -> SyscallError {
+        match e {
+            ObjectError::InvalidSize => SyscallError::RangeError,
+            ObjectError::NotEnoughMemory => SyscallError::NotEnoughMemory,
+            ObjectError::RangeError => SyscallError::RangeError,
+            ObjectError::DeviceMemory => SyscallError::IllegalOperation,
+            // Never seen by user space, the call is restarted instead.
+            ObjectError::Preempted => SyscallError::IllegalOperation,
+        }
+    }
+}
+
+impl From<IrqError> for SyscallError {
+    fn from(e: IrqError) -> SyscallError {
+        match e {
+            IrqError::InvalidIrq => SyscallError::RangeError,
+            IrqError::AlreadyRegistered => SyscallError::DeleteFirst,
+            IrqError::NotRegistered => SyscallError::IllegalOperation,
+        }
+    }
+}
+
+pub type Result<T> = ::core::result::Result<T, SyscallError>;
+
+/// Entry from the lower EL synchronous exception vector for `svc`.
+///
+/// Saves the caller's registers into its TCB, performs the call and
+/// leaves the registers of the thread to run next in `frame`. That is the
+/// caller unless it blocked, yielded or woke a higher priority thread.
+pub fn handle_syscall(frame: &mut TrapFrame) {
+    let syscall = Syscall::from_raw(frame.gpr[SYSCALL_REGISTER]);
+    {
+        let _kernel = KERNEL_LOCK.lock();
+        // The caller may have been destroyed from another core.
+        if let Some(thread) = sched::current() {
+            thread.context = *frame;
+
+            match syscall {
+                Some(syscall) => handle(syscall, thread),
+                None => reply_error(thread, SyscallError::IllegalOperation),
+            }
+            if sched::preemption::take_preempted() {
+                // Drop the error reply, the call runs again once the
+                // pending interrupt has been taken.
+                thread.context = *frame;
+                thread.set_state(ThreadState::Restart);
+            }
+
+            if syscall != Some(Syscall::Yield) && !sched::should_switch(thread) {
+                sched::activate(thread, frame);
+                return;
+            }
+        }
+    }
+    sched::schedule(frame);
+}
+
+fn reply_error(thread: &mut Tcb, error: SyscallError) {
+    ipc::set_message(thread, 0, MessageInfo::new(error as u64, 0, 0, 0));
+}
+
+fn handle(syscall: Syscall, thread: &mut Tcb) {
+    let cptr = thread.context.gpr[ipc::BADGE_REGISTER];
+    match syscall {
+        Syscall::Call => handle_send(thread, cptr, true, true),
+        Syscall::Send => handle_send(thread, cptr, true, false),
+        Syscall::NBSend => handle_send(thread, cptr, false, false),
+        Syscall::Recv => handle_recv(thread, cptr, true),
+        Syscall::NBRecv => handle_recv(thread, cptr, false),
+        Syscall::Reply => endpoint::reply(thread),
+        Syscall::ReplyRecv => {
+            endpoint::reply(thread);
+            handle_recv(thread, cptr, true);
+        }
+        Syscall::Yield => {}
+        Syscall::DebugPutChar => {
+            let c = thread.context.gpr[0] as u8;
+            CONSOLE.lock_irqsave().send(c as char);
+        }
+        Syscall::DebugLatency => {
+            let latency = timer::worst_latency();
+            if thread.context.gpr[0] != 0 {
+                timer::reset_worst_latency();
+            }
+            thread.context.gpr[0] = timer::duration_to_micros(latency);
+        }
+    }
+}
+
+fn handle_send(thread: &mut Tcb, cptr: u64, blocking: bool, is_call: bool) {
+    let slot = match cspace::resolve(&thread.cspace_root(), cptr, cspace::CPTR_BITS) {
+        Ok(resolved) => resolved.slot,
+        Err(e) => {
+            if is_call {
+                reply_error(thread, SyscallError::from(e));
+            }
+            return;
+        }
+    };
+
+    match slot.cap() {
+        Capability::Endpoint { ptr, badge, rights } if rights.contains(CapRights::WRITE) => {
+            let can_grant = rights.contains(CapRights::GRANT);
+            Endpoint::from_ptr(ptr).send(thread, badge, can_grant, blocking, is_call);
+        }
+        Capability::Notification { ptr, badge, rights } if rights.contains(CapRights::WRITE) => {
+            Notification::from_ptr(ptr).signal(badge);
+        }
+        Capability::Endpoint { .. } | Capability::Notification { .. } | Capability::Null => {
+            if is_call {
+                reply_error(thread, SyscallError::InvalidCapability);
+            }
+        }
+        cap => {
+            let info = ipc::message_info(thread);
+            let result = invocation::invoke(cap, slot, thread, info);
+            if is_call && thread.is_runnable() {
+                match result {
+                    Ok(length) => ipc::set_message(thread, 0, MessageInfo::new(0, 0, 0, length)),
+                    Err(e) => reply_error(thread, e),
+                }
+            }
+        }
+    }
+}
+
+fn handle_recv(thread: &mut Tcb, cptr: u64, blocking: bool) {
+    let cap = match cspace::lookup_cap(&thread.cspace_root(), cptr) {
+        Ok(cap) => cap,
+        Err(e) => return reply_error(thread, SyscallError::from(e)),
+    };
+
+    match cap {
+        Capability::Endpoint { ptr, rights, .. } if rights.contains(CapRights::READ) => {
+            Endpoint::from_ptr(ptr).receive(thread, blocking);
+        }
+        Capability::Notification { ptr, rights, .. } if rights.contains(CapRights::READ) => {
+            Notification::from_ptr(ptr).wait(thread, blocking);
+        }
+        _ => reply_error(thread, SyscallError::InvalidCapability),
+    }
+}
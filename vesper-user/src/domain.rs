@@ -0,0 +1,98 @@
+// mod domain
+
+//! Scheduling domains and activations.
+//!
+//! The kernel multiplexes the CPU between domains, each run by one thread
+//! acting as its virtual processor. Which of its own threads a domain runs
+//! is up to its library OS: when the domain gets the CPU back after being
+//! preempted and activations are enabled, the kernel doesn't resume it
+//! where it stopped but upcalls its activation handler.
+//!
+//! Before the upcall the kernel saves the interrupted registers and the
+//! pending events into the domain control block (DCB), domain memory in a
+//! frame shared with the kernel, and disables activations. The handler
+//! runs on its own stack with x0 holding the `ActivationReason` and x1 the
+//! DCB address. Once it has taken the saved context it re-enables
+//! activations and resumes a thread of its choice.
+//!
+//! While activations are disabled the kernel resumes a preempted domain
+//! where it stopped, so the handler is never re-entered.
+//!
+//! A domain may hold a `Contract` guaranteeing it CPU time, scheduled
+//! earliest deadline first like Nemesis' Atropos. Domains without one, or
+//! past their slice with extra time allowed, share the remaining time.
+
+use core::ptr;
+use core::time::Duration;
+
+/// Longest period of a contract in microseconds, an hour.
+pub const MAX_PERIOD_MICROS: u64 = 3_600_000_000;
+
+/// CPU reservation of a domain.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Contract {
+    /// The reservation renews every period, at most MAX_PERIOD_MICROS.
+    pub period: Duration,
+    /// CPU time guaranteed within each period, zero for no reservation.
+    pub slice: Duration,
+    /// Whether the domain may run beyond its slice when the CPU is idle.
+    pub extra: bool,
+}
+
+/// Why a domain was activated.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ActivationReason {
+    /// Got the CPU back after preemption.
+    Preempted = 1,
+    /// Got the CPU back after preemption with events pending.
+    Event = 2,
+}
+
+impl ActivationReason {
+    pub fn from_raw(raw: u64) -> Option<ActivationReason> {
+        match raw {
+            1 => Some(ActivationReason::Preempted),
+            2 => Some(ActivationReason::Event),
+            _ => None,
+        }
+    }
+}
+
+/// User registers of an interrupted domain.
+#[derive(Clone, Copy, Debug, Default)]
+#[repr(C)]
+pub struct SavedContext {
+    pub gpr: [u64; 31],
+    pub sp: u64,
+    pub pc: u64,
+    /// PSTATE, of interest are the NZCV flags.
+    pub pstate: u64,
+}
+
+/// Domain control block, shared between a domain and the kernel.
+///
+/// The kernel only writes it when activating the domain: it fills in the
+/// reason, events and context and clears `activations_enabled`.
+#[repr(C)]
+pub struct DomainControlBlock {
+    /// Non-zero if the kernel may upcall the domain.
+    pub activations_enabled: u64,
+    /// `ActivationReason` of the last activation.
+    pub reason: u64,
+    /// Badge word of the notification bound to the domain's thread,
+    /// taken by the kernel on activation.
+    pub events: u64,
+    /// Registers at the time of the last preemption.
+    pub context: SavedContext,
+}
+
+impl DomainControlBlock {
+    pub fn activations_enabled(&self) -> bool {
+        unsafe { ptr::read_volatile(&self.activations_enabled) != 0 }
+    }
+
+    /// Allow or prevent upcalls, e.g. while switching between threads.
+    pub fn set_activations(&mut self, enabled: bool) {
+        unsafe { ptr::write_volatile(&mut self.activations_enabled, enabled as u64) }
+    }
+}
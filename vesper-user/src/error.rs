@@ -0,0 +1,46 @@
+// mod error
+
+//! Errors of kernel object invocations.
+
+/// Error codes returned in the label of a kernel reply, 0 is success.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Error {
+    InvalidArgument = 1,
+    InvalidCapability = 2,
+    IllegalOperation = 3,
+    RangeError = 4,
+    AlignmentError = 5,
+    FailedLookup = 6,
+    TruncatedMessage = 7,
+    DeleteFirst = 8,
+    RevokeFirst = 9,
+    NotEnoughMemory = 10,
+}
+
+pub type Result<T> = ::core::result::Result<T, Error>;
+
+impl Error {
+    pub fn from_raw(code: u64) -> Option<Error> {
+        match code {
+            1 => Some(Error::InvalidArgument),
+            2 => Some(Error::InvalidCapability),
+            3 => Some(Error::IllegalOperation),
+            4 => Some(Error::RangeError),
+            5 => Some(Error::AlignmentError),
+            6 => Some(Error::FailedLookup),
+            7 => Some(Error::TruncatedMessage),
+            8 => Some(Error::DeleteFirst),
+            9 => Some(Error::RevokeFirst),
+            10 => Some(Error::NotEnoughMemory),
+            _ => None,
+        }
+    }
+
+    /// Outcome of an invocation from the label of the kernel's reply.
+    pub fn check(label: u64) -> Result<()> {
+        match label {
+            0 => Ok(()),
+            code => Err(Error::from_raw(code).unwrap_or(Error::IllegalOperation)),
+        }
+    }
+}
@@ -0,0 +1,23 @@
+// mod fault
+
+//! Faults of user threads.
+//!
+//! A thread that faults, e.g. on an access its protection domain doesn't
+//! allow or an undefined instruction, is suspended. If it has a fault
+//! handler notification, set with `Tcb::set_fault_handler()`, that is
+//! signalled with the badge of its capability, so the handler can look at
+//! the fault with `Tcb::read_fault()`, fix things up and resume the thread,
+//! which then retries the faulting instruction. Faults of threads without
+//! a fault handler are reported on the kernel console.
+
+/// Last fault of a thread.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+#[repr(C)]
+pub struct Fault {
+    /// ESR_EL1 syndrome, the exception class is in bits 31:26.
+    pub syndrome: u64,
+    /// Faulting address of instruction and data aborts.
+    pub address: u64,
+    /// Address of the faulting instruction.
+    pub pc: u64,
+}
@@ -0,0 +1,118 @@
+// mod invocation
+
+//! Operations on kernel objects.
+//!
+//! An invocation is a call on a capability to a kernel object. The message
+//! label selects the operation, message registers carry the arguments.
+//! CPtr arguments are looked up in the invoking thread's CSpace, slot
+//! arguments are index and depth relative to the invoked CNode.
+
+/// Operations on kernel objects, passed as the message label.
+///
+/// The values are ABI, new labels take the next free number.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum InvocationLabel {
+    /// type, size bits, destination CNode CPtr, first slot index, count;
+    /// Frames can't come from the first 2MiB of RAM
+    UntypedRetype = 1,
+    /// dest index, dest depth, src index, src depth, rights
+    CNodeCopy = 2,
+    /// dest index, dest depth, src index, src depth, rights, badge or guard
+    CNodeMint = 3,
+    /// dest index, dest depth, src index, src depth
+    CNodeMove = 4,
+    /// dest index, dest depth, src index, src depth, badge or guard
+    CNodeMutate = 5,
+    /// index, depth
+    CNodeDelete = 6,
+    /// index, depth
+    CNodeRevoke = 7,
+    /// CNode CPtr, VSpace CPtr or 0 for none
+    TcbSetSpace = 8,
+    /// IPC buffer address or 0 for none, Frame CPtr holding the buffer
+    TcbSetIpcBuffer = 9,
+    /// priority, 0-255
+    TcbSetPriority = 18,
+    /// pc, sp, x0
+    TcbWriteRegisters = 10,
+    /// TPIDR_EL0 value
+    TcbSetTlsBase = 19,
+    TcbResume = 11,
+    TcbSuspend = 12,
+    /// notification CPtr
+    TcbBindNotification = 13,
+    TcbUnbindNotification = 14,
+    /// DCB address, activation handler entry point and stack, Frame CPtr holding the DCB
+    DomainConfigure = 20,
+    /// TCB CPtr
+    DomainBindTcb = 21,
+    DomainUnbindTcb = 22,
+    /// period in µs up to `domain::MAX_PERIOD_MICROS`, slice in µs, extra time flag
+    DomainSetContract = 23,
+    /// replies with period in µs, slice in µs, extra time flag, µs consumed
+    DomainGetContract = 24,
+    /// Frame CPtr, offset, length, `vspace::Access` bits
+    VSpaceGrant = 25,
+    /// Frame CPtr, offset, length
+    VSpaceRevoke = 26,
+    /// VSpace CPtr, `vspace::Access` bits, `vspace::Cacheability`
+    FrameMap = 29,
+    /// `vspace::Access` bits, `vspace::Cacheability`
+    FrameRemap = 30,
+    FrameUnmap = 31,
+    /// VSpace CPtr, address
+    PageTableMap = 27,
+    PageTableUnmap = 28,
+    /// interrupt line, notification CPtr
+    IrqSetNotification = 15,
+    /// interrupt line
+    IrqAck = 16,
+    /// interrupt line
+    IrqClear = 17,
+    /// notification CPtr or 0 for none
+    TcbSetFaultHandler = 32,
+    /// replies with syndrome, fault address, pc
+    TcbReadFault = 33,
+}
+
+impl InvocationLabel {
+    pub fn from_raw(label: u64) -> Option<InvocationLabel> {
+        use self::InvocationLabel::*;
+        let labels = [
+            UntypedRetype,
+            CNodeCopy,
+            CNodeMint,
+            CNodeMove,
+            CNodeMutate,
+            CNodeDelete,
+            CNodeRevoke,
+            TcbSetSpace,
+            TcbSetIpcBuffer,
+            TcbSetPriority,
+            TcbWriteRegisters,
+            TcbSetTlsBase,
+            TcbResume,
+            TcbSuspend,
+            TcbBindNotification,
+            TcbUnbindNotification,
+            DomainConfigure,
+            DomainBindTcb,
+            DomainUnbindTcb,
+            DomainSetContract,
+            DomainGetContract,
+            VSpaceGrant,
+            VSpaceRevoke,
+            FrameMap,
+            FrameRemap,
+            FrameUnmap,
+            PageTableMap,
+            PageTableUnmap,
+            IrqSetNotification,
+            IrqAck,
+            IrqClear,
+            TcbSetFaultHandler,
+            TcbReadFault,
+        ];
+        labels.iter().cloned().find(|&l| l as u64 == label)
+    }
+}
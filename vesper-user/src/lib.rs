@@ -0,0 +1,51 @@
+// crate vesper_user
+
+//! User-side interface to the Vesper kernel, similar to libsel4.
+//!
+//! Holds the definitions of the system call ABI shared with the kernel:
+//! system call numbers, message format, IPC buffer layout, error codes,
+//! object types, invocation labels, the domain control block, faults and
+//! memory access rights. On top of that it provides the
+//! system call stubs and typed wrappers for every kernel object invocation.
+
+#![no_std]
+// Same inline assembly syntax as the kernel, only used on aarch64.
+#![cfg_attr(target_arch = "aarch64", feature(asm))]
+
+#[macro_use]
+extern crate bitflags;
+
+pub mod domain;
+pub mod error;
+pub mod fault;
+pub mod invocation;
+pub mod message;
+pub mod object;
+pub mod syscall;
+pub mod vspace;
+
+pub mod objects;
+
+pub use error::{Error, Result};
+pub use invocation::InvocationLabel;
+pub use message::{IpcBuffer, MessageInfo};
+pub use object::ObjectType;
+pub use syscall::Syscall;
+
+/// Capability address, resolved in the calling thread's CSpace.
+pub type CPtr = u64;
+
+/// Bits in a CPtr.
+pub const CPTR_BITS: u8 = 64;
+
+bitflags! {
+    /// Access rights carried by a capability.
+    pub struct CapRights: u8 {
+        /// Receive on endpoints, wait on notifications, read mappings.
+        const READ = 1 << 0;
+        /// Send on endpoints, signal notifications, write mappings.
+        const WRITE = 1 << 1;
+        /// Transfer capabilities along with messages.
+        const GRANT = 1 << 2;
+    }
+}
@@ -0,0 +1,157 @@
+// mod message
+
+//! Message format.
+//!
+//! A message is a MessageInfo word describing it, up to MSG_MAX_LENGTH
+//! message registers and up to MSG_MAX_EXTRA_CAPS capabilities.
+//! The first MSG_REGISTERS message registers travel in CPU registers
+//! x2-x5, the rest in the thread's IPC buffer. Register x0 carries the
+//! badge of the capability the message was sent with and x1 the
+//! MessageInfo.
+
+use core::cmp;
+use CPtr;
+
+/// Message registers in total.
+pub const MSG_MAX_LENGTH: usize = 120;
+/// Capabilities a single message can carry.
+pub const MSG_MAX_EXTRA_CAPS: usize = 3;
+/// Message registers passed in CPU registers.
+pub const MSG_REGISTERS: usize = 4;
+
+/// CPU register holding the badge, or the invoked CPtr on entry.
+pub const BADGE_REGISTER: usize = 0;
+/// CPU register holding the MessageInfo.
+pub const INFO_REGISTER: usize = 1;
+/// CPU register holding message register 0.
+pub const FIRST_MSG_REGISTER: usize = 2;
+
+/// Message descriptor.
+///
+/// Bits 63:12 label, 11:9 caps unwrapped, 8:7 extra caps, 6:0 length.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct MessageInfo(u64);
+
+impl MessageInfo {
+    pub fn new(label: u64, caps_unwrapped: u8, extra_caps: usize, length: usize) -> MessageInfo {
+        MessageInfo(
+            (label << 12)
+                | (u64::from(caps_unwrapped) & 0x7) << 9
+                | (extra_caps as u64 & 0x3) << 7
+                | (length as u64 & 0x7f),
+        )
+    }
+
+    pub fn from_raw(raw: u64) -> MessageInfo {
+        MessageInfo(raw)
+    }
+
+    pub fn raw(&self) -> u64 {
+        self.0
+    }
+
+    /// Meaning of the message, defined by the protocol in use.
+    pub fn label(&self) -> u64 {
+        self.0 >> 12
+    }
+
+    /// Bit N set if capability N was unwrapped into its badge.
+    pub fn caps_unwrapped(&self) -> u8 {
+        ((self.0 >> 9) & 0x7) as u8
+    }
+
+    /// Number of capabilities carried, at most MSG_MAX_EXTRA_CAPS.
+    pub fn extra_caps(&self) -> usize {
+        cmp::min(((self.0 >> 7) & 0x3) as usize, MSG_MAX_EXTRA_CAPS)
+    }
+
+    /// Number of message registers used, at most MSG_MAX_LENGTH.
+    pub fn length(&self) -> usize {
+        cmp::min((self.0 & 0x7f) as usize, MSG_MAX_LENGTH)
+    }
+}
+
+/// Per-thread memory shared with the kernel for passing long messages.
+///
+/// Message registers below MSG_REGISTERS have space reserved, but
+/// travel in CPU registers.
+#[repr(C)]
+pub struct IpcBuffer {
+    pub tag: u64,
+    pub msg: [u64; MSG_MAX_LENGTH],
+    pub user_data: u64,
+    /// CPtrs of capabilities to send, badges of received unwrapped ones.
+    pub caps_or_badges: [u64; MSG_MAX_EXTRA_CAPS],
+    /// Where a received capability goes: CNode CPtr, slot index and depth.
+    pub receive_cnode: CPtr,
+    pub receive_index: u64,
+    pub receive_depth: u64,
+}
+
+/// IPC buffer of the calling thread.
+///
+/// The kernel keeps its address in TPIDRRO_EL0, null if the thread has none.
+#[cfg(target_arch = "aarch64")]
+pub fn ipc_buffer() -> Option<&'static mut IpcBuffer> {
+    let address: u64;
+    unsafe {
+        asm!("mrs $0, tpidrro_el0" : "=r"(address) ::: "volatile");
+        (address as *mut IpcBuffer).as_mut()
+    }
+}
+
+#[cfg(not(target_arch = "aarch64"))]
+pub fn ipc_buffer() -> Option<&'static mut IpcBuffer> {
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fields_round_trip() {
+        let info = MessageInfo::new(0xabc_def, 0b101, 2, 17);
+        assert_eq!(info.label(), 0xabc_def);
+        assert_eq!(info.caps_unwrapped(), 0b101);
+        assert_eq!(info.extra_caps(), 2);
+        assert_eq!(info.length(), 17);
+        assert_eq!(MessageInfo::from_raw(info.raw()), info);
+    }
+
+    #[test]
+    fn label_drops_top_bits() {
+        let info = MessageInfo::new(!0u64, 0, 0, 0);
+        assert_eq!(info.label(), !0u64 >> 12);
+        assert_eq!(info.caps_unwrapped(), 0);
+        assert_eq!(info.extra_caps(), 0);
+        assert_eq!(info.length(), 0);
+    }
+
+    #[test]
+    fn caps_unwrapped_truncated() {
+        let info = MessageInfo::new(1, 0xff, 0, 0);
+        assert_eq!(info.caps_unwrapped(), 0x7);
+        assert_eq!(info.label(), 1);
+        assert_eq!(info.extra_caps(), 0);
+    }
+
+    #[test]
+    fn extra_caps_truncated() {
+        let info = MessageInfo::new(1, 0, MSG_MAX_EXTRA_CAPS + 1, 0);
+        assert_eq!(info.extra_caps(), 0);
+        assert_eq!(info.caps_unwrapped(), 0);
+        assert_eq!(info.label(), 1);
+    }
+
+    #[test]
+    fn length_truncated_and_capped() {
+        let info = MessageInfo::new(1, 0, 0, 0x80 + 3);
+        assert_eq!(info.length(), 3);
+        assert_eq!(info.extra_caps(), 0);
+
+        let info = MessageInfo::new(1, 0, 0, 0x7f);
+        assert_eq!(info.length(), MSG_MAX_LENGTH);
+        assert_eq!(info.label(), 1);
+    }
+}
@@ -0,0 +1,102 @@
+// mod object
+
+//! Kernel object types and their sizes.
+
+/// Size of the smallest Untyped object.
+pub const MIN_UNTYPED_BITS: u8 = 4;
+/// Size of the largest Untyped object, RAM is below 1GiB on RPi3.
+pub const MAX_UNTYPED_BITS: u8 = 30;
+
+pub const TCB_SIZE_BITS: u8 = 11;
+pub const ENDPOINT_SIZE_BITS: u8 = 5;
+pub const NOTIFICATION_SIZE_BITS: u8 = 6;
+pub const PAGE_TABLE_SIZE_BITS: u8 = 12;
+pub const DOMAIN_SIZE_BITS: u8 = 8;
+/// Top-level translation table and the one below it for the first GiB.
+pub const VSPACE_SIZE_BITS: u8 = 13;
+/// Frames range from a page to the largest Untyped.
+pub const MIN_FRAME_BITS: u8 = 12;
+pub const MAX_FRAME_BITS: u8 = MAX_UNTYPED_BITS;
+/// Size of a capability slot in a CNode.
+pub const CNODE_SLOT_BITS: u8 = 6;
+/// Largest CNode is 2^MAX_CNODE_RADIX slots.
+pub const MAX_CNODE_RADIX: u8 = 16;
+
+/// Types of objects Untyped memory can be retyped into.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ObjectType {
+    /// Smaller Untyped region, of the given size.
+    Untyped = 0,
+    Tcb = 1,
+    Endpoint = 2,
+    Notification = 3,
+    /// Array of capability slots, sized by radix.
+    CNode = 4,
+    /// Last-level translation table.
+    PageTable = 5,
+    /// Scheduling domain, see `domain`.
+    Domain = 6,
+    /// Protection domain, see `vspace`.
+    VSpace = 7,
+    /// Memory a protection domain can be given access to, of the given size.
+    Frame = 8,
+}
+
+impl ObjectType {
+    /// Object type from its number in the system call ABI.
+    pub fn from_raw(raw: u64) -> Option<ObjectType> {
+        match raw {
+            0 => Some(ObjectType::Untyped),
+            1 => Some(ObjectType::Tcb),
+            2 => Some(ObjectType::Endpoint),
+            3 => Some(ObjectType::Notification),
+            4 => Some(ObjectType::CNode),
+            5 => Some(ObjectType::PageTable),
+            6 => Some(ObjectType::Domain),
+            7 => Some(ObjectType::VSpace),
+            8 => Some(ObjectType::Frame),
+            _ => None,
+        }
+    }
+
+    /// Object size as a power of two, None if `user_size_bits` is out of range.
+    ///
+    /// `user_size_bits` is the Untyped or Frame size or the CNode radix,
+    /// ignored for fixed-size objects.
+    pub fn size_bits(self, user_size_bits: u8) -> Option<u8> {
+        match self {
+            ObjectType::Untyped => {
+                if !(MIN_UNTYPED_BITS..=MAX_UNTYPED_BITS).contains(&user_size_bits) {
+                    return None;
+                }
+                Some(user_size_bits)
+            }
+            ObjectType::CNode => {
+                if user_size_bits == 0 || user_size_bits > MAX_CNODE_RADIX {
+                    return None;
+                }
+                Some(user_size_bits + CNODE_SLOT_BITS)
+            }
+            ObjectType::Tcb => Some(TCB_SIZE_BITS),
+            ObjectType::Endpoint => Some(ENDPOINT_SIZE_BITS),
+            ObjectType::Notification => Some(NOTIFICATION_SIZE_BITS),
+            ObjectType::PageTable => Some(PAGE_TABLE_SIZE_BITS),
+            ObjectType::Domain => Some(DOMAIN_SIZE_BITS),
+            ObjectType::VSpace => Some(VSPACE_SIZE_BITS),
+            ObjectType::Frame => {
+                if !(MIN_FRAME_BITS..=MAX_FRAME_BITS).contains(&user_size_bits) {
+                    return None;
+                }
+                Some(user_size_bits)
+            }
+        }
+    }
+
+    /// Whether objects of this type may be carved out of device memory.
+    ///
+    /// The kernel never touches device Untyped, so only types it doesn't
+    /// need to initialize are allowed.
+    pub fn allowed_in_device_memory(self) -> bool {
+        matches!(self, ObjectType::Untyped | ObjectType::Frame)
+    }
+}
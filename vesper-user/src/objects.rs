@@ -0,0 +1,482 @@
+// mod objects
+
+//! Typed handles for kernel objects.
+//!
+//! Each handle wraps the CPtr of a capability in the caller's CSpace and
+//! turns invocations into method calls.
+
+use core::time::Duration;
+use domain::{Contract, DomainControlBlock};
+use error::{Error, Result};
+use fault::Fault;
+use invocation::InvocationLabel;
+use message::{ipc_buffer, MessageInfo, MSG_MAX_LENGTH, MSG_REGISTERS};
+use object::ObjectType;
+use syscall::{self, Registers};
+use vspace::{Access, Cacheability};
+use {CPtr, CapRights};
+
+/// Invoke `cap` with `label` and `args`, checking the kernel's reply.
+fn invoke(cap: CPtr, label: InvocationLabel, args: &[u64]) -> Result<()> {
+    invoke_with_reply(cap, label, args).map(|_| ())
+}
+
+/// Like `invoke()`, returning the message registers of the reply.
+fn invoke_with_reply(cap: CPtr, label: InvocationLabel, args: &[u64]) -> Result<Registers> {
+    if args.len() > MSG_MAX_LENGTH {
+        return Err(Error::InvalidArgument);
+    }
+    let mut mrs: Registers = [0; MSG_REGISTERS];
+    for (i, &arg) in args.iter().enumerate() {
+        if i < MSG_REGISTERS {
+            mrs[i] = arg;
+        } else {
+            ipc_buffer().ok_or(Error::TruncatedMessage)?.msg[i] = arg;
+        }
+    }
+    let info = MessageInfo::new(label as u64, 0, 0, args.len());
+    Error::check(syscall::call(cap, info, &mut mrs).label())?;
+    Ok(mrs)
+}
+
+fn micros(duration: Duration) -> u64 {
+    duration.as_secs() * 1_000_000 + u64::from(duration.subsec_micros())
+}
+
+/// Untyped memory.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Untyped(pub CPtr);
+
+impl Untyped {
+    /// Carve `count` objects out of the Untyped, placing capabilities to them
+    /// in slots `index..index + count` of `dest`.
+    pub fn retype(
+        &self,
+        object_type: ObjectType,
+        size_bits: u8,
+        dest: CNode,
+        index: u64,
+        count: usize,
+    ) -> Result<()> {
+        invoke(
+            self.0,
+            InvocationLabel::UntypedRetype,
+            &[
+                object_type as u64,
+                u64::from(size_bits),
+                dest.0,
+                index,
+                count as u64,
+            ],
+        )
+    }
+}
+
+/// Array of capability slots.
+///
+/// Slots are named by index and depth relative to this CNode.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct CNode(pub CPtr);
+
+impl CNode {
+    /// Derive a copy of the capability in `src` into `dest` with `rights`.
+    ///
+    /// Untyped and reply capabilities can't be copied, only moved.
+    pub fn copy(
+        &self,
+        dest: u64,
+        dest_depth: u8,
+        src: u64,
+        src_depth: u8,
+        rights: CapRights,
+    ) -> Result<()> {
+        invoke(
+            self.0,
+            InvocationLabel::CNodeCopy,
+            &[
+                dest,
+                u64::from(dest_depth),
+                src,
+                u64::from(src_depth),
+                u64::from(rights.bits()),
+            ],
+        )
+    }
+
+    /// Like `copy()`, additionally setting the badge or guard from `data`.
+    pub fn mint(
+        &self,
+        dest: u64,
+        dest_depth: u8,
+        src: u64,
+        src_depth: u8,
+        rights: CapRights,
+        data: u64,
+    ) -> Result<()> {
+        invoke(
+            self.0,
+            InvocationLabel::CNodeMint,
+            &[
+                dest,
+                u64::from(dest_depth),
+                src,
+                u64::from(src_depth),
+                u64::from(rights.bits()),
+                data,
+            ],
+        )
+    }
+
+    /// Move the capability in `src` to `dest`.
+    pub fn move_cap(&self, dest: u64, dest_depth: u8, src: u64, src_depth: u8) -> Result<()> {
+        invoke(
+            self.0,
+            InvocationLabel::CNodeMove,
+            &[dest, u64::from(dest_depth), src, u64::from(src_depth)],
+        )
+    }
+
+    /// Like `move_cap()`, additionally setting the badge or guard from `data`.
+    pub fn mutate(
+        &self,
+        dest: u64,
+        dest_depth: u8,
+        src: u64,
+        src_depth: u8,
+        data: u64,
+    ) -> Result<()> {
+        invoke(
+            self.0,
+            InvocationLabel::CNodeMutate,
+            &[dest, u64::from(dest_depth), src, u64::from(src_depth), data],
+        )
+    }
+
+    /// Empty the slot, destroying the object if this was its last capability.
+    pub fn delete(&self, index: u64, depth: u8) -> Result<()> {
+        invoke(
+            self.0,
+            InvocationLabel::CNodeDelete,
+            &[index, u64::from(depth)],
+        )
+    }
+
+    /// Delete all capabilities derived from the one in the slot.
+    pub fn revoke(&self, index: u64, depth: u8) -> Result<()> {
+        invoke(
+            self.0,
+            InvocationLabel::CNodeRevoke,
+            &[index, u64::from(depth)],
+        )
+    }
+}
+
+/// Thread.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Tcb(pub CPtr);
+
+impl Tcb {
+    /// Make `cspace` the root of the thread's CSpace and run it in the
+    /// protection domain `vspace`, or with access to nothing but the kernel
+    /// if None.
+    pub fn set_space(&self, cspace: CNode, vspace: Option<VSpace>) -> Result<()> {
+        invoke(
+            self.0,
+            InvocationLabel::TcbSetSpace,
+            &[cspace.0, vspace.map_or(0, |vspace| vspace.0)],
+        )
+    }
+
+    /// Set the thread's IPC buffer to `address` in a frame, or remove it.
+    ///
+    /// The frame must be writable RAM, the thread loses the buffer when
+    /// the frame capability is deleted.
+    pub fn set_ipc_buffer(&self, buffer: Option<(Frame, usize)>) -> Result<()> {
+        let (frame, address) = buffer.map_or((0, 0), |(frame, address)| (frame.0, address));
+        invoke(
+            self.0,
+            InvocationLabel::TcbSetIpcBuffer,
+            &[address as u64, frame],
+        )
+    }
+
+    /// Higher priority threads run first.
+    pub fn set_priority(&self, priority: u8) -> Result<()> {
+        invoke(
+            self.0,
+            InvocationLabel::TcbSetPriority,
+            &[u64::from(priority)],
+        )
+    }
+
+    /// Set where the thread starts, its stack and first argument.
+    pub fn write_registers(&self, pc: usize, sp: usize, arg: u64) -> Result<()> {
+        invoke(
+            self.0,
+            InvocationLabel::TcbWriteRegisters,
+            &[pc as u64, sp as u64, arg],
+        )
+    }
+
+    /// Set the thread pointer register TPIDR_EL0, usually the TLS block address.
+    pub fn set_tls_base(&self, address: usize) -> Result<()> {
+        invoke(self.0, InvocationLabel::TcbSetTlsBase, &[address as u64])
+    }
+
+    pub fn resume(&self) -> Result<()> {
+        invoke(self.0, InvocationLabel::TcbResume, &[])
+    }
+
+    pub fn suspend(&self) -> Result<()> {
+        invoke(self.0, InvocationLabel::TcbSuspend, &[])
+    }
+
+    /// Let signals of `notification` wake the thread from endpoint receives.
+    pub fn bind_notification(&self, notification: Notification) -> Result<()> {
+        invoke(
+            self.0,
+            InvocationLabel::TcbBindNotification,
+            &[notification.0],
+        )
+    }
+
+    pub fn unbind_notification(&self) -> Result<()> {
+        invoke(self.0, InvocationLabel::TcbUnbindNotification, &[])
+    }
+
+    /// Signal `notification` when the thread faults, or nobody if None.
+    pub fn set_fault_handler(&self, notification: Option<Notification>) -> Result<()> {
+        invoke(
+            self.0,
+            InvocationLabel::TcbSetFaultHandler,
+            &[notification.map_or(0, |notification| notification.0)],
+        )
+    }
+
+    /// What went wrong when the thread last faulted.
+    pub fn read_fault(&self) -> Result<Fault> {
+        let mrs = invoke_with_reply(self.0, InvocationLabel::TcbReadFault, &[])?;
+        Ok(Fault {
+            syndrome: mrs[0],
+            address: mrs[1],
+            pc: mrs[2],
+        })
+    }
+}
+
+/// Scheduling domain, see `domain`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Domain(pub CPtr);
+
+impl Domain {
+    /// Set the domain control block, found in `frame`, and where
+    /// activations run.
+    ///
+    /// The frame must be writable RAM, activations stop when the frame
+    /// capability is deleted.
+    pub fn configure(
+        &self,
+        frame: Frame,
+        dcb: &mut DomainControlBlock,
+        entry: usize,
+        stack: usize,
+    ) -> Result<()> {
+        invoke(
+            self.0,
+            InvocationLabel::DomainConfigure,
+            &[
+                dcb as *mut DomainControlBlock as u64,
+                entry as u64,
+                stack as u64,
+                frame.0,
+            ],
+        )
+    }
+
+    /// Make `tcb` the thread running the domain.
+    pub fn bind_tcb(&self, tcb: Tcb) -> Result<()> {
+        invoke(self.0, InvocationLabel::DomainBindTcb, &[tcb.0])
+    }
+
+    pub fn unbind_tcb(&self) -> Result<()> {
+        invoke(self.0, InvocationLabel::DomainUnbindTcb, &[])
+    }
+
+    /// Replace the domain's CPU reservation, starting a new period now.
+    pub fn set_contract(&self, contract: Contract) -> Result<()> {
+        invoke(
+            self.0,
+            InvocationLabel::DomainSetContract,
+            &[
+                micros(contract.period),
+                micros(contract.slice),
+                contract.extra as u64,
+            ],
+        )
+    }
+
+    /// The domain's CPU reservation and the CPU time it used so far.
+    pub fn contract(&self) -> Result<(Contract, Duration)> {
+        let mrs = invoke_with_reply(self.0, InvocationLabel::DomainGetContract, &[])?;
+        let contract = Contract {
+            period: Duration::from_micros(mrs[0]),
+            slice: Duration::from_micros(mrs[1]),
+            extra: mrs[2] != 0,
+        };
+        Ok((contract, Duration::from_micros(mrs[3])))
+    }
+}
+
+/// Protection domain, see `vspace`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct VSpace(pub CPtr);
+
+impl VSpace {
+    /// Give the domain `access` to `length` bytes of `frame` from `offset`,
+    /// replacing the access previously granted there.
+    pub fn grant(&self, frame: Frame, offset: usize, length: usize, access: Access) -> Result<()> {
+        invoke(
+            self.0,
+            InvocationLabel::VSpaceGrant,
+            &[
+                frame.0,
+                offset as u64,
+                length as u64,
+                u64::from(access.bits()),
+            ],
+        )
+    }
+
+    /// Take away access to `length` bytes of `frame` from `offset`.
+    pub fn revoke(&self, frame: Frame, offset: usize, length: usize) -> Result<()> {
+        invoke(
+            self.0,
+            InvocationLabel::VSpaceRevoke,
+            &[frame.0, offset as u64, length as u64],
+        )
+    }
+}
+
+/// Physical memory, found at the same address in every protection domain.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Frame(pub CPtr);
+
+impl Frame {
+    /// Give `vspace` `access` to the whole frame as `cacheability` memory.
+    pub fn map(&self, vspace: VSpace, access: Access, cacheability: Cacheability) -> Result<()> {
+        invoke(
+            self.0,
+            InvocationLabel::FrameMap,
+            &[vspace.0, u64::from(access.bits()), cacheability as u64],
+        )
+    }
+
+    /// Change access and cacheability wherever the frame granted access.
+    pub fn remap(&self, access: Access, cacheability: Cacheability) -> Result<()> {
+        invoke(
+            self.0,
+            InvocationLabel::FrameRemap,
+            &[u64::from(access.bits()), cacheability as u64],
+        )
+    }
+
+    /// Take away all access the frame granted.
+    pub fn unmap(&self) -> Result<()> {
+        invoke(self.0, InvocationLabel::FrameUnmap, &[])
+    }
+}
+
+/// Translation table allowing access control by the page within 2MiB.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct PageTable(pub CPtr);
+
+impl PageTable {
+    /// Use the table for the 2MiB block at `address` in `vspace`.
+    ///
+    /// The block must not have been granted access to as a whole.
+    pub fn map(&self, vspace: VSpace, address: usize) -> Result<()> {
+        invoke(
+            self.0,
+            InvocationLabel::PageTableMap,
+            &[vspace.0, address as u64],
+        )
+    }
+
+    /// Remove the table from its VSpace, revoking the access it granted.
+    pub fn unmap(&self) -> Result<()> {
+        invoke(self.0, InvocationLabel::PageTableUnmap, &[])
+    }
+}
+
+/// Synchronous IPC endpoint.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Endpoint(pub CPtr);
+
+impl Endpoint {
+    pub fn send(&self, info: MessageInfo, mrs: &Registers) {
+        syscall::send(self.0, info, mrs)
+    }
+
+    pub fn nb_send(&self, info: MessageInfo, mrs: &Registers) {
+        syscall::nb_send(self.0, info, mrs)
+    }
+
+    pub fn call(&self, info: MessageInfo, mrs: &mut Registers) -> MessageInfo {
+        syscall::call(self.0, info, mrs)
+    }
+
+    pub fn recv(&self, mrs: &mut Registers) -> (u64, MessageInfo) {
+        syscall::recv(self.0, mrs)
+    }
+
+    pub fn nb_recv(&self, mrs: &mut Registers) -> (u64, MessageInfo) {
+        syscall::nb_recv(self.0, mrs)
+    }
+
+    pub fn reply_recv(&self, info: MessageInfo, mrs: &mut Registers) -> (u64, MessageInfo) {
+        syscall::reply_recv(self.0, info, mrs)
+    }
+}
+
+/// Asynchronous notification.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Notification(pub CPtr);
+
+impl Notification {
+    pub fn signal(&self) {
+        syscall::signal(self.0)
+    }
+
+    pub fn wait(&self) -> u64 {
+        syscall::wait(self.0)
+    }
+
+    pub fn poll(&self) -> u64 {
+        syscall::poll(self.0)
+    }
+}
+
+/// Routing of interrupt lines to notifications.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct IrqControl(pub CPtr);
+
+impl IrqControl {
+    /// Deliver interrupt `line` to `notification`, signalled with its badge.
+    pub fn set_notification(&self, line: u32, notification: Notification) -> Result<()> {
+        invoke(
+            self.0,
+            InvocationLabel::IrqSetNotification,
+            &[u64::from(line), notification.0],
+        )
+    }
+
+    /// Unmask `line` after handling it.
+    pub fn ack(&self, line: u32) -> Result<()> {
+        invoke(self.0, InvocationLabel::IrqAck, &[u64::from(line)])
+    }
+
+    /// Stop delivering `line`.
+    pub fn clear(&self, line: u32) -> Result<()> {
+        invoke(self.0, InvocationLabel::IrqClear, &[u64::from(line)])
+    }
+}
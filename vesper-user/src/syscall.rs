@@ -0,0 +1,188 @@
+// mod syscall
+
+//! System call numbers and stubs.
+//!
+//! User code enters the kernel with `svc #0`. The register ABI is:
+//!
+//! | Register | On entry                          | On return                  |
+//! |----------|-----------------------------------|----------------------------|
+//! | x0       | CPtr of the invoked capability    | badge of the sender        |
+//! | x1       | MessageInfo                       | MessageInfo                |
+//! | x2-x5    | message registers 0-3             | message registers 0-3      |
+//! | x7       | system call number, see `Syscall` | preserved                  |
+//!
+//! Message registers from 4 on live in the thread's IPC buffer. All other
+//! registers are preserved.
+//!
+//! Send and call on a kernel object other than an endpoint or notification
+//! invoke it, see `invocation`. A call then returns a message whose label
+//! is 0 on success or an `Error` code.
+
+use core::time::Duration;
+#[cfg(not(target_arch = "aarch64"))]
+use error::Error;
+use message::{MessageInfo, MSG_REGISTERS};
+use CPtr;
+
+/// Register holding the system call number.
+pub const SYSCALL_REGISTER: usize = 7;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Syscall {
+    /// Send and wait for the reply, or invoke a kernel object.
+    Call = 1,
+    /// Reply to the last caller, then receive.
+    ReplyRecv = 2,
+    /// Send, blocking until a receiver is ready.
+    Send = 3,
+    /// Send if a receiver is waiting, otherwise drop the message.
+    NBSend = 4,
+    /// Receive from an endpoint or wait on a notification.
+    Recv = 5,
+    /// Receive or poll without blocking.
+    NBRecv = 6,
+    /// Reply to the last caller.
+    Reply = 7,
+    /// Give up the rest of the time slice.
+    Yield = 8,
+    /// Print a character on the kernel console.
+    DebugPutChar = 9,
+    /// Read the worst-case interrupt latency in microseconds, resetting it
+    /// if x0 is non-zero.
+    DebugLatency = 10,
+}
+
+impl Syscall {
+    pub fn from_raw(number: u64) -> Option<Syscall> {
+        match number {
+            1 => Some(Syscall::Call),
+            2 => Some(Syscall::ReplyRecv),
+            3 => Some(Syscall::Send),
+            4 => Some(Syscall::NBSend),
+            5 => Some(Syscall::Recv),
+            6 => Some(Syscall::NBRecv),
+            7 => Some(Syscall::Reply),
+            8 => Some(Syscall::Yield),
+            9 => Some(Syscall::DebugPutChar),
+            10 => Some(Syscall::DebugLatency),
+            _ => None,
+        }
+    }
+}
+
+/// Message registers passed in CPU registers.
+pub type Registers = [u64; MSG_REGISTERS];
+
+/// Perform system call `number`, returning x0 and the MessageInfo.
+///
+/// Message registers in `mrs` are sent and replaced by the received ones.
+#[cfg(target_arch = "aarch64")]
+#[inline(always)]
+fn syscall(number: Syscall, x0: u64, info: MessageInfo, mrs: &mut Registers) -> (u64, MessageInfo) {
+    let mut x0 = x0;
+    let mut x1 = info.raw();
+    let (mut x2, mut x3, mut x4, mut x5) = (mrs[0], mrs[1], mrs[2], mrs[3]);
+    unsafe {
+        asm!("svc #0"
+            : "+{x0}"(x0), "+{x1}"(x1), "+{x2}"(x2), "+{x3}"(x3), "+{x4}"(x4), "+{x5}"(x5)
+            : "{x7}"(number as u64)
+            : "memory"
+            : "volatile");
+    }
+    *mrs = [x2, x3, x4, x5];
+    (x0, MessageInfo::from_raw(x1))
+}
+
+/// There is no kernel to call elsewhere, every call fails.
+#[cfg(not(target_arch = "aarch64"))]
+fn syscall(
+    _number: Syscall,
+    _x0: u64,
+    _info: MessageInfo,
+    _mrs: &mut Registers,
+) -> (u64, MessageInfo) {
+    let reply = MessageInfo::new(Error::IllegalOperation as u64, 0, 0, 0);
+    (0, reply)
+}
+
+/// Send a message, blocking until it is received.
+pub fn send(dest: CPtr, info: MessageInfo, mrs: &Registers) {
+    syscall(Syscall::Send, dest, info, &mut mrs.clone());
+}
+
+/// Send a message if a receiver is waiting, otherwise drop it.
+pub fn nb_send(dest: CPtr, info: MessageInfo, mrs: &Registers) {
+    syscall(Syscall::NBSend, dest, info, &mut mrs.clone());
+}
+
+/// Send a message and wait for the reply, which replaces `mrs`.
+pub fn call(dest: CPtr, info: MessageInfo, mrs: &mut Registers) -> MessageInfo {
+    syscall(Syscall::Call, dest, info, mrs).1
+}
+
+/// Wait for a message, returning the sender's badge.
+pub fn recv(src: CPtr, mrs: &mut Registers) -> (u64, MessageInfo) {
+    syscall(Syscall::Recv, src, MessageInfo::new(0, 0, 0, 0), mrs)
+}
+
+/// Receive a message if one is waiting, badge 0 and an empty message otherwise.
+pub fn nb_recv(src: CPtr, mrs: &mut Registers) -> (u64, MessageInfo) {
+    syscall(Syscall::NBRecv, src, MessageInfo::new(0, 0, 0, 0), mrs)
+}
+
+/// Reply to the last caller.
+pub fn reply(info: MessageInfo, mrs: &Registers) {
+    syscall(Syscall::Reply, 0, info, &mut mrs.clone());
+}
+
+/// Reply to the last caller and wait for the next message on `src`.
+pub fn reply_recv(src: CPtr, info: MessageInfo, mrs: &mut Registers) -> (u64, MessageInfo) {
+    syscall(Syscall::ReplyRecv, src, info, mrs)
+}
+
+/// Signal a notification.
+pub fn signal(dest: CPtr) {
+    send(dest, MessageInfo::new(0, 0, 0, 0), &[0; MSG_REGISTERS]);
+}
+
+/// Wait for a notification, returning its badge word.
+pub fn wait(src: CPtr) -> u64 {
+    recv(src, &mut [0; MSG_REGISTERS]).0
+}
+
+/// Take the badge word of a notification without blocking, 0 if not signalled.
+pub fn poll(src: CPtr) -> u64 {
+    nb_recv(src, &mut [0; MSG_REGISTERS]).0
+}
+
+/// Let other threads run.
+pub fn yield_now() {
+    syscall(
+        Syscall::Yield,
+        0,
+        MessageInfo::new(0, 0, 0, 0),
+        &mut [0; MSG_REGISTERS],
+    );
+}
+
+/// Print a character on the kernel console.
+pub fn debug_put_char(c: u8) {
+    syscall(
+        Syscall::DebugPutChar,
+        u64::from(c),
+        MessageInfo::new(0, 0, 0, 0),
+        &mut [0; MSG_REGISTERS],
+    );
+}
+
+/// Worst-case interrupt latency the kernel observed since the last reset,
+/// optionally starting a new measurement.
+pub fn debug_latency(reset: bool) -> Duration {
+    let (micros, _) = syscall(
+        Syscall::DebugLatency,
+        reset as u64,
+        MessageInfo::new(0, 0, 0, 0),
+        &mut [0; MSG_REGISTERS],
+    );
+    Duration::from_micros(micros)
+}
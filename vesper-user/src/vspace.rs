@@ -0,0 +1,68 @@
+// mod vspace
+
+//! Protection domains in a single address space.
+//!
+//! All threads share one virtual layout: memory is found at its physical
+//! address, so a pointer means the same in every protection domain and
+//! sharing a buffer is a matter of passing its address. What differs is
+//! access: each VSpace object is a protection domain with its own
+//! translation tables, in which nothing but the kernel is accessible until
+//! a holder of a Frame capability grants access to ranges of the frame.
+//! Threads run in the protection domain set with `Tcb::set_space()`.
+//!
+//! Each protection domain has its own address space identifier, so
+//! switching between them needs no TLB flush. There are 255 of them.
+//!
+//! Access is granted in 2MiB blocks where a range covers whole blocks.
+//! Smaller ranges need a PageTable mapped for the block first, otherwise
+//! granting fails with `Error::FailedLookup`. The first 2MiB hold the
+//! kernel image and can't be granted, neither can memory above 1GiB.
+//!
+//! A Frame capability grants access in one protection domain. To share a
+//! frame with several, grant through copies of the capability, which start
+//! out unused. Deleting a capability revokes the access it granted.
+//!
+//! Memory managers map whole frames with `Frame::map()`, choosing the
+//! `Cacheability` as well, change rights or cacheability with
+//! `Frame::remap()` and take all access the frame granted away with
+//! `Frame::unmap()`. Grants of parts of a frame use normal memory, or
+//! device memory for frames outside of RAM. The kernel keeps the TLBs and
+//! caches of all cores consistent with the changes.
+
+bitflags! {
+    /// Access to memory granted to a protection domain.
+    ///
+    /// Write and execute access imply read access.
+    pub struct Access: u8 {
+        const READ = 1 << 0;
+        const WRITE = 1 << 1;
+        const EXECUTE = 1 << 2;
+    }
+}
+
+/// Memory type a frame is mapped with.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Cacheability {
+    /// Cached normal memory.
+    Normal = 0,
+    /// Uncached, unbuffered device registers, never executable.
+    Device = 1,
+    /// Uncached normal memory whose writes may be combined, for framebuffers.
+    WriteCombining = 2,
+}
+
+impl Cacheability {
+    pub fn from_raw(raw: u64) -> Option<Cacheability> {
+        match raw {
+            0 => Some(Cacheability::Normal),
+            1 => Some(Cacheability::Device),
+            2 => Some(Cacheability::WriteCombining),
+            _ => None,
+        }
+    }
+}
+
+/// Granularity of access control, the page size.
+pub const PAGE_SIZE: usize = 4096;
+/// Range covered by a single translation table entry one level up.
+pub const BLOCK_SIZE: usize = 2 << 20;